@@ -0,0 +1,126 @@
+//! Measures publish latency of `/ws` under many concurrent sockets.
+//!
+//! Opens `SOCKETS` connections for a user that participates in `CONVERSATION_ID`, sends
+//! `MESSAGES` messages through one extra connection and reports how long each
+//! `message.created` event took to reach every socket. Run against a live server:
+//!
+//! ```sh
+//! ACCESS_TOKEN=... CONVERSATION_ID=1 SOCKETS=5000 cargo run --release --example ws_load
+//! ```
+//!
+//! Raise the open file limit (`ulimit -n`) for both the server and this process beforehand.
+//! Exits with a failure when an event is not delivered to every socket, or when the p99 latency
+//! exceeds `MAX_P99_MS` if set.
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+
+fn var<T: std::str::FromStr>(name: &str, default: Option<T>) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .or(default)
+        .unwrap_or_else(|| panic!("{} must be set", name))
+}
+
+fn now_micros() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros()
+}
+
+#[tokio::main]
+async fn main() {
+    let url: String = var("WS_URL", Some("ws://localhost:8080/ws".to_string()));
+    let access_token: String = var("ACCESS_TOKEN", None);
+    let conversation_id: i64 = var("CONVERSATION_ID", None);
+    let sockets: usize = var("SOCKETS", Some(1000));
+    let messages: usize = var("MESSAGES", Some(100));
+    let max_p99 = env::var("MAX_P99_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_millis);
+    let url = format!("{}?access_token={}", url, access_token);
+
+    let (latency_tx, mut latency_rx) = mpsc::unbounded_channel::<Duration>();
+
+    for _ in 0..sockets {
+        let (stream, _) = connect_async(&url).await.expect("Failed to connect");
+        let (_, mut receiver) = stream.split();
+        let latency_tx = latency_tx.clone();
+        tokio::spawn(async move {
+            while let Some(Ok(Message::Text(text))) = receiver.next().await {
+                let event: Value = serde_json::from_str(&text).unwrap();
+                if event["type"] != "message.created" {
+                    continue;
+                }
+                let Some(sent_at) = event["message"]["text"]
+                    .as_str()
+                    .and_then(|text| text.strip_prefix("load-test "))
+                    .and_then(|sent_at| sent_at.parse::<u128>().ok())
+                else {
+                    continue;
+                };
+                let latency = Duration::from_micros((now_micros() - sent_at) as u64);
+                if latency_tx.send(latency).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(latency_tx);
+    println!("Connected {} sockets", sockets);
+
+    let (publisher, _) = connect_async(&url).await.expect("Failed to connect");
+    let (mut publisher, _) = publisher.split();
+    for _ in 0..messages {
+        let frame = json!({
+            "type": "message.send",
+            "conversation_id": conversation_id,
+            "text": format!("load-test {}", now_micros()),
+        });
+        publisher
+            .send(Message::Text(frame.to_string()))
+            .await
+            .expect("Failed to publish");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let expected = sockets * messages;
+    let mut latencies = Vec::with_capacity(expected);
+    while latencies.len() < expected {
+        match tokio::time::timeout(Duration::from_secs(10), latency_rx.recv()).await {
+            Ok(Some(latency)) => latencies.push(latency),
+            _ => break,
+        }
+    }
+
+    latencies.sort();
+    let percentile = |p: f64| {
+        latencies
+            .get(((latencies.len() as f64 * p) as usize).min(latencies.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or_default()
+    };
+    println!("Delivered {}/{} events", latencies.len(), expected);
+    println!("p50: {:?}", percentile(0.50));
+    println!("p99: {:?}", percentile(0.99));
+    println!("max: {:?}", latencies.last().copied().unwrap_or_default());
+
+    if latencies.len() < expected {
+        eprintln!("Not every event reached every socket");
+        std::process::exit(1);
+    }
+    if let Some(max_p99) = max_p99 {
+        if percentile(0.99) > max_p99 {
+            eprintln!("p99 latency is above {:?}", max_p99);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,14 @@
+-- A session is started by every sign in and identified by its refresh token family. Each
+-- refresh bumps refresh_generation, so a refresh token of an older generation being presented
+-- again means it was stolen and the whole session is revoked.
+CREATE TABLE "session"
+(
+    id                 BIGSERIAL PRIMARY KEY,
+    user_id            BIGINT REFERENCES "user" (id) NOT NULL,
+    refresh_generation INT                           NOT NULL,
+    revoked_at         TIMESTAMPTZ                   NULL,
+    created_at         TIMESTAMPTZ                   NOT NULL,
+    updated_at         TIMESTAMPTZ                   NOT NULL
+);
+
+CREATE INDEX idx_session_user_id ON "session" (user_id);
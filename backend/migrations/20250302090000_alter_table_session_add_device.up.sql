@@ -0,0 +1,5 @@
+ALTER TABLE "session"
+    ADD COLUMN device_name  VARCHAR(255) NULL,
+    ADD COLUMN user_agent   VARCHAR(512) NULL,
+    ADD COLUMN ip           VARCHAR(64)  NULL,
+    ADD COLUMN last_seen_at TIMESTAMPTZ  NOT NULL DEFAULT NOW();
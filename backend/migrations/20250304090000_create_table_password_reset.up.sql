@@ -0,0 +1,13 @@
+-- A password reset requested by mail. Only the SHA-256 of the mailed token is stored, a reset
+-- can be used once and only until expires_at.
+CREATE TABLE "password_reset"
+(
+    id         BIGSERIAL PRIMARY KEY,
+    user_id    BIGINT REFERENCES "user" (id) NOT NULL,
+    token_hash VARCHAR(64)                   NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ                   NOT NULL,
+    used_at    TIMESTAMPTZ                   NULL,
+    created_at TIMESTAMPTZ                   NOT NULL
+);
+
+CREATE INDEX idx_password_reset_user_id ON "password_reset" (user_id);
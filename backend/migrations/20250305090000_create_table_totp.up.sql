@@ -0,0 +1,23 @@
+-- TOTP second factor of a user. It only guards sign in once enabled_at is set, i.e. after the
+-- user proved their authenticator works. last_used_step keeps a code from being used twice.
+CREATE TABLE "totp"
+(
+    user_id        BIGINT PRIMARY KEY REFERENCES "user" (id),
+    secret         VARCHAR(64)  NOT NULL,
+    enabled_at     TIMESTAMPTZ  NULL,
+    last_used_step BIGINT       NOT NULL,
+    created_at     TIMESTAMPTZ  NOT NULL,
+    updated_at     TIMESTAMPTZ  NOT NULL
+);
+
+-- Single-use codes to sign in with when the authenticator is lost, stored as SHA-256
+CREATE TABLE "recovery_code"
+(
+    id         BIGSERIAL PRIMARY KEY,
+    user_id    BIGINT REFERENCES "user" (id) NOT NULL,
+    code_hash  VARCHAR(64)                   NOT NULL,
+    used_at    TIMESTAMPTZ                   NULL,
+    created_at TIMESTAMPTZ                   NOT NULL
+);
+
+CREATE INDEX idx_recovery_code_user_id ON "recovery_code" (user_id);
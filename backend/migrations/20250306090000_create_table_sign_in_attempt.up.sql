@@ -0,0 +1,15 @@
+-- Audit log of failed sign in attempts. user_id is NULL when the username or email given did
+-- not belong to any account.
+CREATE TABLE "sign_in_attempt"
+(
+    id         BIGSERIAL PRIMARY KEY,
+    user_id    BIGINT REFERENCES "user" (id) NULL,
+    identifier VARCHAR(255)                  NOT NULL,
+    ip         VARCHAR(64)                   NULL,
+    user_agent VARCHAR(512)                  NULL,
+    reason     VARCHAR(32)                   NOT NULL,
+    created_at TIMESTAMPTZ                   NOT NULL
+);
+
+CREATE INDEX idx_sign_in_attempt_user_id ON "sign_in_attempt" (user_id);
+CREATE INDEX idx_sign_in_attempt_ip ON "sign_in_attempt" (ip);
@@ -0,0 +1,14 @@
+-- Accounts at external identity providers (Google, GitHub, ...) a user signs in with. subject
+-- is the provider's id of the account, which unlike the email never changes.
+CREATE TABLE "user_identity"
+(
+    id         BIGSERIAL PRIMARY KEY,
+    user_id    BIGINT REFERENCES "user" (id) NOT NULL,
+    provider   VARCHAR(32)                   NOT NULL,
+    subject    VARCHAR(255)                  NOT NULL,
+    email      VARCHAR(255)                  NULL,
+    created_at TIMESTAMPTZ                   NOT NULL,
+    UNIQUE (provider, subject)
+);
+
+CREATE INDEX idx_user_identity_user_id ON "user_identity" (user_id);
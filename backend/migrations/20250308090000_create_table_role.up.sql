@@ -0,0 +1,45 @@
+-- Global roles and what they permit. Every user implicitly has USER, so only the roles granted
+-- on top of it are stored in user_role.
+CREATE TABLE "role"
+(
+    name        VARCHAR(32) PRIMARY KEY,
+    description VARCHAR(255) NOT NULL
+);
+
+CREATE TABLE "permission"
+(
+    name        VARCHAR(64) PRIMARY KEY,
+    description VARCHAR(255) NOT NULL
+);
+
+CREATE TABLE "role_permission"
+(
+    role       VARCHAR(32) REFERENCES "role" (name)       NOT NULL,
+    permission VARCHAR(64) REFERENCES "permission" (name) NOT NULL,
+    PRIMARY KEY (role, permission)
+);
+
+CREATE TABLE "user_role"
+(
+    user_id    BIGINT REFERENCES "user" (id)        NOT NULL,
+    role       VARCHAR(32) REFERENCES "role" (name) NOT NULL,
+    created_at TIMESTAMPTZ                          NOT NULL,
+    PRIMARY KEY (user_id, role)
+);
+
+INSERT INTO "role" (name, description)
+VALUES ('SUPERADMIN', 'Operates the service, including granting roles'),
+       ('MODERATOR', 'Acts on abusive users and content'),
+       ('USER', 'Every signed up user');
+
+INSERT INTO "permission" (name, description)
+VALUES ('ROLE_MANAGE', 'Grant and revoke global roles'),
+       ('USER_MODERATE', 'Search, suspend and restore any user'),
+       ('CONVERSATION_MODERATE', 'Act on any conversation and its messages');
+
+INSERT INTO "role_permission" (role, permission)
+VALUES ('SUPERADMIN', 'ROLE_MANAGE'),
+       ('SUPERADMIN', 'USER_MODERATE'),
+       ('SUPERADMIN', 'CONVERSATION_MODERATE'),
+       ('MODERATOR', 'USER_MODERATE'),
+       ('MODERATOR', 'CONVERSATION_MODERATE');
@@ -0,0 +1,6 @@
+DROP TABLE IF EXISTS "moderation_log";
+
+ALTER TABLE "user"
+    DROP COLUMN suspended_at,
+    DROP COLUMN suspended_until,
+    DROP COLUMN suspension_reason;
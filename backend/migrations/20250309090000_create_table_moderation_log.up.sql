@@ -0,0 +1,19 @@
+-- A suspended user cannot sign in until suspended_until, or ever while it is NULL (a ban)
+ALTER TABLE "user"
+    ADD COLUMN suspended_at      TIMESTAMPTZ  NULL,
+    ADD COLUMN suspended_until   TIMESTAMPTZ  NULL,
+    ADD COLUMN suspension_reason VARCHAR(255) NULL;
+
+-- Audit log of every action an operator took on a user through the admin API
+CREATE TABLE "moderation_log"
+(
+    id         BIGSERIAL PRIMARY KEY,
+    actor_id   BIGINT REFERENCES "user" (id) NOT NULL,
+    user_id    BIGINT REFERENCES "user" (id) NOT NULL,
+    action     VARCHAR(32)                   NOT NULL,
+    reason     VARCHAR(255)                  NULL,
+    expires_at TIMESTAMPTZ                   NULL,
+    created_at TIMESTAMPTZ                   NOT NULL
+);
+
+CREATE INDEX idx_moderation_log_user_id ON "moderation_log" (user_id);
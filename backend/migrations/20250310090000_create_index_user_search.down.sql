@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_user_name_trgm;
+DROP INDEX IF EXISTS idx_user_username_trgm;
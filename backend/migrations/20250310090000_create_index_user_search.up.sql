@@ -0,0 +1,5 @@
+-- Trigram indexes back the prefix and fuzzy matching of the user search
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX idx_user_username_trgm ON "user" USING GIN (username gin_trgm_ops);
+CREATE INDEX idx_user_name_trgm ON "user" USING GIN (name gin_trgm_ops);
@@ -0,0 +1,10 @@
+-- Who can see which parts of a user's profile, EVERYONE, CONTACTS or NOBODY. Users without a row
+-- have the defaults below.
+CREATE TABLE "user_privacy"
+(
+    user_id              BIGINT PRIMARY KEY REFERENCES "user" (id),
+    email_visibility     VARCHAR(16) NOT NULL DEFAULT 'NOBODY',
+    last_seen_visibility VARCHAR(16) NOT NULL DEFAULT 'CONTACTS',
+    photo_visibility     VARCHAR(16) NOT NULL DEFAULT 'EVERYONE',
+    updated_at           TIMESTAMPTZ NOT NULL
+);
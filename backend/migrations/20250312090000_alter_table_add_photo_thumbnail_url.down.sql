@@ -0,0 +1,5 @@
+ALTER TABLE "conversation"
+    DROP COLUMN photo_thumbnail_url;
+
+ALTER TABLE "user"
+    DROP COLUMN photo_thumbnail_url;
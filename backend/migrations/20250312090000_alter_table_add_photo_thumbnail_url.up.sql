@@ -0,0 +1,6 @@
+-- Photos are uploaded to the blob store, which keeps a square thumbnail next to each
+ALTER TABLE "user"
+    ADD COLUMN photo_thumbnail_url VARCHAR(255) NULL;
+
+ALTER TABLE "conversation"
+    ADD COLUMN photo_thumbnail_url VARCHAR(512) NULL;
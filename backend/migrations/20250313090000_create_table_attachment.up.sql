@@ -0,0 +1,23 @@
+-- Files sent in conversations. An attachment is uploaded first and linked to a message once the
+-- message referencing it is sent, until then message_id is NULL. The file itself is in the
+-- private blob store under storage_key.
+CREATE TABLE "attachment"
+(
+    id              BIGSERIAL PRIMARY KEY,
+    uploader_id     BIGINT REFERENCES "user" (id)         NOT NULL,
+    conversation_id BIGINT REFERENCES "conversation" (id) NOT NULL,
+    message_id      BIGINT REFERENCES "message" (id)      NULL,
+    kind            VARCHAR(16)                           NOT NULL,
+    file_name       VARCHAR(255)                          NOT NULL,
+    mime_type       VARCHAR(255)                          NOT NULL,
+    size            BIGINT                                NOT NULL,
+    storage_key     VARCHAR(512)                          NOT NULL UNIQUE,
+    -- Hex encoded SHA-256 of the file
+    checksum        CHAR(64)                              NOT NULL,
+    width           INTEGER                               NULL,
+    height          INTEGER                               NULL,
+    duration_ms     INTEGER                               NULL,
+    created_at      TIMESTAMPTZ                           NOT NULL
+);
+
+CREATE INDEX idx_attachment_message_id ON "attachment" (message_id);
@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS idx_message_conversation_id_id;
+
+ALTER TABLE "conversation_participant"
+    DROP COLUMN last_read_message_id;
+
+ALTER TABLE "conversation"
+    DROP COLUMN last_activity_at,
+    DROP COLUMN last_message_id;
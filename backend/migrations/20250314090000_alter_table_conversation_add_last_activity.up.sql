@@ -0,0 +1,27 @@
+-- The latest message of each conversation and when it was sent, or when the conversation was
+-- created while it has none. Kept up to date as messages are sent and deleted, so the inbox is
+-- ordered without scanning messages.
+ALTER TABLE "conversation"
+    ADD COLUMN last_message_id  BIGINT REFERENCES "message" (id) NULL,
+    ADD COLUMN last_activity_at TIMESTAMPTZ                      NULL;
+
+UPDATE "conversation" c
+SET last_message_id  = (SELECT MAX(m.id)
+                        FROM "message" m
+                        WHERE m.conversation_id = c.id
+                          AND m.deleted_at IS NULL),
+    last_activity_at = c.created_at;
+
+UPDATE "conversation" c
+SET last_activity_at = m.created_at
+FROM "message" m
+WHERE m.id = c.last_message_id;
+
+ALTER TABLE "conversation"
+    ALTER COLUMN last_activity_at SET NOT NULL;
+
+-- Messages up to this one have been read by the participant, the ones after count as unread
+ALTER TABLE "conversation_participant"
+    ADD COLUMN last_read_message_id BIGINT REFERENCES "message" (id) NULL;
+
+CREATE INDEX idx_message_conversation_id_id ON "message" (conversation_id, id);
@@ -0,0 +1,2 @@
+ALTER TABLE "conversation_participant"
+    DROP COLUMN last_delivered_message_id;
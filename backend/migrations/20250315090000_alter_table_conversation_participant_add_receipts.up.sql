@@ -0,0 +1,7 @@
+-- Messages up to this one have reached one of the participant's devices. Never behind
+-- last_read_message_id, as a message that was read was delivered too.
+ALTER TABLE "conversation_participant"
+    ADD COLUMN last_delivered_message_id BIGINT REFERENCES "message" (id) NULL;
+
+UPDATE "conversation_participant"
+SET last_delivered_message_id = last_read_message_id;
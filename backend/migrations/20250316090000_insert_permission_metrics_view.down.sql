@@ -0,0 +1,5 @@
+DELETE FROM "role_permission"
+WHERE permission = 'METRICS_VIEW';
+
+DELETE FROM "permission"
+WHERE name = 'METRICS_VIEW';
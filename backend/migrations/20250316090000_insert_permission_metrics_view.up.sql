@@ -0,0 +1,5 @@
+INSERT INTO "permission" (name, description)
+VALUES ('METRICS_VIEW', 'Read operational metrics of the service');
+
+INSERT INTO "role_permission" (role, permission)
+VALUES ('SUPERADMIN', 'METRICS_VIEW');
@@ -0,0 +1,11 @@
+-- Sign in challenges that were completed with the second factor, by the jti of their token, so
+-- each challenge token signs in only once. Rows are swept once the token has expired.
+CREATE TABLE "mfa_challenge"
+(
+    jti        VARCHAR(64) PRIMARY KEY,
+    user_id    BIGINT REFERENCES "user" (id) NOT NULL,
+    expires_at TIMESTAMPTZ                   NOT NULL,
+    used_at    TIMESTAMPTZ                   NOT NULL
+);
+
+CREATE INDEX idx_mfa_challenge_expires_at ON "mfa_challenge" (expires_at);
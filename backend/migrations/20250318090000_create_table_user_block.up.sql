@@ -0,0 +1,10 @@
+-- Users someone blocked. Neither side of a block finds the other in the user search.
+CREATE TABLE "user_block"
+(
+    user_id         BIGINT REFERENCES "user" (id) NOT NULL,
+    blocked_user_id BIGINT REFERENCES "user" (id) NOT NULL,
+    created_at      TIMESTAMPTZ                   NOT NULL,
+    PRIMARY KEY (user_id, blocked_user_id)
+);
+
+CREATE INDEX idx_user_block_blocked_user_id ON "user_block" (blocked_user_id);
@@ -0,0 +1,2 @@
+-- Uploads never sent with a message, which the sweeper removes once they are old enough
+CREATE INDEX idx_attachment_pending ON "attachment" (created_at) WHERE message_id IS NULL;
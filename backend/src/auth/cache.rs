@@ -0,0 +1,42 @@
+use std::collections::HashMap;
+use std::sync::{PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+/// Entries above which expired ones are swept on insert
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// Remembers for `ttl` whether a session was still active, so verifying an access token doesn't
+/// have to hit the database on every request. Revoking through this instance takes effect at
+/// once, other instances notice within `ttl`.
+pub struct SessionCache {
+    ttl: Duration,
+    entries: RwLock<HashMap<i64, (bool, Instant)>>,
+}
+
+impl SessionCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the session is active, `None` if it is unknown or was checked too long ago
+    pub fn get(&self, session_id: i64) -> Option<bool> {
+        let entries = self.entries.read().unwrap_or_else(PoisonError::into_inner);
+
+        entries
+            .get(&session_id)
+            .filter(|(_, checked_at)| checked_at.elapsed() < self.ttl)
+            .map(|(active, _)| *active)
+    }
+
+    pub fn insert(&self, session_id: i64, active: bool) {
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+
+        if entries.len() >= SWEEP_THRESHOLD {
+            entries.retain(|_, (_, checked_at)| checked_at.elapsed() < self.ttl);
+        }
+        entries.insert(session_id, (active, Instant::now()));
+    }
+}
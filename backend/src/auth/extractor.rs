@@ -1,14 +1,32 @@
+use crate::auth::model::{ClientInfo, Permission};
 use crate::auth::service::AuthReadService;
 use crate::common::json::IntoApiResponse;
 use crate::common::model::{ApiResponse, Error};
 use crate::common::state::AppState;
-use axum::extract::FromRequestParts;
+use axum::extract::{ConnectInfo, FromRequestParts, Query};
 use axum::http::request::Parts;
 use axum::http::StatusCode;
 use axum::{async_trait, Json};
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::net::{IpAddr, SocketAddr};
+
+/// Subprotocol a browser client offers alongside its token, e.g.
+/// `Sec-WebSocket-Protocol: access_token, <token>`
+pub const ACCESS_TOKEN_PROTOCOL: &str = "access_token";
 
 pub struct Auth {
     pub user_id: i64,
+    pub session_id: i64,
+    pub permissions: Vec<Permission>,
+}
+
+impl Auth {
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
 }
 
 #[async_trait]
@@ -32,14 +50,284 @@ impl FromRequestParts<AppState> for Auth {
             );
         }
 
-        let claim = state
+        authenticate(state, &token[7..])
+            .await
+            .map_err(|error| error.into_json())
+    }
+}
+
+/// Authentication for WebSocket upgrades. Browsers cannot set headers on upgrade requests, so
+/// besides the `Authorization` header the token is also accepted from `Sec-WebSocket-Protocol`
+/// or the `access_token` query param. Never rejects: the failure is reported with a close frame
+/// once the socket is upgraded.
+pub struct WsAuth(pub Result<Auth, Error>);
+
+#[derive(Deserialize)]
+struct WsAuthQuery {
+    access_token: Option<String>,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for WsAuth {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = match find_ws_token(parts) {
+            Some(token) => token,
+            None => {
+                return Ok(WsAuth(Err(Error::UnAuthorized(
+                    "Access token not found".to_string(),
+                ))))
+            }
+        };
+
+        Ok(WsAuth(authenticate(state, &token).await))
+    }
+}
+
+fn find_ws_token(parts: &Parts) -> Option<String> {
+    let from_header = parts
+        .headers
+        .get("Authorization")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "));
+    if let Some(token) = from_header {
+        return Some(token.to_string());
+    }
+
+    let from_protocol = parts
+        .headers
+        .get("Sec-WebSocket-Protocol")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| {
+            let mut protocols = header.split(',').map(str::trim);
+            protocols.find(|protocol| *protocol == ACCESS_TOKEN_PROTOCOL)?;
+            protocols.next()
+        });
+    if let Some(token) = from_protocol {
+        return Some(token.to_string());
+    }
+
+    Query::<WsAuthQuery>::try_from_uri(&parts.uri)
+        .ok()
+        .and_then(|Query(query)| query.access_token)
+}
+
+async fn authenticate(state: &AppState, token: &str) -> Result<Auth, Error> {
+    let claim = state.auth_read_service.verify_token(token).await?;
+
+    let user_id = claim
+        .sub
+        .parse()
+        .map_err(|_| Error::UnAuthorized("Invalid token subject".to_string()))?;
+
+    Ok(Auth {
+        user_id,
+        session_id: claim.sid,
+        permissions: claim.permissions,
+    })
+}
+
+/// Authentication for actions unverified accounts may not take when `REQUIRE_VERIFIED_EMAIL`
+/// is on. The verification status is looked up rather than taken from the access token, so
+/// verifying takes effect right away and changing the email right away too.
+pub struct VerifiedAuth(pub Auth);
+
+#[async_trait]
+impl FromRequestParts<AppState> for VerifiedAuth {
+    type Rejection = (StatusCode, Json<ApiResponse<Option<()>>>);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let auth = Auth::from_request_parts(parts, state).await?;
+        if !state.config.require_verified_email {
+            return Ok(VerifiedAuth(auth));
+        }
+
+        let email_verified = state
             .auth_read_service
-            .verify_token(&token[7..])
+            .is_email_verified(auth.user_id)
             .await
             .map_err(|error| error.into_json())?;
+        if !email_verified {
+            return Err(Error::Forbidden("Please verify your email first".to_string()).into_json());
+        }
 
-        Ok(Auth {
-            user_id: claim.sub.parse().unwrap(),
+        Ok(VerifiedAuth(auth))
+    }
+}
+
+/// What an `Authorized` handler requires of the caller, declared with a marker type, e.g.
+/// `auth: Authorized<ManageRoles>`
+pub trait AccessPolicy: Send + Sync {
+    fn allows(auth: &Auth) -> bool;
+}
+
+/// Authentication of a caller the access policy `P` allows, rejected with 403 otherwise. The
+/// permissions are looked up rather than taken from the access token, so a revoked role stops
+/// working right away.
+pub struct Authorized<P: AccessPolicy>(pub Auth, pub PhantomData<P>);
+
+#[async_trait]
+impl<P: AccessPolicy> FromRequestParts<AppState> for Authorized<P> {
+    type Rejection = (StatusCode, Json<ApiResponse<Option<()>>>);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let mut auth = Auth::from_request_parts(parts, state).await?;
+        auth.permissions = state
+            .auth_read_service
+            .find_permissions(auth.user_id)
+            .await
+            .map_err(|error| error.into_json())?;
+        if !P::allows(&auth) {
+            return Err(
+                Error::Forbidden("You are not allowed to perform this action".to_string())
+                    .into_json(),
+            );
+        }
+
+        Ok(Authorized(auth, PhantomData))
+    }
+}
+
+/// Granting and revoking global roles
+pub struct ManageRoles;
+
+impl AccessPolicy for ManageRoles {
+    fn allows(auth: &Auth) -> bool {
+        auth.has_permission(Permission::RoleManage)
+    }
+}
+
+/// Acting on other users' accounts through the admin API
+pub struct ModerateUsers;
+
+impl AccessPolicy for ModerateUsers {
+    fn allows(auth: &Auth) -> bool {
+        auth.has_permission(Permission::UserModerate)
+    }
+}
+
+/// Reading operational metrics such as the message bus stats
+pub struct ViewMetrics;
+
+impl AccessPolicy for ViewMetrics {
+    fn allows(auth: &Auth) -> bool {
+        auth.has_permission(Permission::MetricsView)
+    }
+}
+
+/// Client details of a request. Behind one of `Config::trusted_proxies` the IP is the rightmost
+/// `X-Forwarded-For` hop that is not a trusted proxy, as hops to the left of it may be made up by
+/// the client. Otherwise it is the peer address.
+#[async_trait]
+impl FromRequestParts<AppState> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get("User-Agent")
+            .and_then(|header| header.to_str().ok())
+            .map(|header| header.chars().take(512).collect());
+
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let forwarded_for = parts
+            .headers
+            .get_all("X-Forwarded-For")
+            .iter()
+            .filter_map(|header| header.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        let ip = client_ip(peer, &forwarded_for, &state.config.trusted_proxies);
+
+        Ok(ClientInfo {
+            user_agent,
+            ip: ip.map(|ip| ip.to_string()),
         })
     }
 }
+
+fn client_ip(
+    peer: Option<IpAddr>,
+    forwarded_for: &str,
+    trusted_proxies: &[IpNet],
+) -> Option<IpAddr> {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|proxy| proxy.contains(ip));
+
+    let mut ip = peer?;
+    for hop in forwarded_for.rsplit(',').map(str::trim) {
+        if !is_trusted(&ip) {
+            break;
+        }
+        match hop.parse::<IpAddr>() {
+            Ok(hop) => ip = hop,
+            // A garbled hop was not written by a trusted proxy, so the proxy's peer is the client
+            Err(_) => break,
+        }
+    }
+
+    Some(ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn proxies() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    #[test]
+    fn untrusted_peers_are_the_client() {
+        let client = client_ip(Some(ip("203.0.113.7")), "198.51.100.1", &proxies());
+
+        assert_eq!(client, Some(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn trusted_proxies_forward_the_client() {
+        let client = client_ip(Some(ip("10.0.0.2")), "198.51.100.1, 10.0.0.1", &proxies());
+
+        assert_eq!(client, Some(ip("198.51.100.1")));
+    }
+
+    #[test]
+    fn hops_left_of_the_first_untrusted_one_are_ignored() {
+        let client = client_ip(Some(ip("10.0.0.2")), "1.2.3.4, 198.51.100.1", &proxies());
+
+        assert_eq!(client, Some(ip("198.51.100.1")));
+    }
+
+    #[test]
+    fn garbled_hops_end_the_chain() {
+        let client = client_ip(Some(ip("10.0.0.2")), "198.51.100.1, unknown", &proxies());
+
+        assert_eq!(client, Some(ip("10.0.0.2")));
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_without_trusted_proxies() {
+        let client = client_ip(Some(ip("10.0.0.2")), "198.51.100.1", &[]);
+
+        assert_eq!(client, Some(ip("10.0.0.2")));
+    }
+}
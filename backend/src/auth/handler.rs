@@ -1,33 +1,249 @@
-use crate::auth::model::{SignInRequest, SignUpRequest};
-use crate::auth::service::AuthWriteService;
+use crate::auth::extractor::{Auth, Authorized, ManageRoles};
+use crate::auth::model::{
+    ChangeEmailRequest, ChangePasswordRequest, ClientInfo, ForgotPasswordRequest, MfaCodeRequest,
+    OAuthSignInRequest, RefreshTokenRequest, ResetPasswordRequest, SignInMfaRequest, SignInRequest,
+    SignUpRequest, VerifyEmailRequest,
+};
+use crate::auth::service::{
+    AuthReadService, AuthWriteService, MfaService, OAuthService, RoleService,
+};
 use crate::common::json::IntoApiResponse;
+use crate::common::model::Error;
 use crate::common::state::AppState;
-use axum::response::IntoResponse;
-use axum::routing::post;
+use axum::extract::Path;
+use axum::http::header::{COOKIE, SET_COOKIE};
+use axum::http::HeaderMap;
+use axum::response::{AppendHeaders, IntoResponse};
+use axum::routing::{delete, get, post, put};
 use axum::{Json, Router};
 use std::sync::Arc;
 
-pub struct AuthHandler<W>
+/// Keeps the PKCE verifier of a social sign in in the browser that started it
+const OAUTH_VERIFIER_COOKIE: &str = "oauth_verifier";
+
+pub struct AuthHandler<W, R, F, O, G>
 where
     W: AuthWriteService + Send + Sync + 'static,
+    R: AuthReadService + Send + Sync + 'static,
+    F: MfaService + Send + Sync + 'static,
+    O: OAuthService + Send + Sync + 'static,
+    G: RoleService + Send + Sync + 'static,
 {
     auth_write_service: Arc<W>,
+    auth_read_service: Arc<R>,
+    mfa_service: Arc<F>,
+    oauth_service: Arc<O>,
+    role_service: Arc<G>,
 }
 
-impl<W> AuthHandler<W>
+impl<W, R, F, O, G> AuthHandler<W, R, F, O, G>
 where
     W: AuthWriteService + Send + Sync + 'static,
+    R: AuthReadService + Send + Sync + 'static,
+    F: MfaService + Send + Sync + 'static,
+    O: OAuthService + Send + Sync + 'static,
+    G: RoleService + Send + Sync + 'static,
 {
-    pub fn new(auth_write_service: Arc<W>) -> Self {
-        Self { auth_write_service }
+    pub fn new(
+        auth_write_service: Arc<W>,
+        auth_read_service: Arc<R>,
+        mfa_service: Arc<F>,
+        oauth_service: Arc<O>,
+        role_service: Arc<G>,
+    ) -> Self {
+        Self {
+            auth_write_service,
+            auth_read_service,
+            mfa_service,
+            oauth_service,
+            role_service,
+        }
+    }
+
+    async fn sign_up(
+        &self,
+        client: ClientInfo,
+        Json(req): Json<SignUpRequest>,
+    ) -> impl IntoResponse {
+        self.auth_write_service
+            .sign_up(req, client)
+            .await
+            .into_json()
+    }
+
+    async fn sign_in(
+        &self,
+        client: ClientInfo,
+        Json(req): Json<SignInRequest>,
+    ) -> impl IntoResponse {
+        self.auth_write_service
+            .sign_in(req, client)
+            .await
+            .into_json()
+    }
+
+    async fn sign_in_mfa(
+        &self,
+        client: ClientInfo,
+        Json(req): Json<SignInMfaRequest>,
+    ) -> impl IntoResponse {
+        self.auth_write_service
+            .sign_in_mfa(req, client)
+            .await
+            .into_json()
+    }
+
+    async fn authorize_oauth(&self, provider: String) -> impl IntoResponse {
+        let result = self.oauth_service.authorize(&provider).await;
+        let cookie = result
+            .as_ref()
+            .map(|res| (SET_COOKIE, oauth_verifier_cookie(&res.code_verifier, 600)))
+            .ok();
+
+        (AppendHeaders(cookie), result.into_json())
+    }
+
+    async fn sign_in_oauth(
+        &self,
+        provider: String,
+        client: ClientInfo,
+        headers: HeaderMap,
+        Json(mut req): Json<OAuthSignInRequest>,
+    ) -> impl IntoResponse {
+        // A verifier is good for one attempt, whatever its outcome
+        let cookie = [(SET_COOKIE, oauth_verifier_cookie("", 0))];
+
+        let Some(code_verifier) = find_cookie(&headers, OAUTH_VERIFIER_COOKIE) else {
+            let error = Error::BadRequest("Sign in was not started in this browser".to_string());
+            return (cookie, error.into_json());
+        };
+        req.code_verifier = code_verifier;
+
+        let result = self
+            .auth_write_service
+            .sign_in_oauth(&provider, req, client)
+            .await;
+
+        (cookie, result.into_json())
+    }
+
+    async fn enroll_totp(&self, auth: Auth) -> impl IntoResponse {
+        self.mfa_service.enroll_totp(auth.user_id).await.into_json()
+    }
+
+    async fn confirm_totp(&self, auth: Auth, req: MfaCodeRequest) -> impl IntoResponse {
+        self.mfa_service
+            .confirm_totp(auth.user_id, req)
+            .await
+            .into_json()
+    }
+
+    async fn disable_totp(&self, auth: Auth, req: MfaCodeRequest) -> impl IntoResponse {
+        self.mfa_service
+            .disable_totp(auth.user_id, req)
+            .await
+            .into_json()
+    }
+
+    async fn refresh(&self, Json(req): Json<RefreshTokenRequest>) -> impl IntoResponse {
+        self.auth_write_service.refresh(req).await.into_json()
+    }
+
+    async fn sign_out(&self, Json(req): Json<RefreshTokenRequest>) -> impl IntoResponse {
+        self.auth_write_service.sign_out(req).await.into_json()
+    }
+
+    async fn verify_email(&self, Json(req): Json<VerifyEmailRequest>) -> impl IntoResponse {
+        self.auth_write_service.verify_email(req).await.into_json()
+    }
+
+    async fn resend_verification(&self, auth: Auth) -> impl IntoResponse {
+        self.auth_write_service
+            .resend_verification(auth.user_id)
+            .await
+            .into_json()
+    }
+
+    async fn forgot_password(
+        &self,
+        client: ClientInfo,
+        Json(req): Json<ForgotPasswordRequest>,
+    ) -> impl IntoResponse {
+        self.auth_write_service
+            .forgot_password(req, client)
+            .await
+            .into_json()
+    }
+
+    async fn reset_password(&self, Json(req): Json<ResetPasswordRequest>) -> impl IntoResponse {
+        self.auth_write_service
+            .reset_password(req)
+            .await
+            .into_json()
+    }
+
+    async fn change_password(
+        &self,
+        auth: Auth,
+        client: ClientInfo,
+        Json(req): Json<ChangePasswordRequest>,
+    ) -> impl IntoResponse {
+        self.auth_write_service
+            .change_password(auth.user_id, auth.session_id, req, client)
+            .await
+            .into_json()
     }
 
-    async fn sign_up(&self, Json(req): Json<SignUpRequest>) -> impl IntoResponse {
-        self.auth_write_service.sign_up(req).await.into_json()
+    async fn change_email(
+        &self,
+        auth: Auth,
+        client: ClientInfo,
+        Json(req): Json<ChangeEmailRequest>,
+    ) -> impl IntoResponse {
+        self.auth_write_service
+            .change_email(auth.user_id, req, client)
+            .await
+            .into_json()
     }
 
-    async fn sign_in(&self, Json(req): Json<SignInRequest>) -> impl IntoResponse {
-        self.auth_write_service.sign_in(req).await.into_json()
+    async fn find_sessions(&self, auth: Auth) -> impl IntoResponse {
+        self.auth_read_service
+            .find_sessions(auth.user_id, auth.session_id)
+            .await
+            .into_json()
+    }
+
+    /// Public keys other services verify access tokens with. Served as a plain JWK Set rather
+    /// than wrapped in an `ApiResponse`, so standard JWKS clients can consume it.
+    async fn jwks(&self) -> impl IntoResponse {
+        Json(self.auth_read_service.jwks().clone())
+    }
+
+    async fn revoke_session(&self, auth: Auth, session_id: i64) -> impl IntoResponse {
+        self.auth_write_service
+            .revoke_session(auth.user_id, session_id)
+            .await
+            .into_json()
+    }
+
+    async fn find_roles(&self, user_id: i64) -> impl IntoResponse {
+        self.role_service.find_roles(user_id).await.into_json()
+    }
+
+    async fn grant_role(&self, user_id: i64, role: String) -> impl IntoResponse {
+        match role.parse() {
+            Ok(role) => self.role_service.grant(user_id, role).await,
+            Err(error) => Err(error),
+        }
+        .into_json()
+    }
+
+    async fn revoke_role(&self, auth: Auth, user_id: i64, role: String) -> impl IntoResponse {
+        match role.parse() {
+            Ok(role) => self.role_service.revoke(auth.user_id, user_id, role).await,
+            Err(error) => Err(error),
+        }
+        .into_json()
     }
 
     pub fn create_route(handler: Arc<Self>, router: Router<AppState>) -> Router<AppState> {
@@ -36,15 +252,207 @@ where
                 "/api/auth/sign_up",
                 post({
                     let handler = Arc::clone(&handler);
-                    move |req: Json<SignUpRequest>| async move { handler.sign_up(req).await }
+                    move |client: ClientInfo, req: Json<SignUpRequest>| async move {
+                        handler.sign_up(client, req).await
+                    }
                 }),
             )
             .route(
                 "/api/auth/sign_in",
                 post({
                     let handler = Arc::clone(&handler);
-                    move |req: Json<SignInRequest>| async move { handler.sign_in(req).await }
+                    move |client: ClientInfo, req: Json<SignInRequest>| async move {
+                        handler.sign_in(client, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/auth/sign_in/mfa",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |client: ClientInfo, req: Json<SignInMfaRequest>| async move {
+                        handler.sign_in_mfa(client, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/auth/oauth/:provider",
+                get({
+                    let handler = Arc::clone(&handler);
+                    move |Path(provider): Path<String>| async move {
+                        handler.authorize_oauth(provider).await
+                    }
+                }),
+            )
+            .route(
+                "/api/auth/oauth/:provider/callback",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |Path(provider): Path<String>,
+                          client: ClientInfo,
+                          headers: HeaderMap,
+                          req: Json<OAuthSignInRequest>| async move {
+                        handler.sign_in_oauth(provider, client, headers, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/auth/mfa/totp",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |auth: Auth| async move { handler.enroll_totp(auth).await }
+                }),
+            )
+            .route(
+                "/api/auth/mfa/totp/confirm",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |auth: Auth, Json(req): Json<MfaCodeRequest>| async move {
+                        handler.confirm_totp(auth, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/auth/mfa/totp/disable",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |auth: Auth, Json(req): Json<MfaCodeRequest>| async move {
+                        handler.disable_totp(auth, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/auth/refresh",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |req: Json<RefreshTokenRequest>| async move { handler.refresh(req).await }
+                }),
+            )
+            .route(
+                "/api/auth/sign_out",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |req: Json<RefreshTokenRequest>| async move { handler.sign_out(req).await }
+                }),
+            )
+            .route(
+                "/api/auth/verify_email",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |req: Json<VerifyEmailRequest>| async move {
+                        handler.verify_email(req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/auth/resend_verification",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |auth: Auth| async move { handler.resend_verification(auth).await }
+                }),
+            )
+            .route(
+                "/api/auth/password/forgot",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |client: ClientInfo, req: Json<ForgotPasswordRequest>| async move {
+                        handler.forgot_password(client, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/auth/password/reset",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |req: Json<ResetPasswordRequest>| async move {
+                        handler.reset_password(req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/user/password",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |auth: Auth, client: ClientInfo, req: Json<ChangePasswordRequest>| async move {
+                        handler.change_password(auth, client, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/user/email",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |auth: Auth, client: ClientInfo, req: Json<ChangeEmailRequest>| async move {
+                        handler.change_email(auth, client, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/auth/sessions",
+                get({
+                    let handler = Arc::clone(&handler);
+                    move |auth: Auth| async move { handler.find_sessions(auth).await }
+                }),
+            )
+            .route(
+                "/api/auth/sessions/:session_id",
+                delete({
+                    let handler = Arc::clone(&handler);
+                    move |auth: Auth, Path(session_id): Path<i64>| async move {
+                        handler.revoke_session(auth, session_id).await
+                    }
+                }),
+            )
+            .route(
+                "/api/admin/users/:user_id/roles",
+                get({
+                    let handler = Arc::clone(&handler);
+                    move |_: Authorized<ManageRoles>, Path(user_id): Path<i64>| async move {
+                        handler.find_roles(user_id).await
+                    }
+                }),
+            )
+            .route(
+                "/api/admin/users/:user_id/roles/:role",
+                put({
+                    let handler = Arc::clone(&handler);
+                    move |_: Authorized<ManageRoles>, Path((user_id, role)): Path<(i64, String)>| async move {
+                        handler.grant_role(user_id, role).await
+                    }
+                })
+                .delete({
+                    let handler = Arc::clone(&handler);
+                    move |Authorized(auth, _): Authorized<ManageRoles>,
+                          Path((user_id, role)): Path<(i64, String)>| async move {
+                        handler.revoke_role(auth, user_id, role).await
+                    }
+                }),
+            )
+            .route(
+                "/.well-known/jwks.json",
+                get({
+                    let handler = Arc::clone(&handler);
+                    move || async move { handler.jwks().await }
                 }),
             )
     }
 }
+
+/// HttpOnly so scripts can't read it, SameSite so other sites can't send it along
+fn oauth_verifier_cookie(code_verifier: &str, max_age: i64) -> String {
+    format!(
+        "{}={}; Path=/api/auth/oauth; Max-Age={}; HttpOnly; Secure; SameSite=Lax",
+        OAUTH_VERIFIER_COOKIE, code_verifier, max_age
+    )
+}
+
+fn find_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, value)| *key == name && !value.is_empty())
+        .map(|(_, value)| value.to_string())
+}
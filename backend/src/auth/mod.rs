@@ -1,4 +1,10 @@
+pub mod cache;
 pub mod extractor;
 pub mod handler;
+pub mod keys;
 pub mod model;
+pub mod oauth;
+pub mod repo;
 pub mod service;
+pub mod throttle;
+pub mod totp;
@@ -1,4 +1,8 @@
+use crate::common::model::Error;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::str::FromStr;
 use validator::Validate;
 
 #[derive(Debug, Serialize)]
@@ -8,12 +12,296 @@ pub struct AuthResponse {
     pub user_id: i64,
 }
 
+/// Claims of an access token. `jti` is unique per issued token pair, `sid` names the session it
+/// was issued for so the token stops working once the session is revoked.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claim {
     pub(crate) sub: String,
     pub(crate) exp: i64,
     pub(crate) iat: i64,
     pub(crate) username: String,
+    pub(crate) jti: String,
+    pub(crate) sid: i64,
+    #[serde(default)]
+    pub(crate) email_verified: bool,
+    /// For clients to tailor their UI, access checks go by `permissions`
+    #[serde(default)]
+    pub(crate) roles: Vec<Role>,
+    #[serde(default)]
+    pub(crate) permissions: Vec<Permission>,
+}
+
+/// Global role of a user, rows of the `role` table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Role {
+    #[serde(rename = "SUPERADMIN")]
+    SuperAdmin,
+    Moderator,
+    /// Held by every user without being stored
+    User,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::SuperAdmin => "SUPERADMIN",
+            Role::Moderator => "MODERATOR",
+            Role::User => "USER",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "SUPERADMIN" => Ok(Role::SuperAdmin),
+            "MODERATOR" => Ok(Role::Moderator),
+            "USER" => Ok(Role::User),
+            _ => Err(Error::BadRequest(format!("Unknown role {}", s))),
+        }
+    }
+}
+
+/// What a role allows, rows of the `permission` table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Permission {
+    RoleManage,
+    UserModerate,
+    ConversationModerate,
+    MetricsView,
+}
+
+impl FromStr for Permission {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ROLE_MANAGE" => Ok(Permission::RoleManage),
+            "USER_MODERATE" => Ok(Permission::UserModerate),
+            "CONVERSATION_MODERATE" => Ok(Permission::ConversationModerate),
+            "METRICS_VIEW" => Ok(Permission::MetricsView),
+            _ => Err(Error::BadRequest(format!("Unknown permission {}", s))),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserRolesResponse {
+    pub user_id: i64,
+    pub roles: Vec<Role>,
+    pub permissions: Vec<Permission>,
+}
+
+/// Claims of a refresh token, `sid` names the session (token family) it belongs to and `gen`
+/// the refresh generation it was issued for
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshClaim {
+    pub(crate) sub: String,
+    pub(crate) exp: i64,
+    pub(crate) iat: i64,
+    pub(crate) sid: i64,
+    pub(crate) gen: i32,
+}
+
+/// Claims of a token mailed to the user. `email` binds it to the address it was sent to and
+/// `purpose` keeps a token of one flow from being accepted by another.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailClaim {
+    pub(crate) sub: String,
+    pub(crate) exp: i64,
+    pub(crate) iat: i64,
+    pub(crate) email: String,
+    pub(crate) purpose: String,
+}
+
+/// Claims of the challenge token handed out by a sign in that still needs the second factor
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaClaim {
+    pub(crate) sub: String,
+    pub(crate) exp: i64,
+    pub(crate) iat: i64,
+    /// Recorded once the challenge is completed, so the token signs in only once
+    pub(crate) jti: String,
+    pub(crate) purpose: String,
+    pub(crate) device_name: Option<String>,
+}
+
+/// Claims of the `state` of a social sign in. `code_challenge` binds it to the random PKCE
+/// verifier kept in a cookie of the browser that started the sign in, so no other browser can
+/// complete it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OAuthClaim {
+    pub(crate) exp: i64,
+    pub(crate) iat: i64,
+    pub(crate) purpose: String,
+    pub(crate) provider: String,
+    pub(crate) code_challenge: String,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct Session {
+    pub id: i64,
+    pub refresh_generation: i32,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub last_seen_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateSessionRequest {
+    pub user_id: i64,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// Where a sign in request came from, recorded on the session it starts
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SignInFailure {
+    UnknownUser,
+    WrongPassword,
+    WrongCode,
+    LockedOut,
+}
+
+impl SignInFailure {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignInFailure::UnknownUser => "unknown_user",
+            SignInFailure::WrongPassword => "wrong_password",
+            SignInFailure::WrongCode => "wrong_code",
+            SignInFailure::LockedOut => "locked_out",
+        }
+    }
+}
+
+/// Audit record of a failed sign in attempt
+#[derive(Debug, Clone)]
+pub struct CreateSignInAttemptRequest {
+    pub user_id: Option<i64>,
+    pub identifier: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub reason: SignInFailure,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    pub id: i64,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    /// Whether this is the session of the token the request was made with
+    pub current: bool,
+    pub last_seen_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SessionResponse {
+    pub fn from(session: Session, current_session_id: i64) -> Self {
+        Self {
+            id: session.id,
+            current: session.id == current_session_id,
+            device_name: session.device_name,
+            user_agent: session.user_agent,
+            ip: session.ip,
+            last_seen_at: session.last_seen_at,
+            created_at: session.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct Totp {
+    pub secret: String,
+    pub enabled_at: Option<DateTime<Utc>>,
+}
+
+/// Account at an identity provider, as the provider describes it
+#[derive(Debug, Clone)]
+pub struct ExternalIdentity {
+    pub provider: String,
+    /// Id of the account at the provider, unique per provider
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub username: Option<String>,
+    pub name: Option<String>,
+    pub photo_url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OAuthAuthorizationResponse {
+    pub authorization_url: String,
+    pub state: String,
+    /// Set as an HttpOnly cookie instead, out of reach of scripts
+    #[serde(skip)]
+    pub code_verifier: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct OAuthSignInRequest {
+    pub code: String,
+    pub state: String,
+    /// Taken from the cookie set when the sign in started, never from the body
+    #[serde(skip)]
+    pub code_verifier: String,
+
+    #[validate(length(
+        max = 255,
+        message = "Device name length must be at most 255 characters."
+    ))]
+    pub device_name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollmentResponse {
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    /// Shown only once, just their hashes are stored
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaCodeRequest {
+    /// A TOTP code or, where accepted, a recovery code
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+}
+
+/// Either the tokens, or a challenge to complete with `POST /api/auth/sign_in/mfa`
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum SignInResponse {
+    Authenticated(AuthResponse),
+    MfaRequired(MfaChallengeResponse),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignInMfaRequest {
+    pub mfa_token: String,
+    pub code: String,
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -48,10 +336,76 @@ pub struct SignUpRequest {
         message = "Password length must be between 6 and 16 characters."
     ))]
     pub password: String,
+
+    #[validate(length(
+        max = 255,
+        message = "Device name length must be at most 255 characters."
+    ))]
+    pub device_name: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate)]
 pub struct SignInRequest {
     pub username: String,
     pub password: String,
+
+    #[validate(length(
+        max = 255,
+        message = "Device name length must be at most 255 characters."
+    ))]
+    pub device_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+
+    #[validate(length(
+        min = 6,
+        max = 16,
+        message = "Password length must be between 6 and 16 characters."
+    ))]
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangeEmailRequest {
+    pub password: String,
+
+    #[validate(
+        email(message = "Invalid email format. Please provide a valid email address."),
+        length(
+            min = 1,
+            max = 64,
+            message = "Email length must be between 1 and 64 characters."
+        )
+    )]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+
+    #[validate(length(
+        min = 6,
+        max = 16,
+        message = "Password length must be between 6 and 16 characters."
+    ))]
+    pub password: String,
 }
@@ -0,0 +1,294 @@
+use crate::auth::model::ExternalIdentity;
+use crate::common::config::{Config, OAuthProviderConfig, OAuthProviderKind};
+use crate::common::model::Error;
+use reqwest::header::{ACCEPT, USER_AGENT};
+use reqwest::{Client, Url};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct OidcUserInfo {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    preferred_username: Option<String>,
+    name: Option<String>,
+    picture: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GithubUser {
+    id: i64,
+    login: String,
+    name: Option<String>,
+    avatar_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GithubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+/// Talks to the configured identity providers for the authorization code flow with PKCE
+pub struct OAuthClient {
+    http: Client,
+    providers: Vec<OAuthProviderConfig>,
+}
+
+impl OAuthClient {
+    pub fn init(config: &Config) -> Result<Self, Error> {
+        Self::with_providers(config.oauth_providers.clone())
+    }
+
+    fn with_providers(providers: Vec<OAuthProviderConfig>) -> Result<Self, Error> {
+        let http = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        Ok(Self { http, providers })
+    }
+
+    fn provider(&self, name: &str) -> Result<&OAuthProviderConfig, Error> {
+        self.providers
+            .iter()
+            .find(|provider| provider.name == name)
+            .ok_or_else(|| Error::NotFound(format!("Unknown provider {}", name)))
+    }
+
+    /// URL of the provider's consent page, which sends the user back to the redirect URL with
+    /// a code and the `state`
+    pub fn authorization_url(
+        &self,
+        provider: &str,
+        state: &str,
+        code_challenge: &str,
+    ) -> Result<String, Error> {
+        let provider = self.provider(provider)?;
+
+        let url = Url::parse_with_params(
+            &provider.authorization_url,
+            &[
+                ("response_type", "code"),
+                ("client_id", &provider.client_id),
+                ("redirect_uri", &provider.redirect_url),
+                ("scope", &provider.scopes),
+                ("state", state),
+                ("code_challenge", code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        Ok(url.to_string())
+    }
+
+    /// Redeems an authorization code and looks up who it was issued to
+    pub async fn exchange(
+        &self,
+        provider: &str,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<ExternalIdentity, Error> {
+        let provider = self.provider(provider)?;
+
+        let response = self
+            .http
+            .post(&provider.token_url)
+            .header(ACCEPT, "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &provider.redirect_url),
+                ("client_id", &provider.client_id),
+                ("client_secret", &provider.client_secret),
+                ("code_verifier", code_verifier),
+            ])
+            .send()
+            .await
+            .map_err(provider_error)?;
+        if !response.status().is_success() {
+            return Err(Error::UnAuthorized(
+                "Authorization code was rejected by the provider".to_string(),
+            ));
+        }
+        // GitHub answers a rejected code with 200 and an error body, hence no access token
+        let token = response
+            .json::<TokenResponse>()
+            .await
+            .map_err(|_| {
+                Error::UnAuthorized("Authorization code was rejected by the provider".to_string())
+            })?
+            .access_token;
+
+        match provider.kind {
+            OAuthProviderKind::Oidc => {
+                let info: OidcUserInfo = self.get(&provider.userinfo_url, &token).await?;
+
+                Ok(ExternalIdentity {
+                    provider: provider.name.clone(),
+                    subject: info.sub,
+                    email: info.email,
+                    email_verified: info.email_verified,
+                    username: info.preferred_username,
+                    name: info.name,
+                    photo_url: info.picture,
+                })
+            }
+            OAuthProviderKind::Github => {
+                let user: GithubUser = self.get(&provider.userinfo_url, &token).await?;
+                // The email on the profile may be hidden, the emails endpoint always lists it
+                let emails: Vec<GithubEmail> = self
+                    .get(
+                        &format!("{}/emails", provider.userinfo_url.trim_end_matches('/')),
+                        &token,
+                    )
+                    .await?;
+                let email = emails
+                    .into_iter()
+                    .find(|email| email.primary && email.verified);
+
+                Ok(ExternalIdentity {
+                    provider: provider.name.clone(),
+                    subject: user.id.to_string(),
+                    email_verified: email.is_some(),
+                    email: email.map(|email| email.email),
+                    username: Some(user.login),
+                    name: user.name,
+                    photo_url: user.avatar_url,
+                })
+            }
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str, token: &str) -> Result<T, Error> {
+        self.http
+            .get(url)
+            .bearer_auth(token)
+            .header(ACCEPT, "application/json")
+            .header(USER_AGENT, "medhia")
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(provider_error)?
+            .json::<T>()
+            .await
+            .map_err(provider_error)
+    }
+}
+
+fn provider_error(error: reqwest::Error) -> Error {
+    Error::InternalServerError(format!("Identity provider request failed: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::{get, post};
+    use axum::{Form, Json, Router};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    const CODE: &str = "code";
+    const CODE_VERIFIER: &str = "verifier";
+
+    /// Identity provider that only redeems `CODE` along with `CODE_VERIFIER`
+    async fn mock_provider() -> OAuthClient {
+        let app = Router::new()
+            .route(
+                "/token",
+                post(|Form(form): Form<HashMap<String, String>>| async move {
+                    let valid = form.get("grant_type").map(String::as_str)
+                        == Some("authorization_code")
+                        && form.get("code").map(String::as_str) == Some(CODE)
+                        && form.get("code_verifier").map(String::as_str) == Some(CODE_VERIFIER);
+                    if !valid {
+                        return Err(StatusCode::BAD_REQUEST);
+                    }
+
+                    Ok(Json(
+                        json!({ "access_token": "token", "token_type": "Bearer" }),
+                    ))
+                }),
+            )
+            .route(
+                "/userinfo",
+                get(|headers: HeaderMap| async move {
+                    let authorization = headers.get("authorization").and_then(|v| v.to_str().ok());
+                    if authorization != Some("Bearer token") {
+                        return Err(StatusCode::UNAUTHORIZED);
+                    }
+
+                    Ok(Json(json!({
+                        "sub": "42",
+                        "email": "jane@example.com",
+                        "email_verified": true,
+                        "preferred_username": "jane",
+                    })))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        OAuthClient::with_providers(vec![OAuthProviderConfig {
+            name: "mock".to_string(),
+            kind: OAuthProviderKind::Oidc,
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            authorization_url: format!("{}/authorize", url),
+            token_url: format!("{}/token", url),
+            userinfo_url: format!("{}/userinfo", url),
+            scopes: "openid email profile".to_string(),
+            redirect_url: "http://localhost:3000/oauth/mock".to_string(),
+        }])
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn exchange_redeems_the_code_with_the_verifier() {
+        let client = mock_provider().await;
+
+        let identity = client.exchange("mock", CODE, CODE_VERIFIER).await.unwrap();
+
+        assert_eq!(identity.provider, "mock");
+        assert_eq!(identity.subject, "42");
+        assert_eq!(identity.email.as_deref(), Some("jane@example.com"));
+        assert!(identity.email_verified);
+        assert_eq!(identity.username.as_deref(), Some("jane"));
+    }
+
+    #[tokio::test]
+    async fn exchange_fails_with_another_verifier() {
+        let client = mock_provider().await;
+
+        let result = client.exchange("mock", CODE, "other verifier").await;
+
+        assert!(matches!(result, Err(Error::UnAuthorized(_))));
+    }
+
+    #[tokio::test]
+    async fn authorization_url_carries_the_challenge() {
+        let client = mock_provider().await;
+
+        let url = client
+            .authorization_url("mock", "state", "challenge")
+            .unwrap();
+        let url = Url::parse(&url).unwrap();
+        let params = url.query_pairs().into_owned().collect::<HashMap<_, _>>();
+
+        assert_eq!(params["state"], "state");
+        assert_eq!(params["code_challenge"], "challenge");
+        assert_eq!(params["code_challenge_method"], "S256");
+    }
+}
@@ -0,0 +1,192 @@
+use crate::auth::model::{Permission, Role, Session, Totp};
+use crate::common::model::Error;
+use sqlx::{Pool, Postgres};
+use std::future::Future;
+use std::sync::Arc;
+
+pub trait SessionReadRepo: Send + Sync {
+    /// Sessions of the user that are not revoked yet, most recently seen first
+    fn find_all_active_by_user_id(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = Result<Vec<Session>, Error>> + Send;
+}
+
+pub struct SessionReadRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl SessionReadRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl SessionReadRepo for SessionReadRepoPg {
+    async fn find_all_active_by_user_id(&self, user_id: i64) -> Result<Vec<Session>, Error> {
+        let query = r#"
+            SELECT 
+                id, refresh_generation, device_name, user_agent, ip, last_seen_at, 
+                created_at
+            FROM 
+                "session"
+            WHERE 
+                user_id = $1 AND revoked_at IS NULL
+            ORDER BY 
+                last_seen_at DESC
+        "#;
+
+        sqlx::query_as::<_, Session>(query)
+            .bind(user_id)
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+pub trait TotpReadRepo: Send + Sync {
+    fn find_by_user_id(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = Result<Option<Totp>, Error>> + Send;
+}
+
+pub struct TotpReadRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl TotpReadRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl TotpReadRepo for TotpReadRepoPg {
+    async fn find_by_user_id(&self, user_id: i64) -> Result<Option<Totp>, Error> {
+        let query = r#"
+            SELECT 
+                secret, enabled_at
+            FROM 
+                "totp"
+            WHERE 
+                user_id = $1
+        "#;
+
+        sqlx::query_as::<_, Totp>(query)
+            .bind(user_id)
+            .fetch_optional(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+pub trait UserIdentityReadRepo: Send + Sync {
+    /// Id of the user the provider's account is linked to
+    fn find_user_id(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> impl Future<Output = Result<Option<i64>, Error>> + Send;
+}
+
+pub struct UserIdentityReadRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl UserIdentityReadRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl UserIdentityReadRepo for UserIdentityReadRepoPg {
+    async fn find_user_id(&self, provider: &str, subject: &str) -> Result<Option<i64>, Error> {
+        let query = r#"
+            SELECT 
+                user_id
+            FROM 
+                "user_identity"
+            WHERE 
+                provider = $1 AND subject = $2
+        "#;
+
+        sqlx::query_scalar(query)
+            .bind(provider)
+            .bind(subject)
+            .fetch_optional(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+pub trait RoleReadRepo: Send + Sync {
+    /// Roles granted to the user, `Role::User` included
+    fn find_roles_by_user_id(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = Result<Vec<Role>, Error>> + Send;
+
+    /// Permissions of all roles of the user
+    fn find_permissions_by_user_id(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = Result<Vec<Permission>, Error>> + Send;
+}
+
+pub struct RoleReadRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl RoleReadRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl RoleReadRepo for RoleReadRepoPg {
+    async fn find_roles_by_user_id(&self, user_id: i64) -> Result<Vec<Role>, Error> {
+        let query = r#"
+            SELECT 
+                role
+            FROM 
+                "user_role"
+            WHERE 
+                user_id = $1
+            UNION
+            SELECT 
+                'USER'
+        "#;
+
+        let roles: Vec<String> = sqlx::query_scalar(query)
+            .bind(user_id)
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        // Roles added to the table before the code knows them are ignored
+        Ok(roles.iter().filter_map(|role| role.parse().ok()).collect())
+    }
+
+    async fn find_permissions_by_user_id(&self, user_id: i64) -> Result<Vec<Permission>, Error> {
+        let query = r#"
+            SELECT DISTINCT
+                rp.permission
+            FROM 
+                "role_permission" rp
+            WHERE 
+                rp.role = 'USER' 
+                OR rp.role IN (SELECT ur.role FROM "user_role" ur WHERE ur.user_id = $1)
+        "#;
+
+        let permissions: Vec<String> = sqlx::query_scalar(query)
+            .bind(user_id)
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        Ok(permissions
+            .iter()
+            .filter_map(|permission| permission.parse().ok())
+            .collect())
+    }
+}
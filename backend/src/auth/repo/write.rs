@@ -0,0 +1,682 @@
+use crate::auth::model::{
+    CreateSessionRequest, CreateSignInAttemptRequest, ExternalIdentity, Role, Session,
+};
+use crate::common::database::connection;
+use crate::common::model::Error;
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+use std::future::Future;
+use std::sync::Arc;
+
+pub trait SessionWriteRepo: Send + Sync {
+    fn create(
+        &self,
+        req: CreateSessionRequest,
+    ) -> impl Future<Output = Result<Session, Error>> + Send;
+
+    /// Moves the session to the next refresh generation and marks it as seen, but only if it is
+    /// still active and at `generation`. Returns `None` otherwise.
+    fn rotate(
+        &self,
+        session_id: i64,
+        generation: i32,
+    ) -> impl Future<Output = Result<Option<Session>, Error>> + Send;
+
+    fn revoke(&self, session_id: i64) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Marks the session as seen now, if it is still active. Returns whether it is.
+    fn touch(&self, session_id: i64) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Revokes every active session of the user, but the one excepted if any. Returns the ids of
+    /// the revoked sessions.
+    fn revoke_all_by_user_id(
+        &self,
+        user_id: i64,
+        except_session_id: Option<i64>,
+    ) -> impl Future<Output = Result<Vec<i64>, Error>> + Send;
+
+    /// Revokes the session only if it belongs to the user and is still active. Returns whether
+    /// it was revoked.
+    fn revoke_by_user_id(
+        &self,
+        user_id: i64,
+        session_id: i64,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+}
+
+pub struct SessionWriteRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl SessionWriteRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl SessionWriteRepo for SessionWriteRepoPg {
+    async fn create(&self, req: CreateSessionRequest) -> Result<Session, Error> {
+        let query = r#"
+            INSERT INTO "session" (
+                id, user_id, refresh_generation, device_name, user_agent, ip, revoked_at, 
+                last_seen_at, created_at, updated_at
+            ) VALUES (
+                default, $1, 0, $2, $3, $4, NULL, $5, $6, $7
+            )
+            RETURNING 
+                id, refresh_generation, device_name, user_agent, ip, last_seen_at, 
+                created_at
+        "#;
+
+        sqlx::query_as::<_, Session>(query)
+            .bind(req.user_id)
+            .bind(req.device_name)
+            .bind(req.user_agent)
+            .bind(req.ip)
+            .bind(Utc::now()) // last_seen_at
+            .bind(Utc::now()) // created_at
+            .bind(Utc::now()) // updated_at
+            .fetch_one(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn rotate(&self, session_id: i64, generation: i32) -> Result<Option<Session>, Error> {
+        let query = r#"
+            UPDATE 
+                "session"
+            SET 
+                refresh_generation = refresh_generation + 1,
+                last_seen_at = $1,
+                updated_at = $2
+            WHERE 
+                id = $3 AND refresh_generation = $4 AND revoked_at IS NULL
+            RETURNING 
+                id, refresh_generation, device_name, user_agent, ip, last_seen_at, 
+                created_at
+        "#;
+
+        sqlx::query_as::<_, Session>(query)
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .bind(session_id)
+            .bind(generation)
+            .fetch_optional(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn touch(&self, session_id: i64) -> Result<bool, Error> {
+        let query = r#"
+            UPDATE 
+                "session"
+            SET 
+                last_seen_at = $1
+            WHERE 
+                id = $2 AND revoked_at IS NULL
+        "#;
+
+        sqlx::query(query)
+            .bind(Utc::now())
+            .bind(session_id)
+            .execute(&*self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn revoke(&self, session_id: i64) -> Result<(), Error> {
+        let query = r#"
+            UPDATE 
+                "session"
+            SET 
+                revoked_at = COALESCE(revoked_at, $1),
+                updated_at = $2
+            WHERE 
+                id = $3
+        "#;
+
+        sqlx::query(query)
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .bind(session_id)
+            .execute(&*self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn revoke_all_by_user_id(
+        &self,
+        user_id: i64,
+        except_session_id: Option<i64>,
+    ) -> Result<Vec<i64>, Error> {
+        let query = r#"
+            UPDATE 
+                "session"
+            SET 
+                revoked_at = $1,
+                updated_at = $2
+            WHERE 
+                user_id = $3 AND id IS DISTINCT FROM $4 AND revoked_at IS NULL
+            RETURNING 
+                id
+        "#;
+
+        let query = sqlx::query_scalar(query)
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .bind(user_id)
+            .bind(except_session_id);
+
+        // Runs in the transaction of a moderation action's unit of work, if there is one
+        query
+            .fetch_all(&mut *connection(&self.pool).await?)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn revoke_by_user_id(&self, user_id: i64, session_id: i64) -> Result<bool, Error> {
+        let query = r#"
+            UPDATE 
+                "session"
+            SET 
+                revoked_at = $1,
+                updated_at = $2
+            WHERE 
+                id = $3 AND user_id = $4 AND revoked_at IS NULL
+        "#;
+
+        sqlx::query(query)
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .bind(session_id)
+            .bind(user_id)
+            .execute(&*self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+pub trait PasswordResetWriteRepo: Send + Sync {
+    fn create(
+        &self,
+        user_id: i64,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Marks the reset as used if it is still unused and not expired, along with every other
+    /// pending reset of its user. Returns the user it was for.
+    fn consume(&self, token_hash: &str) -> impl Future<Output = Result<Option<i64>, Error>> + Send;
+}
+
+pub struct PasswordResetWriteRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl PasswordResetWriteRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl PasswordResetWriteRepo for PasswordResetWriteRepoPg {
+    async fn create(
+        &self,
+        user_id: i64,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let query = r#"
+            INSERT INTO "password_reset" (
+                id, user_id, token_hash, expires_at, used_at, created_at
+            ) VALUES (
+                default, $1, $2, $3, NULL, $4
+            )
+        "#;
+
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(token_hash)
+            .bind(expires_at)
+            .bind(Utc::now()) // created_at
+            .execute(&mut *connection(&self.pool).await?)
+            .await
+            .map(|_| ())
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn consume(&self, token_hash: &str) -> Result<Option<i64>, Error> {
+        let query = r#"
+            WITH consumed AS (
+                UPDATE 
+                    "password_reset"
+                SET 
+                    used_at = $1
+                WHERE 
+                    token_hash = $2 AND used_at IS NULL AND expires_at > $1
+                RETURNING 
+                    user_id
+            ), 
+            superseded AS (
+                UPDATE 
+                    "password_reset" p
+                SET 
+                    used_at = $1
+                FROM 
+                    consumed
+                WHERE 
+                    p.user_id = consumed.user_id AND p.used_at IS NULL AND p.token_hash <> $2
+            )
+            SELECT 
+                user_id
+            FROM 
+                consumed
+        "#;
+
+        sqlx::query_scalar(query)
+            .bind(Utc::now())
+            .bind(token_hash)
+            .fetch_optional(&mut *connection(&self.pool).await?)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+pub trait TotpWriteRepo: Send + Sync {
+    /// Starts a new, not yet enabled enrollment, replacing an earlier unfinished one
+    fn create_pending(
+        &self,
+        user_id: i64,
+        secret: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Enables the pending enrollment and replaces the user's recovery codes
+    fn enable(
+        &self,
+        user_id: i64,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Removes the user's TOTP along with their recovery codes
+    fn delete(&self, user_id: i64) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Records `step` as used if it is newer than the last used one. Returns whether it was.
+    fn use_step(&self, user_id: i64, step: i64)
+        -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Marks an unused recovery code of the user as used. Returns whether there was one.
+    fn use_recovery_code(
+        &self,
+        user_id: i64,
+        code_hash: &str,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Records the sign in challenge `jti` as completed, sweeping expired ones. Returns whether
+    /// it was not completed before.
+    fn use_challenge(
+        &self,
+        jti: &str,
+        user_id: i64,
+        expires_at: DateTime<Utc>,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+}
+
+pub struct TotpWriteRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl TotpWriteRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl TotpWriteRepo for TotpWriteRepoPg {
+    async fn create_pending(&self, user_id: i64, secret: &str) -> Result<(), Error> {
+        let query = r#"
+            INSERT INTO "totp" (
+                user_id, secret, enabled_at, last_used_step, created_at, updated_at
+            ) VALUES (
+                $1, $2, NULL, 0, $3, $4
+            )
+            ON CONFLICT (user_id) DO UPDATE SET
+                secret = EXCLUDED.secret,
+                last_used_step = 0,
+                updated_at = EXCLUDED.updated_at
+            WHERE 
+                "totp".enabled_at IS NULL
+        "#;
+
+        let result = sqlx::query(query)
+            .bind(user_id)
+            .bind(secret)
+            .bind(Utc::now()) // created_at
+            .bind(Utc::now()) // updated_at
+            .execute(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+        if result.rows_affected() == 0 {
+            return Err(Error::Conflict(
+                "Two-factor authentication is already enabled".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn enable(
+        &self,
+        user_id: i64,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> Result<(), Error> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        let query = r#"
+            UPDATE 
+                "totp"
+            SET 
+                enabled_at = $1,
+                last_used_step = $2,
+                updated_at = $3
+            WHERE 
+                user_id = $4 AND enabled_at IS NULL
+        "#;
+
+        let result = sqlx::query(query)
+            .bind(Utc::now())
+            .bind(step)
+            .bind(Utc::now())
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+        if result.rows_affected() == 0 {
+            return Err(Error::Conflict(
+                "Two-factor authentication is already enabled".to_string(),
+            ));
+        }
+
+        sqlx::query(r#"DELETE FROM "recovery_code" WHERE user_id = $1"#)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        let query = r#"
+            INSERT INTO "recovery_code" (
+                user_id, code_hash, used_at, created_at
+            ) 
+            SELECT 
+                $1, code_hash, NULL, $2
+            FROM 
+                UNNEST($3::VARCHAR[]) AS code_hash
+        "#;
+
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(Utc::now())
+            .bind(recovery_code_hashes)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        tx.commit()
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn delete(&self, user_id: i64) -> Result<(), Error> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        sqlx::query(r#"DELETE FROM "recovery_code" WHERE user_id = $1"#)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        sqlx::query(r#"DELETE FROM "totp" WHERE user_id = $1"#)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        tx.commit()
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn use_step(&self, user_id: i64, step: i64) -> Result<bool, Error> {
+        let query = r#"
+            UPDATE 
+                "totp"
+            SET 
+                last_used_step = $1,
+                updated_at = $2
+            WHERE 
+                user_id = $3 AND last_used_step < $1
+        "#;
+
+        sqlx::query(query)
+            .bind(step)
+            .bind(Utc::now())
+            .bind(user_id)
+            .execute(&*self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn use_recovery_code(&self, user_id: i64, code_hash: &str) -> Result<bool, Error> {
+        let query = r#"
+            UPDATE 
+                "recovery_code"
+            SET 
+                used_at = $1
+            WHERE 
+                id = (
+                    SELECT 
+                        id 
+                    FROM 
+                        "recovery_code" 
+                    WHERE 
+                        user_id = $2 AND code_hash = $3 AND used_at IS NULL 
+                    LIMIT 1
+                ) AND used_at IS NULL
+        "#;
+
+        sqlx::query(query)
+            .bind(Utc::now())
+            .bind(user_id)
+            .bind(code_hash)
+            .execute(&*self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn use_challenge(
+        &self,
+        jti: &str,
+        user_id: i64,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Error> {
+        let query = r#"
+            WITH swept AS (
+                DELETE FROM 
+                    "mfa_challenge"
+                WHERE 
+                    expires_at < $4
+            )
+            INSERT INTO "mfa_challenge" (
+                jti, user_id, expires_at, used_at
+            ) VALUES (
+                $1, $2, $3, $4
+            )
+            ON CONFLICT (jti) DO NOTHING
+        "#;
+
+        sqlx::query(query)
+            .bind(jti)
+            .bind(user_id)
+            .bind(expires_at)
+            .bind(Utc::now()) // used_at
+            .execute(&*self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+pub trait SignInAttemptWriteRepo: Send + Sync {
+    fn create(
+        &self,
+        req: CreateSignInAttemptRequest,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+pub struct SignInAttemptWriteRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl SignInAttemptWriteRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl SignInAttemptWriteRepo for SignInAttemptWriteRepoPg {
+    async fn create(&self, req: CreateSignInAttemptRequest) -> Result<(), Error> {
+        let query = r#"
+            INSERT INTO "sign_in_attempt" (
+                id, user_id, identifier, ip, user_agent, reason, created_at
+            ) VALUES (
+                default, $1, $2, $3, $4, $5, $6
+            )
+        "#;
+
+        sqlx::query(query)
+            .bind(req.user_id)
+            .bind(req.identifier.chars().take(255).collect::<String>())
+            .bind(req.ip)
+            .bind(req.user_agent)
+            .bind(req.reason.as_str())
+            .bind(Utc::now()) // created_at
+            .execute(&*self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+pub trait UserIdentityWriteRepo: Send + Sync {
+    /// Links the provider's account to the user
+    fn create(
+        &self,
+        user_id: i64,
+        identity: &ExternalIdentity,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+pub struct UserIdentityWriteRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl UserIdentityWriteRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl UserIdentityWriteRepo for UserIdentityWriteRepoPg {
+    async fn create(&self, user_id: i64, identity: &ExternalIdentity) -> Result<(), Error> {
+        let query = r#"
+            INSERT INTO "user_identity" (
+                id, user_id, provider, subject, email, created_at
+            ) VALUES (
+                default, $1, $2, $3, $4, $5
+            )
+            ON CONFLICT (provider, subject) DO NOTHING
+        "#;
+
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(&identity.provider)
+            .bind(&identity.subject)
+            .bind(&identity.email)
+            .bind(Utc::now()) // created_at
+            .execute(&*self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+pub trait RoleWriteRepo: Send + Sync {
+    /// Returns whether the user did not have the role yet
+    fn grant(&self, user_id: i64, role: Role) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Returns whether the user had the role
+    fn revoke(&self, user_id: i64, role: Role) -> impl Future<Output = Result<bool, Error>> + Send;
+}
+
+pub struct RoleWriteRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl RoleWriteRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl RoleWriteRepo for RoleWriteRepoPg {
+    async fn grant(&self, user_id: i64, role: Role) -> Result<bool, Error> {
+        let query = r#"
+            INSERT INTO "user_role" (
+                user_id, role, created_at
+            ) VALUES (
+                $1, $2, $3
+            )
+            ON CONFLICT (user_id, role) DO NOTHING
+        "#;
+
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(role.as_str())
+            .bind(Utc::now()) // created_at
+            .execute(&*self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn revoke(&self, user_id: i64, role: Role) -> Result<bool, Error> {
+        let query = r#"
+            DELETE FROM 
+                "user_role"
+            WHERE 
+                user_id = $1 AND role = $2
+        "#;
+
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(role.as_str())
+            .execute(&*self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
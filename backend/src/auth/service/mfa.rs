@@ -0,0 +1,226 @@
+use crate::auth::model::{MfaCodeRequest, RecoveryCodesResponse, TotpEnrollmentResponse};
+use crate::auth::repo::{TotpReadRepo, TotpWriteRepo};
+use crate::auth::totp;
+use crate::common::config::Config;
+use crate::common::model::Error;
+use crate::user::repo::UserReadRepo;
+use chrono::{DateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::sync::Arc;
+
+const RECOVERY_CODES: usize = 10;
+
+pub trait MfaService {
+    /// Starts enrolling a TOTP authenticator, it takes effect once confirmed with a code
+    fn enroll_totp(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = Result<TotpEnrollmentResponse, Error>> + Send;
+
+    fn confirm_totp(
+        &self,
+        user_id: i64,
+        req: MfaCodeRequest,
+    ) -> impl Future<Output = Result<RecoveryCodesResponse, Error>> + Send;
+
+    fn disable_totp(
+        &self,
+        user_id: i64,
+        req: MfaCodeRequest,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn is_enabled(&self, user_id: i64) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Accepts a TOTP code that wasn't used before or an unused recovery code
+    fn verify(&self, user_id: i64, code: &str) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Fails if the sign in challenge `jti` was completed before
+    fn use_challenge(
+        &self,
+        jti: &str,
+        user_id: i64,
+        expires_at: DateTime<Utc>,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+pub struct MfaServiceImpl<U, R, W>
+where
+    U: UserReadRepo + Send + Sync + 'static,
+    R: TotpReadRepo + Send + Sync + 'static,
+    W: TotpWriteRepo + Send + Sync + 'static,
+{
+    user_read_repo: Arc<U>,
+    totp_read_repo: Arc<R>,
+    totp_write_repo: Arc<W>,
+    config: Arc<Config>,
+}
+
+impl<U, R, W> MfaServiceImpl<U, R, W>
+where
+    U: UserReadRepo + Send + Sync + 'static,
+    R: TotpReadRepo + Send + Sync + 'static,
+    W: TotpWriteRepo + Send + Sync + 'static,
+{
+    pub fn new(
+        user_read_repo: Arc<U>,
+        totp_read_repo: Arc<R>,
+        totp_write_repo: Arc<W>,
+        config: Arc<Config>,
+    ) -> Self {
+        Self {
+            user_read_repo,
+            totp_read_repo,
+            totp_write_repo,
+            config,
+        }
+    }
+
+    /// Recovery codes are compared case and separator insensitive
+    fn hash_recovery_code(&self, code: &str) -> String {
+        let code = code
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_lowercase();
+
+        hex::encode(Sha256::digest(code.as_bytes()))
+    }
+
+    fn generate_recovery_code(&self) -> String {
+        let mut bytes = [0u8; 10];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let code = BASE32_NOPAD.encode(&bytes).to_lowercase();
+
+        format!("{}-{}", &code[..8], &code[8..])
+    }
+
+    /// Step of a valid, unused TOTP code of the enrollment, enabled or not
+    async fn verify_totp(&self, secret: &str, user_id: i64, code: &str) -> Result<i64, Error> {
+        let step = totp::verify(secret, code, chrono::Utc::now().timestamp())
+            .ok_or_else(|| Error::UnAuthorized("Invalid code".to_string()))?;
+        if !self.totp_write_repo.use_step(user_id, step).await? {
+            return Err(Error::UnAuthorized(
+                "Code has already been used".to_string(),
+            ));
+        }
+
+        Ok(step)
+    }
+}
+
+impl<U, R, W> MfaService for MfaServiceImpl<U, R, W>
+where
+    U: UserReadRepo + Send + Sync + 'static,
+    R: TotpReadRepo + Send + Sync + 'static,
+    W: TotpWriteRepo + Send + Sync + 'static,
+{
+    async fn enroll_totp(&self, user_id: i64) -> Result<TotpEnrollmentResponse, Error> {
+        let user = self
+            .user_read_repo
+            .find_by_id(user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+
+        let secret = totp::generate_secret();
+        self.totp_write_repo
+            .create_pending(user_id, &secret)
+            .await?;
+
+        Ok(TotpEnrollmentResponse {
+            provisioning_uri: totp::provisioning_uri(
+                &self.config.totp_issuer,
+                &user.username,
+                &secret,
+            ),
+            secret,
+        })
+    }
+
+    async fn confirm_totp(
+        &self,
+        user_id: i64,
+        req: MfaCodeRequest,
+    ) -> Result<RecoveryCodesResponse, Error> {
+        let enrollment = self
+            .totp_read_repo
+            .find_by_user_id(user_id)
+            .await?
+            .filter(|totp| totp.enabled_at.is_none())
+            .ok_or_else(|| Error::NotFound("No pending enrollment found".to_string()))?;
+
+        let step = self
+            .verify_totp(&enrollment.secret, user_id, &req.code)
+            .await?;
+
+        let recovery_codes = (0..RECOVERY_CODES)
+            .map(|_| self.generate_recovery_code())
+            .collect::<Vec<_>>();
+        let hashes = recovery_codes
+            .iter()
+            .map(|code| self.hash_recovery_code(code))
+            .collect::<Vec<_>>();
+        self.totp_write_repo.enable(user_id, step, &hashes).await?;
+
+        Ok(RecoveryCodesResponse { recovery_codes })
+    }
+
+    async fn disable_totp(&self, user_id: i64, req: MfaCodeRequest) -> Result<(), Error> {
+        self.verify(user_id, &req.code).await?;
+
+        self.totp_write_repo.delete(user_id).await
+    }
+
+    async fn is_enabled(&self, user_id: i64) -> Result<bool, Error> {
+        let totp = self.totp_read_repo.find_by_user_id(user_id).await?;
+
+        Ok(totp.is_some_and(|totp| totp.enabled_at.is_some()))
+    }
+
+    async fn verify(&self, user_id: i64, code: &str) -> Result<(), Error> {
+        let totp = self
+            .totp_read_repo
+            .find_by_user_id(user_id)
+            .await?
+            .filter(|totp| totp.enabled_at.is_some())
+            .ok_or_else(|| {
+                Error::BadRequest("Two-factor authentication is not enabled".to_string())
+            })?;
+
+        if code.trim().chars().all(|c| c.is_ascii_digit()) {
+            self.verify_totp(&totp.secret, user_id, code).await?;
+            return Ok(());
+        }
+
+        if !self
+            .totp_write_repo
+            .use_recovery_code(user_id, &self.hash_recovery_code(code))
+            .await?
+        {
+            return Err(Error::UnAuthorized("Invalid code".to_string()));
+        }
+
+        Ok(())
+    }
+
+    async fn use_challenge(
+        &self,
+        jti: &str,
+        user_id: i64,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        if !self
+            .totp_write_repo
+            .use_challenge(jti, user_id, expires_at)
+            .await?
+        {
+            return Err(Error::UnAuthorized(
+                "Challenge token has already been used".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
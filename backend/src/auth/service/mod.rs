@@ -1,5 +1,11 @@
+mod mfa;
+mod oauth;
 mod read;
+mod role;
 mod write;
 
+pub use mfa::*;
+pub use oauth::*;
 pub use read::*;
+pub use role::*;
 pub use write::*;
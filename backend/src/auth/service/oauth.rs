@@ -0,0 +1,311 @@
+use crate::auth::model::{
+    ExternalIdentity, OAuthAuthorizationResponse, OAuthClaim, OAuthSignInRequest,
+};
+use crate::auth::oauth::OAuthClient;
+use crate::auth::repo::{UserIdentityReadRepo, UserIdentityWriteRepo};
+use crate::auth::service::hash_password;
+use crate::common::config::Config;
+use crate::common::model::Error;
+use crate::user::model::{CreateUserRequest, User};
+use crate::user::repo::{UserReadRepo, UserWriteRepo};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::{Rng, RngCore};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::ops::Add;
+use std::sync::Arc;
+
+/// `OAuthClaim::purpose` of social sign in states
+const OAUTH_PURPOSE: &str = "oauth";
+
+/// How long the user has to get through the provider's consent page
+const OAUTH_STATE_TTL_MINUTES: i64 = 10;
+
+/// Limit of the username validator on sign up
+const MAX_USERNAME_LENGTH: usize = 10;
+
+pub trait OAuthService {
+    /// Starts a social sign in, the user is to be sent to the returned URL
+    fn authorize(
+        &self,
+        provider: &str,
+    ) -> impl Future<Output = Result<OAuthAuthorizationResponse, Error>> + Send;
+
+    /// Completes a social sign in with the code the provider redirected back with. Returns the
+    /// user the provider's account is linked to, linking or creating one on first sign in.
+    fn authenticate(
+        &self,
+        provider: &str,
+        req: &OAuthSignInRequest,
+    ) -> impl Future<Output = Result<User, Error>> + Send;
+}
+
+pub struct OAuthServiceImpl<UR, UW, IR, IW>
+where
+    UR: UserReadRepo + Send + Sync + 'static,
+    UW: UserWriteRepo + Send + Sync + 'static,
+    IR: UserIdentityReadRepo + Send + Sync + 'static,
+    IW: UserIdentityWriteRepo + Send + Sync + 'static,
+{
+    user_read_repo: Arc<UR>,
+    user_write_repo: Arc<UW>,
+    user_identity_read_repo: Arc<IR>,
+    user_identity_write_repo: Arc<IW>,
+    oauth_client: Arc<OAuthClient>,
+    config: Arc<Config>,
+}
+
+impl<UR, UW, IR, IW> OAuthServiceImpl<UR, UW, IR, IW>
+where
+    UR: UserReadRepo + Send + Sync + 'static,
+    UW: UserWriteRepo + Send + Sync + 'static,
+    IR: UserIdentityReadRepo + Send + Sync + 'static,
+    IW: UserIdentityWriteRepo + Send + Sync + 'static,
+{
+    pub fn new(
+        user_read_repo: Arc<UR>,
+        user_write_repo: Arc<UW>,
+        user_identity_read_repo: Arc<IR>,
+        user_identity_write_repo: Arc<IW>,
+        oauth_client: Arc<OAuthClient>,
+        config: Arc<Config>,
+    ) -> Self {
+        Self {
+            user_read_repo,
+            user_write_repo,
+            user_identity_read_repo,
+            user_identity_write_repo,
+            oauth_client,
+            config,
+        }
+    }
+
+    /// A free username close to the provider's username or the email's local part
+    async fn generate_username(&self, identity: &ExternalIdentity) -> Result<String, Error> {
+        let base = identity
+            .username
+            .as_deref()
+            .or_else(|| identity.email.as_deref()?.split('@').next())
+            .unwrap_or("user")
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .take(MAX_USERNAME_LENGTH)
+            .collect::<String>()
+            .to_lowercase();
+        let base = if base.is_empty() {
+            "user".to_string()
+        } else {
+            base
+        };
+
+        if !self.user_read_repo.exists_by_username(&base).await? {
+            return Ok(base);
+        }
+        for _ in 0..5 {
+            let prefix = base
+                .chars()
+                .take(MAX_USERNAME_LENGTH - 4)
+                .collect::<String>();
+            let username = format!("{}{:04}", prefix, rand::thread_rng().gen_range(0..10_000));
+            if !self.user_read_repo.exists_by_username(&username).await? {
+                return Ok(username);
+            }
+        }
+
+        Err(Error::Conflict(
+            "Could not find a free username, please sign up instead".to_string(),
+        ))
+    }
+
+    async fn create_user(&self, identity: &ExternalIdentity, email: &str) -> Result<User, Error> {
+        let username = self.generate_username(identity).await?;
+
+        // The account has no password until one is set through the password reset flow
+        let mut password = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut password);
+        let password = hash_password(URL_SAFE_NO_PAD.encode(password)).await?;
+
+        let user = self
+            .user_write_repo
+            .create(CreateUserRequest {
+                name: identity.name.clone().unwrap_or_else(|| username.clone()),
+                username,
+                email: email.to_string(),
+                password,
+                photo_url: identity.photo_url.clone(),
+            })
+            .await?;
+        self.user_write_repo.verify_email(user.id, email).await?;
+
+        self.user_read_repo
+            .find_by_id(user.id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))
+    }
+}
+
+impl<UR, UW, IR, IW> OAuthService for OAuthServiceImpl<UR, UW, IR, IW>
+where
+    UR: UserReadRepo + Send + Sync + 'static,
+    UW: UserWriteRepo + Send + Sync + 'static,
+    IR: UserIdentityReadRepo + Send + Sync + 'static,
+    IW: UserIdentityWriteRepo + Send + Sync + 'static,
+{
+    async fn authorize(&self, provider: &str) -> Result<OAuthAuthorizationResponse, Error> {
+        let mut code_verifier = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut code_verifier);
+        let code_verifier = URL_SAFE_NO_PAD.encode(code_verifier);
+
+        let code_challenge = code_challenge(&code_verifier);
+        let state = create_state(
+            self.config.oauth_state_key_secret.as_ref(),
+            provider,
+            &code_challenge,
+        )?;
+        let authorization_url =
+            self.oauth_client
+                .authorization_url(provider, &state, &code_challenge)?;
+
+        Ok(OAuthAuthorizationResponse {
+            authorization_url,
+            state,
+            code_verifier,
+        })
+    }
+
+    async fn authenticate(&self, provider: &str, req: &OAuthSignInRequest) -> Result<User, Error> {
+        verify_state(
+            self.config.oauth_state_key_secret.as_ref(),
+            provider,
+            &req.state,
+            &req.code_verifier,
+        )?;
+        let identity = self
+            .oauth_client
+            .exchange(provider, &req.code, &req.code_verifier)
+            .await?;
+
+        if let Some(user_id) = self
+            .user_identity_read_repo
+            .find_user_id(&identity.provider, &identity.subject)
+            .await?
+        {
+            return self
+                .user_read_repo
+                .find_by_id(user_id)
+                .await?
+                .ok_or_else(|| Error::UnAuthorized("User not found".to_string()));
+        }
+
+        let email = match (&identity.email, identity.email_verified) {
+            (Some(email), true) => email.clone(),
+            _ => {
+                return Err(Error::BadRequest(format!(
+                    "{} did not share a verified email",
+                    provider
+                )))
+            }
+        };
+
+        let user =
+            match self.user_read_repo.find_by_email(&email).await? {
+                // Both sides proved they own the email, so it is the same person
+                Some(user) if user.email_verified_at.is_some() => user,
+                Some(_) => return Err(Error::Conflict(
+                    "An account with this email already exists, please sign in with your password"
+                        .to_string(),
+                )),
+                None => self.create_user(&identity, &email).await?,
+            };
+
+        self.user_identity_write_repo
+            .create(user.id, &identity)
+            .await?;
+
+        Ok(user)
+    }
+}
+
+/// S256 PKCE challenge of a verifier
+fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier))
+}
+
+fn create_state(key: &[u8], provider: &str, code_challenge: &str) -> Result<String, Error> {
+    jsonwebtoken::encode(
+        &Header::new(Algorithm::HS256),
+        &OAuthClaim {
+            exp: chrono::Utc::now()
+                .add(chrono::Duration::minutes(OAUTH_STATE_TTL_MINUTES))
+                .timestamp(),
+            iat: chrono::Utc::now().timestamp(),
+            purpose: OAUTH_PURPOSE.to_string(),
+            provider: provider.to_string(),
+            code_challenge: code_challenge.to_string(),
+        },
+        &EncodingKey::from_secret(key),
+    )
+    .map_err(|e| Error::InternalServerError(e.to_string()))
+}
+
+/// Fails unless the state was issued for the provider and the browser holding `code_verifier`
+fn verify_state(
+    key: &[u8],
+    provider: &str,
+    state: &str,
+    code_verifier: &str,
+) -> Result<OAuthClaim, Error> {
+    let invalid = || Error::BadRequest("Sign in state is invalid or expired".to_string());
+
+    let claim = jsonwebtoken::decode::<OAuthClaim>(
+        state,
+        &DecodingKey::from_secret(key),
+        &Validation::new(Algorithm::HS256),
+    )
+    .map_err(|_| invalid())?
+    .claims;
+    if claim.purpose != OAUTH_PURPOSE || claim.provider != provider {
+        return Err(invalid());
+    }
+    if code_verifier.is_empty() || claim.code_challenge != code_challenge(code_verifier) {
+        return Err(Error::BadRequest(
+            "Sign in was started in another browser, please start over".to_string(),
+        ));
+    }
+
+    Ok(claim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"state key";
+
+    #[test]
+    fn states_are_bound_to_the_verifier() {
+        let state = create_state(KEY, "google", &code_challenge("verifier")).unwrap();
+
+        assert!(verify_state(KEY, "google", &state, "verifier").is_ok());
+        assert!(verify_state(KEY, "google", &state, "other verifier").is_err());
+        assert!(verify_state(KEY, "google", &state, "").is_err());
+    }
+
+    #[test]
+    fn states_are_bound_to_the_provider_and_key() {
+        let state = create_state(KEY, "google", &code_challenge("verifier")).unwrap();
+
+        assert!(verify_state(KEY, "github", &state, "verifier").is_err());
+        assert!(verify_state(b"other key", "google", &state, "verifier").is_err());
+    }
+
+    #[test]
+    fn code_challenge_follows_rfc_7636() {
+        // Appendix B of RFC 7636
+        let challenge = code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk");
+
+        assert_eq!(challenge, "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+    }
+}
@@ -1,34 +1,136 @@
-use crate::auth::model::Claim;
-use crate::common::config::Config;
+use crate::auth::cache::SessionCache;
+use crate::auth::keys::KeySet;
+use crate::auth::model::{Claim, Permission, SessionResponse};
+use crate::auth::repo::{RoleReadRepo, SessionReadRepo, SessionWriteRepo};
 use crate::common::model::Error;
-use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use crate::user::repo::UserReadRepo;
+use jsonwebtoken::jwk::JwkSet;
 use std::future::Future;
 use std::sync::Arc;
 
 pub trait AuthReadService {
     fn verify_token(&self, token: &str) -> impl Future<Output = Result<Claim, Error>> + Send;
+
+    fn find_sessions(
+        &self,
+        user_id: i64,
+        current_session_id: i64,
+    ) -> impl Future<Output = Result<Vec<SessionResponse>, Error>> + Send;
+
+    /// Whether the user's current email is verified, which the access token may not reflect yet
+    fn is_email_verified(&self, user_id: i64) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Permissions the user's roles grant right now, which the access token may not reflect yet
+    fn find_permissions(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = Result<Vec<Permission>, Error>> + Send;
+
+    fn jwks(&self) -> &JwkSet;
 }
 
-#[derive(Clone)]
-pub struct AuthReadServiceImpl {
-    config: Arc<Config>,
+pub struct AuthReadServiceImpl<S, U, R, W>
+where
+    S: SessionReadRepo + Send + Sync + 'static,
+    U: UserReadRepo + Send + Sync + 'static,
+    R: RoleReadRepo + Send + Sync + 'static,
+    W: SessionWriteRepo + Send + Sync + 'static,
+{
+    session_read_repo: Arc<S>,
+    user_read_repo: Arc<U>,
+    role_read_repo: Arc<R>,
+    session_write_repo: Arc<W>,
+    session_cache: Arc<SessionCache>,
+    key_set: Arc<KeySet>,
 }
 
-impl AuthReadServiceImpl {
-    pub fn new(config: Arc<Config>) -> Self {
-        Self { config }
+impl<S, U, R, W> AuthReadServiceImpl<S, U, R, W>
+where
+    S: SessionReadRepo + Send + Sync + 'static,
+    U: UserReadRepo + Send + Sync + 'static,
+    R: RoleReadRepo + Send + Sync + 'static,
+    W: SessionWriteRepo + Send + Sync + 'static,
+{
+    pub fn new(
+        session_read_repo: Arc<S>,
+        user_read_repo: Arc<U>,
+        role_read_repo: Arc<R>,
+        session_write_repo: Arc<W>,
+        session_cache: Arc<SessionCache>,
+        key_set: Arc<KeySet>,
+    ) -> Self {
+        Self {
+            session_read_repo,
+            user_read_repo,
+            role_read_repo,
+            session_write_repo,
+            session_cache,
+            key_set,
+        }
+    }
+
+    /// Checks the session once per cache period, and marks it as seen while at it, so the last
+    /// seen time of an active session lags at most `SESSION_CACHE_TTL` behind
+    async fn is_session_active(&self, session_id: i64) -> Result<bool, Error> {
+        if let Some(active) = self.session_cache.get(session_id) {
+            return Ok(active);
+        }
+
+        let active = self.session_write_repo.touch(session_id).await?;
+        self.session_cache.insert(session_id, active);
+
+        Ok(active)
     }
 }
 
-impl AuthReadService for AuthReadServiceImpl {
+impl<S, U, R, W> AuthReadService for AuthReadServiceImpl<S, U, R, W>
+where
+    S: SessionReadRepo + Send + Sync + 'static,
+    U: UserReadRepo + Send + Sync + 'static,
+    R: RoleReadRepo + Send + Sync + 'static,
+    W: SessionWriteRepo + Send + Sync + 'static,
+{
     async fn verify_token(&self, token: &str) -> Result<Claim, Error> {
-        let data = jsonwebtoken::decode::<Claim>(
-            &token,
-            &DecodingKey::from_secret(self.config.access_token_key_secret.as_ref()),
-            &Validation::new(Algorithm::HS256),
-        )
-        .map_err(|error| Error::UnAuthorized(error.to_string()))?;
-
-        Ok(data.claims)
+        let claim = self.key_set.decode::<Claim>(token)?;
+
+        if !self.is_session_active(claim.sid).await? {
+            return Err(Error::UnAuthorized("Session has been revoked".to_string()));
+        }
+
+        Ok(claim)
+    }
+
+    async fn find_sessions(
+        &self,
+        user_id: i64,
+        current_session_id: i64,
+    ) -> Result<Vec<SessionResponse>, Error> {
+        let sessions = self
+            .session_read_repo
+            .find_all_active_by_user_id(user_id)
+            .await?;
+
+        Ok(sessions
+            .into_iter()
+            .map(|session| SessionResponse::from(session, current_session_id))
+            .collect())
+    }
+
+    async fn is_email_verified(&self, user_id: i64) -> Result<bool, Error> {
+        Ok(self
+            .user_read_repo
+            .find_by_id(user_id)
+            .await?
+            .is_some_and(|user| user.email_verified_at.is_some()))
+    }
+
+    async fn find_permissions(&self, user_id: i64) -> Result<Vec<Permission>, Error> {
+        self.role_read_repo
+            .find_permissions_by_user_id(user_id)
+            .await
+    }
+
+    fn jwks(&self) -> &JwkSet {
+        self.key_set.jwks()
     }
 }
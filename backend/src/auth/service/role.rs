@@ -0,0 +1,136 @@
+use crate::auth::model::{Role, UserRolesResponse};
+use crate::auth::repo::{RoleReadRepo, RoleWriteRepo};
+use crate::common::model::Error;
+use crate::user::repo::UserReadRepo;
+use std::future::Future;
+use std::sync::Arc;
+
+pub trait RoleService {
+    fn find_roles(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = Result<UserRolesResponse, Error>> + Send;
+
+    fn grant(
+        &self,
+        user_id: i64,
+        role: Role,
+    ) -> impl Future<Output = Result<UserRolesResponse, Error>> + Send;
+
+    /// Grants `SUPERADMIN` to the account with the email, if it has signed up. Returns whether
+    /// the role was new to it.
+    fn seed_super_admin(&self, email: &str) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Takes a role away, except a super admin's own, so there is always one left
+    fn revoke(
+        &self,
+        actor_id: i64,
+        user_id: i64,
+        role: Role,
+    ) -> impl Future<Output = Result<UserRolesResponse, Error>> + Send;
+}
+
+pub struct RoleServiceImpl<R, W, U>
+where
+    R: RoleReadRepo + Send + Sync + 'static,
+    W: RoleWriteRepo + Send + Sync + 'static,
+    U: UserReadRepo + Send + Sync + 'static,
+{
+    role_read_repo: Arc<R>,
+    role_write_repo: Arc<W>,
+    user_read_repo: Arc<U>,
+}
+
+impl<R, W, U> RoleServiceImpl<R, W, U>
+where
+    R: RoleReadRepo + Send + Sync + 'static,
+    W: RoleWriteRepo + Send + Sync + 'static,
+    U: UserReadRepo + Send + Sync + 'static,
+{
+    pub fn new(role_read_repo: Arc<R>, role_write_repo: Arc<W>, user_read_repo: Arc<U>) -> Self {
+        Self {
+            role_read_repo,
+            role_write_repo,
+            user_read_repo,
+        }
+    }
+
+    async fn ensure_user_exists(&self, user_id: i64) -> Result<(), Error> {
+        self.user_read_repo
+            .find_by_id(user_id)
+            .await?
+            .map(|_| ())
+            .ok_or_else(|| Error::NotFound(format!("User with id {} not found", user_id)))
+    }
+}
+
+impl<R, W, U> RoleService for RoleServiceImpl<R, W, U>
+where
+    R: RoleReadRepo + Send + Sync + 'static,
+    W: RoleWriteRepo + Send + Sync + 'static,
+    U: UserReadRepo + Send + Sync + 'static,
+{
+    async fn find_roles(&self, user_id: i64) -> Result<UserRolesResponse, Error> {
+        self.ensure_user_exists(user_id).await?;
+
+        Ok(UserRolesResponse {
+            user_id,
+            roles: self.role_read_repo.find_roles_by_user_id(user_id).await?,
+            permissions: self
+                .role_read_repo
+                .find_permissions_by_user_id(user_id)
+                .await?,
+        })
+    }
+
+    async fn grant(&self, user_id: i64, role: Role) -> Result<UserRolesResponse, Error> {
+        if role == Role::User {
+            return Err(Error::BadRequest(
+                "Every user has the USER role".to_string(),
+            ));
+        }
+        self.ensure_user_exists(user_id).await?;
+
+        self.role_write_repo.grant(user_id, role).await?;
+
+        self.find_roles(user_id).await
+    }
+
+    async fn revoke(
+        &self,
+        actor_id: i64,
+        user_id: i64,
+        role: Role,
+    ) -> Result<UserRolesResponse, Error> {
+        if role == Role::User {
+            return Err(Error::BadRequest(
+                "Every user has the USER role".to_string(),
+            ));
+        }
+        if role == Role::SuperAdmin && actor_id == user_id {
+            return Err(Error::Forbidden(
+                "Super admins cannot revoke their own role".to_string(),
+            ));
+        }
+        self.ensure_user_exists(user_id).await?;
+
+        if !self.role_write_repo.revoke(user_id, role).await? {
+            return Err(Error::NotFound(format!(
+                "User does not have the {} role",
+                role.as_str()
+            )));
+        }
+
+        self.find_roles(user_id).await
+    }
+
+    async fn seed_super_admin(&self, email: &str) -> Result<bool, Error> {
+        let user = self
+            .user_read_repo
+            .find_by_email(email)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("User with email {} not found", email)))?;
+
+        self.role_write_repo.grant(user.id, Role::SuperAdmin).await
+    }
+}
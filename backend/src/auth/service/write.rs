@@ -1,80 +1,535 @@
-use crate::auth::model::{AuthResponse, Claim, SignInRequest, SignUpRequest};
+use crate::auth::cache::SessionCache;
+use crate::auth::keys::KeySet;
+use crate::auth::model::{
+    AuthResponse, ChangeEmailRequest, ChangePasswordRequest, Claim, ClientInfo,
+    CreateSessionRequest, CreateSignInAttemptRequest, EmailClaim, ForgotPasswordRequest,
+    MfaChallengeResponse, MfaClaim, OAuthSignInRequest, RefreshClaim, RefreshTokenRequest,
+    ResetPasswordRequest, SignInFailure, SignInMfaRequest, SignInRequest, SignInResponse,
+    SignUpRequest, VerifyEmailRequest,
+};
+use crate::auth::repo::{
+    PasswordResetWriteRepo, RoleReadRepo, SessionWriteRepo, SignInAttemptWriteRepo,
+};
+use crate::auth::service::{MfaService, OAuthService};
+use crate::auth::throttle::{Account, SignInThrottle};
 use crate::common::config::Config;
+use crate::common::database::{after_commit, UnitOfWork};
+use crate::common::mailer::{Mail, Mailer};
 use crate::common::model::Error;
-use crate::user::model::CreateUserRequest;
+use crate::user::model::{CreateUserRequest, User, UserResponse};
 use crate::user::repo::UserReadRepo;
 use crate::user::repo::UserWriteRepo;
-use jsonwebtoken::Algorithm;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::DateTime;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::future::Future;
 use std::ops::Add;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use tracing::warn;
 use validator::Validate;
 
+/// `EmailClaim::purpose` of email verification tokens
+const VERIFY_EMAIL_PURPOSE: &str = "verify_email";
+
+/// `MfaClaim::purpose` of sign in challenge tokens
+const MFA_PURPOSE: &str = "mfa";
+
+/// How long the second factor can be entered after the password
+const MFA_CHALLENGE_TTL_MINUTES: i64 = 5;
+
+/// How long a mailed password reset link stays valid
+const PASSWORD_RESET_TTL_MINUTES: i64 = 60;
+
+/// Error of every failed password check, so it does not tell whether the account exists
+const INVALID_CREDENTIALS: &str = "Invalid username or password";
+
 pub trait AuthWriteService {
     fn sign_up(
         &self,
         req: SignUpRequest,
+        client: ClientInfo,
     ) -> impl Future<Output = Result<AuthResponse, Error>> + Send;
 
+    /// Checks the password and, unless the user has a second factor enabled, signs in
     fn sign_in(
         &self,
         req: SignInRequest,
+        client: ClientInfo,
+    ) -> impl Future<Output = Result<SignInResponse, Error>> + Send;
+
+    /// Signs in with the code an identity provider redirected back with, creating the account
+    /// on first sign in. A second factor is challenged just like on a password sign in.
+    fn sign_in_oauth(
+        &self,
+        provider: &str,
+        req: OAuthSignInRequest,
+        client: ClientInfo,
+    ) -> impl Future<Output = Result<SignInResponse, Error>> + Send;
+
+    /// Completes a sign in challenged for the second factor
+    fn sign_in_mfa(
+        &self,
+        req: SignInMfaRequest,
+        client: ClientInfo,
     ) -> impl Future<Output = Result<AuthResponse, Error>> + Send;
+
+    fn refresh(
+        &self,
+        req: RefreshTokenRequest,
+    ) -> impl Future<Output = Result<AuthResponse, Error>> + Send;
+
+    fn sign_out(&self, req: RefreshTokenRequest) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Signs a session of the user out remotely, e.g. one of a lost device
+    fn revoke_session(
+        &self,
+        user_id: i64,
+        session_id: i64,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Confirms the email address a verification token was mailed to
+    fn verify_email(
+        &self,
+        req: VerifyEmailRequest,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn resend_verification(&self, user_id: i64) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Mails a password reset link if an account uses the email. Succeeds either way and looks
+    /// the account up only after responding, so neither the response nor its timing tells which
+    /// emails have an account. Throttled per email and per IP.
+    fn forgot_password(
+        &self,
+        req: ForgotPasswordRequest,
+        client: ClientInfo,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Sets a new password and signs the user out everywhere
+    fn reset_password(
+        &self,
+        req: ResetPasswordRequest,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Signs the user out of every session, e.g. when an operator suspends the account. Inside a
+    /// unit of work the cached sessions are dropped only once it commits.
+    fn revoke_all_sessions(&self, user_id: i64) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Replaces the password with a random one, signs the user out everywhere and mails them a
+    /// link to choose a new one. `audit` runs in the same transaction, the mail is sent once it
+    /// has committed.
+    fn force_password_reset<F>(
+        &self,
+        user_id: i64,
+        audit: F,
+    ) -> impl Future<Output = Result<(), Error>> + Send
+    where
+        F: Future<Output = Result<(), Error>> + Send;
+
+    /// Sets a new password after checking the current one, and signs every other session out
+    fn change_password(
+        &self,
+        user_id: i64,
+        session_id: i64,
+        req: ChangePasswordRequest,
+        client: ClientInfo,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Sets a new email after checking the password, and mails a link to verify it
+    fn change_email(
+        &self,
+        user_id: i64,
+        req: ChangeEmailRequest,
+        client: ClientInfo,
+    ) -> impl Future<Output = Result<UserResponse, Error>> + Send;
 }
 
-pub struct AuthWriteServiceImpl<W, R>
+pub struct AuthWriteServiceImpl<W, R, S, P, A, G, F, O, M, U>
 where
     W: UserWriteRepo + Send + Sync + 'static,
     R: UserReadRepo + Send + Sync + 'static,
+    S: SessionWriteRepo + Send + Sync + 'static,
+    P: PasswordResetWriteRepo + Send + Sync + 'static,
+    A: SignInAttemptWriteRepo + Send + Sync + 'static,
+    G: RoleReadRepo + Send + Sync + 'static,
+    F: MfaService + Send + Sync + 'static,
+    O: OAuthService + Send + Sync + 'static,
+    M: Mailer + Send + Sync + 'static,
+    U: UnitOfWork + Send + Sync + 'static,
 {
     user_write_repo: Arc<W>,
     user_read_repo: Arc<R>,
+    session_write_repo: Arc<S>,
+    password_reset_write_repo: Arc<P>,
+    sign_in_attempt_write_repo: Arc<A>,
+    role_read_repo: Arc<G>,
+    mfa_service: Arc<F>,
+    oauth_service: Arc<O>,
+    mailer: Arc<M>,
+    unit_of_work: Arc<U>,
+    session_cache: Arc<SessionCache>,
+    sign_in_throttle: Arc<SignInThrottle>,
+    /// Counts reset mails asked for rather than failures, separately from sign ins
+    password_reset_throttle: Arc<SignInThrottle>,
+    key_set: Arc<KeySet>,
     config: Arc<Config>,
 }
 
-impl<W, R> AuthWriteServiceImpl<W, R>
+impl<W, R, S, P, A, G, F, O, M, U> AuthWriteServiceImpl<W, R, S, P, A, G, F, O, M, U>
 where
     W: UserWriteRepo + Send + Sync + 'static,
     R: UserReadRepo + Send + Sync + 'static,
+    S: SessionWriteRepo + Send + Sync + 'static,
+    P: PasswordResetWriteRepo + Send + Sync + 'static,
+    A: SignInAttemptWriteRepo + Send + Sync + 'static,
+    G: RoleReadRepo + Send + Sync + 'static,
+    F: MfaService + Send + Sync + 'static,
+    O: OAuthService + Send + Sync + 'static,
+    M: Mailer + Send + Sync + 'static,
+    U: UnitOfWork + Send + Sync + 'static,
 {
-    pub fn new(user_write_repo: Arc<W>, user_read_repo: Arc<R>, config: Arc<Config>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        user_write_repo: Arc<W>,
+        user_read_repo: Arc<R>,
+        session_write_repo: Arc<S>,
+        password_reset_write_repo: Arc<P>,
+        sign_in_attempt_write_repo: Arc<A>,
+        role_read_repo: Arc<G>,
+        mfa_service: Arc<F>,
+        oauth_service: Arc<O>,
+        mailer: Arc<M>,
+        unit_of_work: Arc<U>,
+        session_cache: Arc<SessionCache>,
+        sign_in_throttle: Arc<SignInThrottle>,
+        password_reset_throttle: Arc<SignInThrottle>,
+        key_set: Arc<KeySet>,
+        config: Arc<Config>,
+    ) -> Self {
         AuthWriteServiceImpl {
             user_write_repo,
             user_read_repo,
+            session_write_repo,
+            password_reset_write_repo,
+            sign_in_attempt_write_repo,
+            role_read_repo,
+            mfa_service,
+            oauth_service,
+            mailer,
+            unit_of_work,
+            session_cache,
+            sign_in_throttle,
+            password_reset_throttle,
+            key_set,
             config,
         }
     }
 
-    fn create_token(
+    /// Checks a password against the hash, or against a dummy hash when there is no user, so
+    /// both cases take about as long and the timing does not reveal whether an account exists
+    async fn check_password(&self, password: &str, user: Option<&User>) -> Result<bool, Error> {
+        static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+        let password = password.to_string();
+        let hash = user.map(|user| user.password.clone());
+        let does_match = tokio::task::spawn_blocking(move || {
+            let hash = hash.as_deref().unwrap_or_else(|| {
+                DUMMY_HASH.get_or_init(|| bcrypt::hash("dummy", 12).unwrap_or_default())
+            });
+            bcrypt::verify(password, hash)
+        })
+        .await
+        .map_err(|e| Error::InternalServerError(e.to_string()))?
+        .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        Ok(does_match && user.is_some())
+    }
+
+    /// Counts a failed attempt towards the lockout and writes its audit record
+    async fn record_sign_in_failure(
+        &self,
+        account: &Account,
+        user_id: Option<i64>,
+        identifier: &str,
+        client: &ClientInfo,
+        reason: SignInFailure,
+    ) {
+        if !matches!(reason, SignInFailure::LockedOut) {
+            self.sign_in_throttle
+                .record_failure(account, client.ip.as_deref());
+        }
+
+        let result = self
+            .sign_in_attempt_write_repo
+            .create(CreateSignInAttemptRequest {
+                user_id,
+                identifier: identifier.to_string(),
+                ip: client.ip.clone(),
+                user_agent: client.user_agent.clone(),
+                reason,
+            })
+            .await;
+        if let Err(error) = result {
+            warn!(%error, ?user_id, "Failed to record sign in attempt");
+        }
+    }
+
+    /// Fails while the account or the client's IP is locked out, auditing the rejected attempt
+    async fn check_throttle(
+        &self,
+        account: &Account,
+        user_id: Option<i64>,
+        identifier: &str,
+        client: &ClientInfo,
+    ) -> Result<(), Error> {
+        if let Err(error) = self.sign_in_throttle.check(account, client.ip.as_deref()) {
+            self.record_sign_in_failure(
+                account,
+                user_id,
+                identifier,
+                client,
+                SignInFailure::LockedOut,
+            )
+            .await;
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Checks the current password of a signed in user before a sensitive change. Wrong guesses
+    /// count towards the same lockout as sign in, so a stolen access token cannot be used to
+    /// brute force the password.
+    async fn reauthenticate(
         &self,
-        key: &[u8],
         user_id: i64,
-        username: &str,
-        exp: i64,
-        iat: i64,
-    ) -> Result<String, Error> {
-        let claims = serde_json::json!(Claim {
-            sub: user_id.to_string(),
-            username: String::from(username),
-            exp,
-            iat,
-        });
+        password: &str,
+        client: &ClientInfo,
+    ) -> Result<User, Error> {
+        let user = self
+            .user_read_repo
+            .find_by_id(user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+
+        let account = Account::User(user.id);
+        self.check_throttle(&account, Some(user.id), &user.username, client)
+            .await?;
+
+        if !self.check_password(password, Some(&user)).await? {
+            self.record_sign_in_failure(
+                &account,
+                Some(user.id),
+                &user.username,
+                client,
+                SignInFailure::WrongPassword,
+            )
+            .await;
+            return Err(Error::Forbidden(
+                "Current password is incorrect".to_string(),
+            ));
+        }
+
+        Ok(user)
+    }
 
+    fn create_token<T: Serialize>(&self, key: &[u8], claims: &T) -> Result<String, Error> {
         jsonwebtoken::encode(
             &jsonwebtoken::Header::new(Algorithm::HS256),
-            &claims,
+            claims,
             &jsonwebtoken::EncodingKey::from_secret(key),
         )
         .map_err(|e| Error::InternalServerError(e.to_string()))
     }
+
+    /// Issues an access token and a refresh token for the session's current generation. Roles
+    /// are read fresh, so granted or revoked roles take effect with the next refresh.
+    async fn create_tokens(
+        &self,
+        user: &User,
+        session_id: i64,
+        generation: i32,
+    ) -> Result<AuthResponse, Error> {
+        let roles = self.role_read_repo.find_roles_by_user_id(user.id).await?;
+        let permissions = self
+            .role_read_repo
+            .find_permissions_by_user_id(user.id)
+            .await?;
+
+        let access_token = self.key_set.encode(&Claim {
+            sub: user.id.to_string(),
+            username: user.username.clone(),
+            exp: chrono::Utc::now()
+                .add(chrono::Duration::minutes(10))
+                .timestamp(),
+            iat: chrono::Utc::now().timestamp(),
+            jti: format!("{}.{}", session_id, generation),
+            sid: session_id,
+            email_verified: user.email_verified_at.is_some(),
+            roles,
+            permissions,
+        })?;
+        let refresh_token = self.create_token(
+            self.config.refresh_token_key_secret.as_ref(),
+            &RefreshClaim {
+                sub: user.id.to_string(),
+                exp: chrono::Utc::now()
+                    .add(chrono::Duration::days(7))
+                    .timestamp(),
+                iat: chrono::Utc::now().timestamp(),
+                sid: session_id,
+                gen: generation,
+            },
+        )?;
+
+        Ok(AuthResponse {
+            access_token,
+            refresh_token,
+            user_id: user.id,
+        })
+    }
+
+    /// Suspended users are turned away once they proved who they are, so the reason is only
+    /// shown to the account owner
+    fn ensure_not_suspended(&self, user: &User) -> Result<(), Error> {
+        if !user.is_suspended() {
+            return Ok(());
+        }
+
+        let reason = user
+            .suspension_reason
+            .as_deref()
+            .unwrap_or("no reason given");
+        Err(match user.suspended_until {
+            Some(until) => Error::Forbidden(format!(
+                "Account is suspended until {}: {}",
+                until.to_rfc3339(),
+                reason
+            )),
+            None => Error::Forbidden(format!("Account is banned: {}", reason)),
+        })
+    }
+
+    async fn start_session(
+        &self,
+        user: &User,
+        device_name: Option<String>,
+        client: ClientInfo,
+    ) -> Result<AuthResponse, Error> {
+        self.ensure_not_suspended(user)?;
+
+        let session = self
+            .session_write_repo
+            .create(CreateSessionRequest {
+                user_id: user.id,
+                device_name,
+                user_agent: client.user_agent,
+                ip: client.ip,
+            })
+            .await?;
+
+        self.create_tokens(user, session.id, session.refresh_generation)
+            .await
+    }
+
+    /// Challenge token to complete the sign in with the second factor
+    fn create_mfa_challenge(
+        &self,
+        user: &User,
+        device_name: Option<String>,
+    ) -> Result<SignInResponse, Error> {
+        self.ensure_not_suspended(user)?;
+
+        let mut jti = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut jti);
+        let mfa_token = self.create_token(
+            self.config.mfa_token_key_secret.as_ref(),
+            &MfaClaim {
+                sub: user.id.to_string(),
+                exp: chrono::Utc::now()
+                    .add(chrono::Duration::minutes(MFA_CHALLENGE_TTL_MINUTES))
+                    .timestamp(),
+                iat: chrono::Utc::now().timestamp(),
+                jti: hex::encode(jti),
+                purpose: MFA_PURPOSE.to_string(),
+                device_name,
+            },
+        )?;
+
+        Ok(SignInResponse::MfaRequired(MfaChallengeResponse {
+            mfa_required: true,
+            mfa_token,
+        }))
+    }
+
+    async fn revoke(&self, session_id: i64) -> Result<(), Error> {
+        self.session_write_repo.revoke(session_id).await?;
+        self.session_cache.insert(session_id, false);
+
+        Ok(())
+    }
+
+    fn verify_token<T: DeserializeOwned>(&self, key: &[u8], token: &str) -> Result<T, Error> {
+        let data = jsonwebtoken::decode::<T>(
+            token,
+            &DecodingKey::from_secret(key),
+            &Validation::new(Algorithm::HS256),
+        )
+        .map_err(|error| Error::UnAuthorized(error.to_string()))?;
+
+        Ok(data.claims)
+    }
+
+    fn verify_refresh_token(&self, token: &str) -> Result<RefreshClaim, Error> {
+        self.verify_token(self.config.refresh_token_key_secret.as_ref(), token)
+    }
+
+    async fn send_verification_mail(&self, user: &User) -> Result<(), Error> {
+        let token = self.create_token(
+            self.config.email_token_key_secret.as_ref(),
+            &EmailClaim {
+                sub: user.id.to_string(),
+                exp: chrono::Utc::now()
+                    .add(chrono::Duration::days(1))
+                    .timestamp(),
+                iat: chrono::Utc::now().timestamp(),
+                email: user.email.clone(),
+                purpose: VERIFY_EMAIL_PURPOSE.to_string(),
+            },
+        )?;
+
+        self.mailer
+            .send(Mail {
+                to: user.email.clone(),
+                subject: "Verify your email".to_string(),
+                body: format!(
+                    "Hi {},\n\nOpen the link below to verify your email, it expires in 24 hours.\n\n{}/verify_email?token={}\n",
+                    user.name, self.config.app_url, token
+                ),
+            })
+            .await
+    }
 }
 
-impl<W, R> AuthWriteService for AuthWriteServiceImpl<W, R>
+impl<W, R, S, P, A, G, F, O, M, U> AuthWriteService
+    for AuthWriteServiceImpl<W, R, S, P, A, G, F, O, M, U>
 where
     W: UserWriteRepo + Send + Sync,
     R: UserReadRepo + Send + Sync,
+    S: SessionWriteRepo + Send + Sync,
+    P: PasswordResetWriteRepo + Send + Sync,
+    A: SignInAttemptWriteRepo + Send + Sync,
+    G: RoleReadRepo + Send + Sync,
+    F: MfaService + Send + Sync,
+    O: OAuthService + Send + Sync,
+    M: Mailer + Send + Sync,
+    U: UnitOfWork + Send + Sync,
 {
-    async fn sign_up(&self, req: SignUpRequest) -> Result<AuthResponse, Error> {
+    async fn sign_up(&self, req: SignUpRequest, client: ClientInfo) -> Result<AuthResponse, Error> {
         req.validate()
             .map_err(|errors| Error::BadRequest(errors.to_string()))?;
 
@@ -90,9 +545,9 @@ where
             return Err(Error::Conflict("Username already exists".to_string()));
         }
 
-        let password = bcrypt::hash(&req.password, 12)
-            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+        let password = hash_password(req.password.clone()).await?;
 
+        let device_name = req.device_name;
         let req = CreateUserRequest {
             username: req.username,
             email: req.email,
@@ -103,68 +558,442 @@ where
 
         let user = self.user_write_repo.create(req).await?;
 
-        let access_token = self.create_token(
-            self.config.access_token_key_secret.as_ref(),
-            user.id,
-            &user.username,
-            chrono::Utc::now()
-                .add(chrono::Duration::minutes(10))
-                .timestamp(),
-            chrono::Utc::now().timestamp(),
-        )?;
-        let refresh_token = self.create_token(
-            self.config.refresh_token_key_secret.as_ref(),
-            user.id,
-            &user.username,
-            chrono::Utc::now()
-                .add(chrono::Duration::days(7))
-                .timestamp(),
-            chrono::Utc::now().timestamp(),
-        )?;
+        // The account is usable without the mail, it can be resent later
+        if let Err(error) = self.send_verification_mail(&user).await {
+            warn!(%error, user_id = user.id, "Failed to send verification mail");
+        }
 
-        Ok(AuthResponse {
-            access_token,
-            refresh_token,
-            user_id: user.id,
-        })
+        self.start_session(&user, device_name, client).await
     }
 
-    async fn sign_in(&self, req: SignInRequest) -> Result<AuthResponse, Error> {
+    async fn sign_in(
+        &self,
+        req: SignInRequest,
+        client: ClientInfo,
+    ) -> Result<SignInResponse, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+
         let user = self
             .user_read_repo
             .find_by_username_or_email(&req.username)
+            .await?;
+        // Failures count per account, whichever of its username or email was given
+        let account = match &user {
+            Some(user) => Account::User(user.id),
+            None => Account::identifier(&req.username),
+        };
+        let user_id = user.as_ref().map(|user| user.id);
+        self.check_throttle(&account, user_id, &req.username, &client)
+            .await?;
+
+        if !self.check_password(&req.password, user.as_ref()).await? {
+            let reason = match user {
+                Some(_) => SignInFailure::WrongPassword,
+                None => SignInFailure::UnknownUser,
+            };
+            self.record_sign_in_failure(&account, user_id, &req.username, &client, reason)
+                .await;
+            return Err(Error::UnAuthorized(INVALID_CREDENTIALS.to_string()));
+        }
+        let user = user.ok_or_else(|| Error::UnAuthorized(INVALID_CREDENTIALS.to_string()))?;
+
+        if self.mfa_service.is_enabled(user.id).await? {
+            // The password was right, but the lockout lifts only once the second factor is too
+            return self.create_mfa_challenge(&user, req.device_name);
+        }
+
+        self.sign_in_throttle.record_success(&account);
+
+        self.start_session(&user, req.device_name, client)
+            .await
+            .map(SignInResponse::Authenticated)
+    }
+
+    async fn sign_in_oauth(
+        &self,
+        provider: &str,
+        req: OAuthSignInRequest,
+        client: ClientInfo,
+    ) -> Result<SignInResponse, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+
+        let user = self.oauth_service.authenticate(provider, &req).await?;
+
+        if self.mfa_service.is_enabled(user.id).await? {
+            return self.create_mfa_challenge(&user, req.device_name);
+        }
+
+        self.start_session(&user, req.device_name, client)
+            .await
+            .map(SignInResponse::Authenticated)
+    }
+
+    async fn sign_in_mfa(
+        &self,
+        req: SignInMfaRequest,
+        client: ClientInfo,
+    ) -> Result<AuthResponse, Error> {
+        let claim: MfaClaim =
+            self.verify_token(self.config.mfa_token_key_secret.as_ref(), &req.mfa_token)?;
+        if claim.purpose != MFA_PURPOSE {
+            return Err(Error::UnAuthorized("Invalid challenge token".to_string()));
+        }
+
+        let user_id = claim
+            .sub
+            .parse::<i64>()
+            .map_err(|_| Error::UnAuthorized("Invalid token subject".to_string()))?;
+        let user = self
+            .user_read_repo
+            .find_by_id(user_id)
+            .await?
+            .ok_or_else(|| Error::UnAuthorized("User not found".to_string()))?;
+
+        let account = Account::User(user.id);
+        self.check_throttle(&account, Some(user.id), &user.username, &client)
+            .await?;
+        match self.mfa_service.verify(user.id, &req.code).await {
+            Err(Error::UnAuthorized(message)) => {
+                self.record_sign_in_failure(
+                    &account,
+                    Some(user.id),
+                    &user.username,
+                    &client,
+                    SignInFailure::WrongCode,
+                )
+                .await;
+                return Err(Error::UnAuthorized(message));
+            }
+            result => result?,
+        }
+        self.sign_in_throttle.record_success(&account);
+
+        let expires_at = DateTime::from_timestamp(claim.exp, 0)
+            .ok_or_else(|| Error::UnAuthorized("Invalid challenge token".to_string()))?;
+        self.mfa_service
+            .use_challenge(&claim.jti, user.id, expires_at)
+            .await?;
+
+        self.start_session(&user, claim.device_name, client).await
+    }
+
+    async fn refresh(&self, req: RefreshTokenRequest) -> Result<AuthResponse, Error> {
+        let claim = self.verify_refresh_token(&req.refresh_token)?;
+
+        let session = match self.session_write_repo.rotate(claim.sid, claim.gen).await? {
+            Some(session) => session,
+            None => {
+                // Either the session is gone already, or an older refresh token of the family
+                // was presented again. Assume it was stolen and revoke the whole family.
+                self.revoke(claim.sid).await?;
+                return Err(Error::UnAuthorized(
+                    "Refresh token is no longer valid, please sign in again".to_string(),
+                ));
+            }
+        };
+
+        let user_id = claim
+            .sub
+            .parse::<i64>()
+            .map_err(|_| Error::UnAuthorized("Invalid token subject".to_string()))?;
+        let user = self
+            .user_read_repo
+            .find_by_id(user_id)
+            .await?
+            .ok_or_else(|| Error::UnAuthorized("User not found".to_string()))?;
+        if let Err(error) = self.ensure_not_suspended(&user) {
+            self.revoke(session.id).await?;
+            return Err(error);
+        }
+
+        self.create_tokens(&user, session.id, session.refresh_generation)
+            .await
+    }
+
+    async fn sign_out(&self, req: RefreshTokenRequest) -> Result<(), Error> {
+        let claim = self.verify_refresh_token(&req.refresh_token)?;
+
+        self.revoke(claim.sid).await
+    }
+
+    async fn revoke_session(&self, user_id: i64, session_id: i64) -> Result<(), Error> {
+        if !self
+            .session_write_repo
+            .revoke_by_user_id(user_id, session_id)
+            .await?
+        {
+            return Err(Error::NotFound("Session not found".to_string()));
+        }
+        self.session_cache.insert(session_id, false);
+
+        Ok(())
+    }
+
+    async fn verify_email(&self, req: VerifyEmailRequest) -> Result<(), Error> {
+        let claim: EmailClaim = self
+            .verify_token(self.config.email_token_key_secret.as_ref(), &req.token)
+            .map_err(|_| {
+                Error::BadRequest("Verification link is invalid or expired".to_string())
+            })?;
+        if claim.purpose != VERIFY_EMAIL_PURPOSE {
+            return Err(Error::BadRequest(
+                "Verification link is invalid or expired".to_string(),
+            ));
+        }
+
+        let user_id = claim
+            .sub
+            .parse::<i64>()
+            .map_err(|_| Error::BadRequest("Invalid token subject".to_string()))?;
+        if !self
+            .user_write_repo
+            .verify_email(user_id, &claim.email)
+            .await?
+        {
+            return Err(Error::Conflict(
+                "Verification link has already been used".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn resend_verification(&self, user_id: i64) -> Result<(), Error> {
+        let user = self
+            .user_read_repo
+            .find_by_id(user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+        if user.email_verified_at.is_some() {
+            return Err(Error::Conflict("Email is already verified".to_string()));
+        }
+
+        self.send_verification_mail(&user).await
+    }
+
+    async fn forgot_password(
+        &self,
+        req: ForgotPasswordRequest,
+        client: ClientInfo,
+    ) -> Result<(), Error> {
+        let account = Account::identifier(&req.email);
+        let ip = client.ip.as_deref();
+        self.password_reset_throttle.check(&account, ip)?;
+        self.password_reset_throttle.record_failure(&account, ip);
+
+        let user_read_repo = Arc::clone(&self.user_read_repo);
+        let password_reset_write_repo = Arc::clone(&self.password_reset_write_repo);
+        let mailer = Arc::clone(&self.mailer);
+        let config = Arc::clone(&self.config);
+        tokio::spawn(async move {
+            let user = match user_read_repo.find_by_email(&req.email).await {
+                Ok(Some(user)) => user,
+                Ok(None) => return,
+                Err(error) => {
+                    warn!(%error, "Failed to look up the account of a password reset");
+                    return;
+                }
+            };
+
+            let result = match create_password_reset(&*password_reset_write_repo, user.id).await {
+                Ok(token) => send_password_reset_mail(&*mailer, &config, &user, &token).await,
+                Err(error) => Err(error),
+            };
+            if let Err(error) = result {
+                warn!(%error, user_id = user.id, "Failed to send password reset mail");
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn reset_password(&self, req: ResetPasswordRequest) -> Result<(), Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+
+        // Hashed up front so the transaction is not held open for it
+        let password = hash_password(req.password.clone()).await?;
+        self.unit_of_work
+            .run(async {
+                let user_id = self
+                    .password_reset_write_repo
+                    .consume(&hash_reset_token(&req.token))
+                    .await?
+                    .ok_or_else(|| {
+                        Error::BadRequest("Reset link is invalid or expired".to_string())
+                    })?;
+                self.user_write_repo
+                    .update_password(user_id, &password)
+                    .await?;
+
+                self.revoke_all_sessions(user_id).await
+            })
+            .await
+    }
+
+    async fn revoke_all_sessions(&self, user_id: i64) -> Result<(), Error> {
+        let session_ids = self
+            .session_write_repo
+            .revoke_all_by_user_id(user_id, None)
+            .await?;
+        let session_cache = Arc::clone(&self.session_cache);
+        after_commit(move || {
+            for session_id in session_ids {
+                session_cache.insert(session_id, false);
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn force_password_reset<T>(&self, user_id: i64, audit: T) -> Result<(), Error>
+    where
+        T: Future<Output = Result<(), Error>> + Send,
+    {
+        let user = self
+            .user_read_repo
+            .find_by_id(user_id)
             .await?
             .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
 
-        let does_match = bcrypt::verify(&req.password, &user.password)
-            .map_err(|e| Error::InternalServerError(e.to_string()))?;
-        if !does_match {
-            return Err(Error::BadRequest("Password does not match".to_string()));
+        let mut password = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut password);
+        let password = hash_password(URL_SAFE_NO_PAD.encode(password)).await?;
+        let token = self
+            .unit_of_work
+            .run(async {
+                self.user_write_repo
+                    .update_password(user.id, &password)
+                    .await?;
+                self.revoke_all_sessions(user.id).await?;
+                let token =
+                    create_password_reset(&*self.password_reset_write_repo, user.id).await?;
+                audit.await?;
+
+                Ok(token)
+            })
+            .await?;
+
+        send_password_reset_mail(&*self.mailer, &self.config, &user, &token).await
+    }
+
+    async fn change_password(
+        &self,
+        user_id: i64,
+        session_id: i64,
+        req: ChangePasswordRequest,
+        client: ClientInfo,
+    ) -> Result<(), Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+
+        let user = self
+            .reauthenticate(user_id, &req.current_password, &client)
+            .await?;
+
+        let password = hash_password(req.new_password.clone()).await?;
+        self.user_write_repo
+            .update_password(user.id, &password)
+            .await?;
+
+        let session_ids = self
+            .session_write_repo
+            .revoke_all_by_user_id(user.id, Some(session_id))
+            .await?;
+        for session_id in session_ids {
+            self.session_cache.insert(session_id, false);
         }
 
-        let access_token = self.create_token(
-            self.config.access_token_key_secret.as_ref(),
-            user.id,
-            &user.username,
-            chrono::Utc::now()
-                .add(chrono::Duration::minutes(10))
-                .timestamp(),
-            chrono::Utc::now().timestamp(),
-        )?;
-        let refresh_token = self.create_token(
-            self.config.refresh_token_key_secret.as_ref(),
-            user.id,
-            &user.username,
-            chrono::Utc::now()
-                .add(chrono::Duration::days(7))
-                .timestamp(),
-            chrono::Utc::now().timestamp(),
-        )?;
+        Ok(())
+    }
 
-        Ok(AuthResponse {
-            access_token,
-            refresh_token,
-            user_id: user.id,
-        })
+    async fn change_email(
+        &self,
+        user_id: i64,
+        req: ChangeEmailRequest,
+        client: ClientInfo,
+    ) -> Result<UserResponse, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+
+        let user = self.reauthenticate(user_id, &req.password, &client).await?;
+        if req.email == user.email {
+            return Err(Error::BadRequest(
+                "New email is the same as the current one".to_string(),
+            ));
+        }
+        if self.user_read_repo.exists_by_email(&req.email).await? {
+            return Err(Error::Conflict("Email already exists".to_string()));
+        }
+
+        let user = self
+            .user_write_repo
+            .update_email(user.id, &req.email)
+            .await?;
+
+        if let Err(error) = self.send_verification_mail(&user).await {
+            warn!(%error, user_id = user.id, "Failed to send verification mail");
+        }
+
+        Ok(UserResponse::from(user))
     }
 }
+
+/// bcrypt is slow on purpose, so it runs on the blocking pool instead of stalling the executor
+pub(crate) async fn hash_password(password: String) -> Result<String, Error> {
+    tokio::task::spawn_blocking(move || bcrypt::hash(password, 12))
+        .await
+        .map_err(|e| Error::InternalServerError(e.to_string()))?
+        .map_err(|e| Error::InternalServerError(e.to_string()))
+}
+
+/// SHA-256 of a password reset token, the form it is stored in
+fn hash_reset_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Stores a new password reset for the user, returning the token to mail them
+async fn create_password_reset<P>(
+    password_reset_write_repo: &P,
+    user_id: i64,
+) -> Result<String, Error>
+where
+    P: PasswordResetWriteRepo + Send + Sync + 'static,
+{
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = URL_SAFE_NO_PAD.encode(bytes);
+
+    password_reset_write_repo
+        .create(
+            user_id,
+            &hash_reset_token(&token),
+            chrono::Utc::now().add(chrono::Duration::minutes(PASSWORD_RESET_TTL_MINUTES)),
+        )
+        .await?;
+
+    Ok(token)
+}
+
+async fn send_password_reset_mail<M>(
+    mailer: &M,
+    config: &Config,
+    user: &User,
+    token: &str,
+) -> Result<(), Error>
+where
+    M: Mailer + Send + Sync + 'static,
+{
+    mailer
+        .send(Mail {
+            to: user.email.clone(),
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Hi {},\n\nOpen the link below to choose a new password, it expires in {} minutes. If you did not ask for this, you can ignore this mail.\n\n{}/reset_password?token={}\n",
+                user.name, PASSWORD_RESET_TTL_MINUTES, config.app_url, token
+            ),
+        })
+        .await
+}
@@ -0,0 +1,254 @@
+use crate::common::model::Error;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Failures an account may have before it is locked out
+const ACCOUNT_FREE_FAILURES: u32 = 5;
+/// Failures an IP may have, higher than per account since many users can share an IP
+const IP_FREE_FAILURES: u32 = 20;
+/// Lockout after the first failure past the free ones, doubling with every further failure
+const BASE_LOCKOUT: Duration = Duration::from_secs(1);
+const MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+/// Counters are forgotten once there was no failure for this long
+const RESET_AFTER: Duration = Duration::from_secs(60 * 60);
+/// Counters kept at most, the least recently failed ones are evicted beyond it
+const CAPACITY: usize = 100_000;
+
+/// Whose failures count towards a lockout
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Account {
+    /// A signed up user, however they identified themselves
+    User(i64),
+    /// A username or email as it was given, e.g. one no account uses
+    Identifier(String),
+}
+
+impl Account {
+    pub fn identifier(identifier: &str) -> Self {
+        Account::Identifier(identifier.to_lowercase())
+    }
+}
+
+/// Counters are keyed by kind, so e.g. the identifier `user:5` never shares one with user 5
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Account(Account),
+    Ip(String),
+}
+
+struct Failures {
+    count: u32,
+    last_failure_at: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Counts failed sign in attempts per account and per IP and locks them out with exponential
+/// backoff. Counters live in memory, so with several instances each enforces its own limit.
+pub struct SignInThrottle {
+    entries: Mutex<LruCache<Key, Failures>>,
+}
+
+impl SignInThrottle {
+    pub fn new() -> Self {
+        Self::with_capacity(CAPACITY)
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(
+                NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN),
+            )),
+        }
+    }
+
+    /// Fails with `TooManyRequests` while the account or the IP is locked out
+    pub fn check(&self, account: &Account, ip: Option<&str>) -> Result<(), Error> {
+        self.check_at(account, ip, Instant::now())
+    }
+
+    pub fn record_failure(&self, account: &Account, ip: Option<&str>) {
+        self.record_failure_at(account, ip, Instant::now())
+    }
+
+    /// Forgets the account's failures, the IP's stay so spraying many accounts is still slowed
+    pub fn record_success(&self, account: &Account) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+
+        entries.pop(&Key::Account(account.clone()));
+    }
+
+    fn check_at(&self, account: &Account, ip: Option<&str>, now: Instant) -> Result<(), Error> {
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let locked_until = keys(account, ip)
+            .iter()
+            .filter_map(|(key, _)| entries.peek(key))
+            .filter_map(|failures| failures.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .max();
+
+        match locked_until {
+            Some(locked_until) => Err(Error::TooManyRequests(format!(
+                "Too many failed attempts, try again in {} seconds",
+                (locked_until - now).as_secs().max(1)
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn record_failure_at(&self, account: &Account, ip: Option<&str>, now: Instant) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+
+        for (key, free_failures) in keys(account, ip) {
+            let failures = entries.get_or_insert_mut(key, || Failures {
+                count: 0,
+                last_failure_at: now,
+                locked_until: None,
+            });
+            if now - failures.last_failure_at >= RESET_AFTER {
+                failures.count = 0;
+            }
+
+            failures.count += 1;
+            failures.last_failure_at = now;
+            if failures.count > free_failures {
+                failures.locked_until = Some(now + lockout(failures.count - free_failures));
+            }
+        }
+    }
+}
+
+/// Counters an attempt is checked against, with the failures each allows
+fn keys(account: &Account, ip: Option<&str>) -> Vec<(Key, u32)> {
+    let mut keys = vec![(Key::Account(account.clone()), ACCOUNT_FREE_FAILURES)];
+    if let Some(ip) = ip {
+        keys.push((Key::Ip(ip.to_string()), IP_FREE_FAILURES));
+    }
+
+    keys
+}
+
+fn lockout(excess_failures: u32) -> Duration {
+    BASE_LOCKOUT
+        .checked_mul(2u32.saturating_pow(excess_failures - 1))
+        .map_or(MAX_LOCKOUT, |lockout| lockout.min(MAX_LOCKOUT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: Option<&str> = Some("198.51.100.1");
+
+    fn fail(
+        throttle: &SignInThrottle,
+        account: &Account,
+        ip: Option<&str>,
+        times: u32,
+        now: Instant,
+    ) {
+        for _ in 0..times {
+            throttle.record_failure_at(account, ip, now);
+        }
+    }
+
+    #[test]
+    fn accounts_lock_out_after_the_free_failures() {
+        let throttle = SignInThrottle::new();
+        let account = Account::User(1);
+        let now = Instant::now();
+
+        fail(&throttle, &account, IP, ACCOUNT_FREE_FAILURES, now);
+        assert!(throttle.check_at(&account, IP, now).is_ok());
+
+        fail(&throttle, &account, IP, 1, now);
+        assert!(throttle.check_at(&account, IP, now).is_err());
+        assert!(throttle.check_at(&account, IP, now + BASE_LOCKOUT).is_ok());
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_maximum() {
+        assert_eq!(lockout(1), BASE_LOCKOUT);
+        assert_eq!(lockout(3), BASE_LOCKOUT * 4);
+        assert_eq!(lockout(64), MAX_LOCKOUT);
+    }
+
+    #[test]
+    fn failures_are_forgotten_after_a_quiet_period() {
+        let throttle = SignInThrottle::new();
+        let account = Account::User(1);
+        let now = Instant::now();
+
+        fail(&throttle, &account, None, ACCOUNT_FREE_FAILURES, now);
+        fail(&throttle, &account, None, 1, now + RESET_AFTER);
+
+        assert!(throttle.check_at(&account, None, now + RESET_AFTER).is_ok());
+    }
+
+    #[test]
+    fn ips_lock_out_across_accounts() {
+        let throttle = SignInThrottle::new();
+        let now = Instant::now();
+        for user_id in 0..=IP_FREE_FAILURES as i64 {
+            fail(&throttle, &Account::User(user_id), IP, 1, now);
+        }
+
+        assert!(throttle.check_at(&Account::User(1000), IP, now).is_err());
+        assert!(throttle.check_at(&Account::User(1000), None, now).is_ok());
+    }
+
+    #[test]
+    fn success_forgets_only_the_account() {
+        let throttle = SignInThrottle::new();
+        let account = Account::User(1);
+        let now = Instant::now();
+        fail(&throttle, &account, IP, IP_FREE_FAILURES + 1, now);
+
+        throttle.record_success(&account);
+
+        assert!(throttle.check_at(&account, None, now).is_ok());
+        assert!(throttle.check_at(&account, IP, now).is_err());
+    }
+
+    #[test]
+    fn identifiers_do_not_share_counters_with_users() {
+        let throttle = SignInThrottle::new();
+        let now = Instant::now();
+
+        fail(
+            &throttle,
+            &Account::identifier("user:5"),
+            None,
+            ACCOUNT_FREE_FAILURES + 1,
+            now,
+        );
+
+        assert!(throttle
+            .check_at(&Account::identifier("USER:5"), None, now)
+            .is_err());
+        assert!(throttle.check_at(&Account::User(5), None, now).is_ok());
+    }
+
+    #[test]
+    fn counters_are_bounded() {
+        let throttle = SignInThrottle::with_capacity(10);
+        let now = Instant::now();
+        fail(
+            &throttle,
+            &Account::User(0),
+            None,
+            ACCOUNT_FREE_FAILURES + 1,
+            now,
+        );
+
+        for user_id in 1..=10 {
+            fail(&throttle, &Account::User(user_id), None, 1, now);
+        }
+
+        let entries = throttle.entries.lock().unwrap();
+        assert_eq!(entries.len(), 10);
+        assert!(!entries.contains(&Key::Account(Account::User(0))));
+    }
+}
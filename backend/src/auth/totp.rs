@@ -0,0 +1,174 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+/// Seconds a code is valid for
+const STEP_SECONDS: i64 = 30;
+/// Steps before and after the current one that are still accepted, to allow for clock drift
+const SKEW_STEPS: i64 = 1;
+const DIGITS: u32 = 6;
+const SECRET_BYTES: usize = 20;
+
+/// A fresh random secret, base32 encoded as authenticator apps expect it
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut secret);
+
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// `otpauth://` URI authenticator apps enroll from, usually shown as a QR code
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &str) -> String {
+    let issuer = encode_uri_component(issuer);
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        issuer,
+        encode_uri_component(account),
+        secret,
+        issuer,
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+/// Checks `code` against the steps around `timestamp` and returns the step it matched. Callers
+/// must reject a step that was already used so a code cannot be replayed.
+pub fn verify(secret: &str, code: &str, timestamp: i64) -> Option<i64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    // Every step is compared in constant time, so timing tells neither how much of a code nor
+    // which step matched
+    let current = timestamp.div_euclid(STEP_SECONDS);
+    let mut matched = None;
+    for step in current - SKEW_STEPS..=current + SKEW_STEPS {
+        let is_match = generate(&secret, step).as_bytes().ct_eq(code.as_bytes());
+        if bool::from(is_match) && matched.is_none() {
+            matched = Some(step);
+        }
+    }
+
+    matched
+}
+
+/// RFC 6238 code of `step`, i.e. the RFC 4226 HOTP of the step counter
+fn generate(secret: &[u8], step: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+fn encode_uri_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The RFC 6238 SHA-1 test secret, "12345678901234567890" in base32
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    /// Times and codes of RFC 6238 Appendix B for SHA-1, cut to the last six of their eight
+    /// digits as the truncation takes the code modulo 10^digits
+    const RFC_VECTORS: [(i64, &str); 6] = [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1111111111, "050471"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+        (20000000000, "353130"),
+    ];
+
+    #[test]
+    fn generates_the_rfc_6238_codes() {
+        let secret = BASE32_NOPAD.decode(RFC_SECRET.as_bytes()).unwrap();
+
+        for (timestamp, code) in RFC_VECTORS {
+            assert_eq!(
+                generate(&secret, timestamp / STEP_SECONDS),
+                code,
+                "at {}",
+                timestamp
+            );
+        }
+    }
+
+    #[test]
+    fn verifies_the_rfc_6238_codes_at_their_step() {
+        for (timestamp, code) in RFC_VECTORS {
+            assert_eq!(
+                verify(RFC_SECRET, code, timestamp),
+                Some(timestamp / STEP_SECONDS)
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_codes_one_step_around_the_current_one() {
+        let (timestamp, code) = (1111111109, "081804");
+        let step = timestamp / STEP_SECONDS;
+
+        assert_eq!(
+            verify(RFC_SECRET, code, timestamp - STEP_SECONDS),
+            Some(step)
+        );
+        assert_eq!(
+            verify(RFC_SECRET, code, timestamp + STEP_SECONDS),
+            Some(step)
+        );
+        assert_eq!(verify(RFC_SECRET, code, timestamp - 2 * STEP_SECONDS), None);
+        assert_eq!(verify(RFC_SECRET, code, timestamp + 2 * STEP_SECONDS), None);
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        let (timestamp, code) = (59, "287082");
+
+        assert_eq!(
+            verify(RFC_SECRET, &format!(" {} ", code), timestamp),
+            Some(1)
+        );
+        assert_eq!(verify(RFC_SECRET, "28708", timestamp), None);
+        assert_eq!(verify(RFC_SECRET, "2870820", timestamp), None);
+        assert_eq!(verify(RFC_SECRET, "28708a", timestamp), None);
+        assert_eq!(verify("not base32!", code, timestamp), None);
+    }
+
+    #[test]
+    fn generated_secrets_decode_to_their_size() {
+        let secret = generate_secret();
+
+        assert_eq!(
+            BASE32_NOPAD.decode(secret.as_bytes()).unwrap().len(),
+            SECRET_BYTES
+        );
+    }
+}
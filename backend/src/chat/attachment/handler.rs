@@ -0,0 +1,202 @@
+use crate::auth::extractor::Auth;
+use crate::chat::attachment::model::{
+    AttachmentContent, AttachmentKind, UploadAttachmentRequest, MAX_ATTACHMENT_UPLOAD_SIZE,
+};
+use crate::chat::attachment::service::read::AttachmentReadService;
+use crate::chat::attachment::service::write::AttachmentWriteService;
+use crate::common::json::IntoApiResponse;
+use crate::common::model::Error;
+use crate::common::state::AppState;
+use axum::body::Body;
+use axum::extract::{DefaultBodyLimit, Multipart, Path};
+use axum::http::header::{
+    CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, ETAG, X_CONTENT_TYPE_OPTIONS,
+};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Uploads read and stored at the same time. Each one is held in memory whole, so this bounds
+/// uploads to about 100 MB all at once. The others wait before their body is read.
+static UPLOAD_PERMITS: Semaphore = Semaphore::const_new(4);
+
+pub struct AttachmentHandler<W, R>
+where
+    W: AttachmentWriteService + Send + Sync + 'static,
+    R: AttachmentReadService + Send + Sync + 'static,
+{
+    attachment_write_service: Arc<W>,
+    attachment_read_service: Arc<R>,
+}
+
+impl<W, R> AttachmentHandler<W, R>
+where
+    W: AttachmentWriteService + Send + Sync + 'static,
+    R: AttachmentReadService + Send + Sync + 'static,
+{
+    pub fn new(attachment_write_service: Arc<W>, attachment_read_service: Arc<R>) -> Self {
+        Self {
+            attachment_write_service,
+            attachment_read_service,
+        }
+    }
+
+    async fn upload(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        multipart: Multipart,
+    ) -> impl IntoResponse {
+        let result = async {
+            let _permit = UPLOAD_PERMITS
+                .acquire()
+                .await
+                .map_err(|e| Error::InternalServerError(e.to_string()))?;
+            let req = read_upload(conversation_id, multipart).await?;
+            self.attachment_write_service.upload(user_id, req).await
+        };
+
+        result.await.into_json()
+    }
+
+    async fn download(&self, user_id: i64, attachment_id: i64) -> Response {
+        match self
+            .attachment_read_service
+            .download(user_id, attachment_id)
+            .await
+        {
+            Ok(content) => into_file_response(content),
+            Err(error) => IntoApiResponse::<()>::into_json(error).into_response(),
+        }
+    }
+
+    pub fn create_route(handler: Arc<Self>, router: Router<AppState>) -> Router<AppState> {
+        router
+            .route(
+                "/api/conversation/:conversation_id/attachments",
+                post({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth, Path(conversation_id): Path<i64>, multipart: Multipart| async move {
+                        handler.upload(auth.user_id, conversation_id, multipart).await
+                    }
+                })
+                .layer(DefaultBodyLimit::max(MAX_ATTACHMENT_UPLOAD_SIZE)),
+            )
+            .route(
+                "/api/attachment/:attachment_id",
+                get({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth, Path(attachment_id): Path<i64>| async move {
+                        handler.download(auth.user_id, attachment_id).await
+                    }
+                }),
+            )
+    }
+}
+
+/// Reads the `file` field of a multipart upload, and the `duration_ms` field of voice notes
+async fn read_upload(
+    conversation_id: i64,
+    mut multipart: Multipart,
+) -> Result<UploadAttachmentRequest, Error> {
+    let mut file = None;
+    let mut duration_ms = None;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| Error::BadRequest(e.body_text()))?
+    {
+        match field.name() {
+            Some("file") => {
+                let file_name = field.file_name().map(str::to_string);
+                let mime_type = field.content_type().map(str::to_string);
+                let bytes = field
+                    .bytes()
+                    .await
+                    .map_err(|e| Error::BadRequest(e.body_text()))?;
+                file = Some((file_name, mime_type, Vec::from(bytes)));
+            }
+            Some("duration_ms") => {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|e| Error::BadRequest(e.body_text()))?;
+                let value = text
+                    .trim()
+                    .parse::<i32>()
+                    .map_err(|_| Error::BadRequest("Duration must be a number".to_string()))?;
+                duration_ms = Some(value);
+            }
+            _ => {}
+        }
+    }
+
+    let (file_name, mime_type, bytes) =
+        file.ok_or_else(|| Error::BadRequest("File is missing".to_string()))?;
+
+    Ok(UploadAttachmentRequest {
+        conversation_id,
+        file_name,
+        mime_type,
+        bytes,
+        duration_ms,
+    })
+}
+
+/// Images and voice notes are shown inline, other files are only offered for download so a
+/// browser never renders e.g. an uploaded HTML page
+fn into_file_response(content: AttachmentContent) -> Response {
+    let attachment = content.attachment;
+    let disposition = match attachment.kind {
+        AttachmentKind::Image | AttachmentKind::Voice => "inline",
+        AttachmentKind::File => "attachment",
+    };
+
+    (
+        [
+            (CONTENT_TYPE, attachment.mime_type),
+            (CONTENT_LENGTH, attachment.size.to_string()),
+            (
+                CONTENT_DISPOSITION,
+                content_disposition(disposition, &attachment.file_name),
+            ),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            // Attachments never change, but only their participants may see them
+            (
+                CACHE_CONTROL,
+                "private, max-age=31536000, immutable".to_string(),
+            ),
+            (ETAG, format!("\"{}\"", attachment.checksum)),
+        ],
+        Body::from_stream(content.bytes),
+    )
+        .into_response()
+}
+
+/// `Content-Disposition` with an ASCII fallback name for old clients and the exact name
+/// percent encoded, see RFC 6266
+fn content_disposition(disposition: &str, file_name: &str) -> String {
+    let fallback = file_name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    let encoded = file_name
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect::<String>();
+
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition, fallback, encoded
+    )
+}
@@ -0,0 +1,130 @@
+use crate::common::model::Error;
+use crate::common::storage::BlobStream;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::str::FromStr;
+
+/// Largest file accepted as an attachment
+pub const MAX_ATTACHMENT_SIZE: usize = 25 * 1024 * 1024;
+
+/// Body limit of attachment uploads, the file plus room for the multipart framing
+pub const MAX_ATTACHMENT_UPLOAD_SIZE: usize = MAX_ATTACHMENT_SIZE + 64 * 1024;
+
+/// Longest voice note accepted
+pub const MAX_VOICE_DURATION_MS: i32 = 15 * 60 * 1000;
+
+/// What clients show an attachment as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AttachmentKind {
+    /// A JPEG, PNG, WebP or GIF image, shown inline
+    Image,
+    /// A recording, played inline
+    Voice,
+    /// Anything else, only offered for download
+    File,
+}
+
+impl AttachmentKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttachmentKind::Image => "IMAGE",
+            AttachmentKind::Voice => "VOICE",
+            AttachmentKind::File => "FILE",
+        }
+    }
+}
+
+impl FromStr for AttachmentKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "IMAGE" => Ok(AttachmentKind::Image),
+            "VOICE" => Ok(AttachmentKind::Voice),
+            "FILE" => Ok(AttachmentKind::File),
+            _ => Err(Error::InternalServerError(format!(
+                "Unknown attachment kind {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl TryFrom<String> for AttachmentKind {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct Attachment {
+    pub id: i64,
+    pub uploader_id: i64,
+    pub conversation_id: i64,
+    /// None until a message referencing the attachment is sent
+    pub message_id: Option<i64>,
+    #[sqlx(try_from = "String")]
+    pub kind: AttachmentKind,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub storage_key: String,
+    pub checksum: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub duration_ms: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A file read from a multipart upload
+#[derive(Debug, Clone)]
+pub struct UploadAttachmentRequest {
+    pub conversation_id: i64,
+    pub file_name: Option<String>,
+    /// Content type the client declared for the file
+    pub mime_type: Option<String>,
+    pub bytes: Vec<u8>,
+    /// Length of a voice note, reported by the client as the server does not decode audio
+    pub duration_ms: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AttachmentResponse {
+    pub id: i64,
+    pub kind: AttachmentKind,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub checksum: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub duration_ms: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AttachmentResponse {
+    pub fn from(attachment: Attachment) -> Self {
+        Self {
+            id: attachment.id,
+            kind: attachment.kind,
+            file_name: attachment.file_name,
+            mime_type: attachment.mime_type,
+            size: attachment.size,
+            checksum: attachment.checksum,
+            width: attachment.width,
+            height: attachment.height,
+            duration_ms: attachment.duration_ms,
+            created_at: attachment.created_at,
+        }
+    }
+}
+
+/// An attachment's file, as downloaded by a participant
+pub struct AttachmentContent {
+    pub attachment: Attachment,
+    pub bytes: BlobStream,
+}
@@ -0,0 +1,96 @@
+use crate::chat::attachment::model::Attachment;
+use crate::common::database::connection;
+use crate::common::model::Error;
+use sqlx::{Pool, Postgres};
+use std::future::Future;
+use std::sync::Arc;
+
+pub trait AttachmentReadRepo {
+    fn find_by_id(
+        &self,
+        attachment_id: i64,
+    ) -> impl Future<Output = Result<Option<Attachment>, Error>> + Send;
+
+    /// Locks the attachments until the transaction of a unit of work running it ends
+    fn find_by_ids(
+        &self,
+        attachment_ids: &[i64],
+    ) -> impl Future<Output = Result<Vec<Attachment>, Error>> + Send;
+
+    /// Attachments of the messages, in the order they were uploaded
+    fn find_by_message_ids(
+        &self,
+        message_ids: &[i64],
+    ) -> impl Future<Output = Result<Vec<Attachment>, Error>> + Send;
+}
+
+pub struct AttachmentReadRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl AttachmentReadRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl AttachmentReadRepo for AttachmentReadRepoPg {
+    async fn find_by_id(&self, attachment_id: i64) -> Result<Option<Attachment>, Error> {
+        let query = r#"
+            SELECT
+                id, uploader_id, conversation_id, message_id, kind, file_name, mime_type, size,
+                storage_key, checksum, width, height, duration_ms, created_at
+            FROM
+                "attachment"
+            WHERE
+                id = $1
+        "#;
+
+        sqlx::query_as::<_, Attachment>(query)
+            .bind(attachment_id)
+            .fetch_optional(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn find_by_ids(&self, attachment_ids: &[i64]) -> Result<Vec<Attachment>, Error> {
+        let query = r#"
+            SELECT
+                id, uploader_id, conversation_id, message_id, kind, file_name, mime_type, size,
+                storage_key, checksum, width, height, duration_ms, created_at
+            FROM
+                "attachment"
+            WHERE
+                id = ANY($1)
+            FOR UPDATE
+        "#;
+
+        let query = sqlx::query_as::<_, Attachment>(query).bind(attachment_ids);
+
+        // Runs in the transaction of the message sent with the attachments, if there is one
+        query
+            .fetch_all(&mut *connection(&self.pool).await?)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn find_by_message_ids(&self, message_ids: &[i64]) -> Result<Vec<Attachment>, Error> {
+        let query = r#"
+            SELECT
+                id, uploader_id, conversation_id, message_id, kind, file_name, mime_type, size,
+                storage_key, checksum, width, height, duration_ms, created_at
+            FROM
+                "attachment"
+            WHERE
+                message_id = ANY($1)
+            ORDER BY
+                id ASC
+        "#;
+
+        sqlx::query_as::<_, Attachment>(query)
+            .bind(message_ids)
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
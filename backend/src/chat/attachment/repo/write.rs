@@ -0,0 +1,140 @@
+use crate::chat::attachment::model::Attachment;
+use crate::common::database::connection;
+use crate::common::model::Error;
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+use std::future::Future;
+use std::sync::Arc;
+
+pub trait AttachmentWriteRepo {
+    fn create(
+        &self,
+        attachment: &Attachment,
+    ) -> impl Future<Output = Result<Attachment, Error>> + Send;
+
+    /// Links the uploaded attachments to a message. Attachments already linked to another
+    /// message are left alone and not returned.
+    fn attach(
+        &self,
+        message_id: i64,
+        attachment_ids: &[i64],
+    ) -> impl Future<Output = Result<Vec<Attachment>, Error>> + Send;
+
+    /// Deletes up to `size` attachments uploaded before `created_before` that were never sent
+    /// with a message, returning them so their files can be removed too
+    fn delete_pending(
+        &self,
+        created_before: DateTime<Utc>,
+        size: i64,
+    ) -> impl Future<Output = Result<Vec<Attachment>, Error>> + Send;
+}
+
+pub struct AttachmentWriteRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl AttachmentWriteRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl AttachmentWriteRepo for AttachmentWriteRepoPg {
+    async fn create(&self, attachment: &Attachment) -> Result<Attachment, Error> {
+        let query = r#"
+            INSERT INTO "attachment" (
+                uploader_id, conversation_id, message_id, kind, file_name, mime_type, size,
+                storage_key, checksum, width, height, duration_ms, created_at
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13
+            )
+            RETURNING
+                id, uploader_id, conversation_id, message_id, kind, file_name, mime_type, size,
+                storage_key, checksum, width, height, duration_ms, created_at
+        "#;
+
+        sqlx::query_as::<_, Attachment>(query)
+            .bind(attachment.uploader_id)
+            .bind(attachment.conversation_id)
+            .bind(attachment.message_id)
+            .bind(attachment.kind.as_str())
+            .bind(&attachment.file_name)
+            .bind(&attachment.mime_type)
+            .bind(attachment.size)
+            .bind(&attachment.storage_key)
+            .bind(&attachment.checksum)
+            .bind(attachment.width)
+            .bind(attachment.height)
+            .bind(attachment.duration_ms)
+            .bind(attachment.created_at)
+            .fetch_one(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn attach(
+        &self,
+        message_id: i64,
+        attachment_ids: &[i64],
+    ) -> Result<Vec<Attachment>, Error> {
+        let query = r#"
+            UPDATE
+                "attachment"
+            SET
+                message_id = $1
+            WHERE
+                id = ANY($2) AND message_id IS NULL
+            RETURNING
+                id, uploader_id, conversation_id, message_id, kind, file_name, mime_type, size,
+                storage_key, checksum, width, height, duration_ms, created_at
+        "#;
+
+        let query = sqlx::query_as::<_, Attachment>(query)
+            .bind(message_id)
+            .bind(attachment_ids);
+
+        let mut attachments = query
+            .fetch_all(&mut *connection(&self.pool).await?)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+        attachments.sort_by_key(|attachment| attachment.id);
+
+        Ok(attachments)
+    }
+
+    async fn delete_pending(
+        &self,
+        created_before: DateTime<Utc>,
+        size: i64,
+    ) -> Result<Vec<Attachment>, Error> {
+        // Attachments locked by a message being sent are skipped, the next sweep sees them sent
+        let query = r#"
+            DELETE FROM
+                "attachment"
+            WHERE
+                id IN (
+                    SELECT
+                        id
+                    FROM
+                        "attachment"
+                    WHERE
+                        message_id IS NULL AND created_at < $1
+                    ORDER BY
+                        created_at ASC
+                    LIMIT
+                        $2
+                    FOR UPDATE SKIP LOCKED
+                )
+            RETURNING
+                id, uploader_id, conversation_id, message_id, kind, file_name, mime_type, size,
+                storage_key, checksum, width, height, duration_ms, created_at
+        "#;
+
+        sqlx::query_as::<_, Attachment>(query)
+            .bind(created_before)
+            .bind(size)
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
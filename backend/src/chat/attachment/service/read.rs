@@ -0,0 +1,102 @@
+use crate::chat::attachment::model::AttachmentContent;
+use crate::chat::attachment::repo::read::AttachmentReadRepo;
+use crate::chat::message::repo::read::MessageReadRepo;
+use crate::chat::participant::repo::read::ConversationParticipantReadRepo;
+use crate::common::model::Error;
+use crate::common::storage::BlobStore;
+use std::future::Future;
+use std::sync::Arc;
+
+pub trait AttachmentReadService {
+    /// The attachment's file, for participants of the conversation it was sent to. Until it is
+    /// sent only the uploader can download it.
+    fn download(
+        &self,
+        user_id: i64,
+        attachment_id: i64,
+    ) -> impl Future<Output = Result<AttachmentContent, Error>> + Send;
+}
+
+pub struct AttachmentReadServiceImpl<A, M, P, B>
+where
+    A: AttachmentReadRepo + Send + Sync + 'static,
+    M: MessageReadRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
+    B: BlobStore + Send + Sync + 'static,
+{
+    attachment_read_repo: Arc<A>,
+    message_read_repo: Arc<M>,
+    participant_read_repo: Arc<P>,
+    blob_store: Arc<B>,
+}
+
+impl<A, M, P, B> AttachmentReadServiceImpl<A, M, P, B>
+where
+    A: AttachmentReadRepo + Send + Sync + 'static,
+    M: MessageReadRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
+    B: BlobStore + Send + Sync + 'static,
+{
+    pub fn new(
+        attachment_read_repo: Arc<A>,
+        message_read_repo: Arc<M>,
+        participant_read_repo: Arc<P>,
+        blob_store: Arc<B>,
+    ) -> Self {
+        Self {
+            attachment_read_repo,
+            message_read_repo,
+            participant_read_repo,
+            blob_store,
+        }
+    }
+}
+
+impl<A, M, P, B> AttachmentReadService for AttachmentReadServiceImpl<A, M, P, B>
+where
+    A: AttachmentReadRepo + Send + Sync + 'static,
+    M: MessageReadRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
+    B: BlobStore + Send + Sync + 'static,
+{
+    async fn download(&self, user_id: i64, attachment_id: i64) -> Result<AttachmentContent, Error> {
+        let not_found =
+            || Error::NotFound(format!("Attachment with id {} not found", attachment_id));
+
+        let attachment = self
+            .attachment_read_repo
+            .find_by_id(attachment_id)
+            .await?
+            .ok_or_else(not_found)?;
+
+        match attachment.message_id {
+            None if attachment.uploader_id != user_id => return Err(not_found()),
+            None => {}
+            Some(message_id) => {
+                let is_participant = self
+                    .participant_read_repo
+                    .exists_by_conversation_and_user(attachment.conversation_id, user_id)
+                    .await?;
+                if !is_participant {
+                    return Err(Error::Forbidden(
+                        "You are not a participant of this conversation".to_string(),
+                    ));
+                }
+
+                // Attachments of deleted messages are gone along with the message
+                self.message_read_repo
+                    .find_by_id(message_id)
+                    .await?
+                    .ok_or_else(not_found)?;
+            }
+        }
+
+        let bytes = self
+            .blob_store
+            .get(&attachment.storage_key)
+            .await?
+            .ok_or_else(not_found)?;
+
+        Ok(AttachmentContent { attachment, bytes })
+    }
+}
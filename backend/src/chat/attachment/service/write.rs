@@ -0,0 +1,355 @@
+use crate::chat::attachment::model::{
+    Attachment, AttachmentKind, AttachmentResponse, UploadAttachmentRequest, MAX_ATTACHMENT_SIZE,
+    MAX_VOICE_DURATION_MS,
+};
+use crate::chat::attachment::repo::write::AttachmentWriteRepo;
+use crate::chat::participant::repo::read::ConversationParticipantReadRepo;
+use crate::common::config::Config;
+use crate::common::model::Error;
+use crate::common::storage::BlobStore;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use image::{ImageFormat, ImageReader};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::io::Cursor;
+use std::sync::Arc;
+use tracing::warn;
+
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+const MAX_FILE_NAME_LENGTH: usize = 255;
+
+/// Pending attachments removed per query of a sweep
+const SWEEP_BATCH_SIZE: i64 = 100;
+
+pub trait AttachmentWriteService {
+    /// Stores a file for a message `user_id` is about to send to the conversation. The
+    /// attachment is linked to the message sent with its id.
+    fn upload(
+        &self,
+        user_id: i64,
+        req: UploadAttachmentRequest,
+    ) -> impl Future<Output = Result<AttachmentResponse, Error>> + Send;
+
+    /// Removes attachments that were not sent with a message within
+    /// `Config::pending_attachment_ttl`, along with their files. Returns how many were removed.
+    fn sweep_pending(&self) -> impl Future<Output = Result<usize, Error>> + Send;
+}
+
+pub struct AttachmentWriteServiceImpl<W, P, B>
+where
+    W: AttachmentWriteRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
+    B: BlobStore + Send + Sync + 'static,
+{
+    attachment_write_repo: Arc<W>,
+    participant_read_repo: Arc<P>,
+    blob_store: Arc<B>,
+    config: Arc<Config>,
+}
+
+impl<W, P, B> AttachmentWriteServiceImpl<W, P, B>
+where
+    W: AttachmentWriteRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
+    B: BlobStore + Send + Sync + 'static,
+{
+    pub fn new(
+        attachment_write_repo: Arc<W>,
+        participant_read_repo: Arc<P>,
+        blob_store: Arc<B>,
+        config: Arc<Config>,
+    ) -> Self {
+        Self {
+            attachment_write_repo,
+            participant_read_repo,
+            blob_store,
+            config,
+        }
+    }
+}
+
+impl<W, P, B> AttachmentWriteService for AttachmentWriteServiceImpl<W, P, B>
+where
+    W: AttachmentWriteRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
+    B: BlobStore + Send + Sync + 'static,
+{
+    async fn upload(
+        &self,
+        user_id: i64,
+        req: UploadAttachmentRequest,
+    ) -> Result<AttachmentResponse, Error> {
+        let is_participant = self
+            .participant_read_repo
+            .exists_by_conversation_and_user(req.conversation_id, user_id)
+            .await?;
+        if !is_participant {
+            return Err(Error::Forbidden(
+                "You are not a participant of this conversation".to_string(),
+            ));
+        }
+
+        let conversation_id = req.conversation_id;
+        let file_name = sanitize_file_name(req.file_name.as_deref());
+        let (inspection, bytes) = tokio::task::spawn_blocking(move || {
+            inspect(&req.bytes, req.mime_type.as_deref(), req.duration_ms)
+                .map(|inspection| (inspection, req.bytes))
+        })
+        .await
+        .map_err(|e| Error::InternalServerError(e.to_string()))??;
+
+        let mut name = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut name);
+        let storage_key = format!(
+            "conversations/{}/{}",
+            conversation_id,
+            URL_SAFE_NO_PAD.encode(name)
+        );
+        let size = bytes.len() as i64;
+        self.blob_store
+            .put(&storage_key, &inspection.mime_type, bytes)
+            .await?;
+
+        let attachment = Attachment {
+            id: 0, // Will be replaced by database
+            uploader_id: user_id,
+            conversation_id,
+            message_id: None,
+            kind: inspection.kind,
+            file_name,
+            mime_type: inspection.mime_type,
+            size,
+            storage_key,
+            checksum: inspection.checksum,
+            width: inspection.width,
+            height: inspection.height,
+            duration_ms: inspection.duration_ms,
+            created_at: chrono::Utc::now(),
+        };
+        let attachment = match self.attachment_write_repo.create(&attachment).await {
+            Ok(attachment) => attachment,
+            Err(error) => {
+                if let Err(err) = self.blob_store.delete(&attachment.storage_key).await {
+                    warn!(
+                        error = %err,
+                        key = attachment.storage_key,
+                        "Failed to remove orphaned attachment"
+                    );
+                }
+                return Err(error);
+            }
+        };
+
+        Ok(AttachmentResponse::from(attachment))
+    }
+
+    async fn sweep_pending(&self) -> Result<usize, Error> {
+        let created_before = chrono::Utc::now()
+            - chrono::Duration::from_std(self.config.pending_attachment_ttl)
+                .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        let mut removed = 0;
+        loop {
+            let attachments = self
+                .attachment_write_repo
+                .delete_pending(created_before, SWEEP_BATCH_SIZE)
+                .await?;
+            // Rows go first, a file left behind by a failed delete is merely unreachable
+            for attachment in &attachments {
+                if let Err(err) = self.blob_store.delete(&attachment.storage_key).await {
+                    warn!(
+                        error = %err,
+                        key = attachment.storage_key,
+                        "Failed to remove pending attachment"
+                    );
+                }
+            }
+
+            removed += attachments.len();
+            if (attachments.len() as i64) < SWEEP_BATCH_SIZE {
+                return Ok(removed);
+            }
+        }
+    }
+}
+
+/// What an uploaded file turned out to be
+struct Inspection {
+    kind: AttachmentKind,
+    mime_type: String,
+    checksum: String,
+    width: Option<i32>,
+    height: Option<i32>,
+    duration_ms: Option<i32>,
+}
+
+/// Images are recognized by their content, whatever the client declared, so only real images
+/// are ever shown inline. Everything else keeps the declared type, and counts as a voice note
+/// when that is audio.
+fn inspect(
+    bytes: &[u8],
+    declared_mime_type: Option<&str>,
+    duration_ms: Option<i32>,
+) -> Result<Inspection, Error> {
+    if bytes.is_empty() {
+        return Err(Error::BadRequest("File is empty".to_string()));
+    }
+    if bytes.len() > MAX_ATTACHMENT_SIZE {
+        return Err(Error::BadRequest(format!(
+            "File must be at most {} MB",
+            MAX_ATTACHMENT_SIZE / 1024 / 1024
+        )));
+    }
+
+    let checksum = hex::encode(Sha256::digest(bytes));
+    let image_format = image::guess_format(bytes).ok().filter(|format| {
+        matches!(
+            format,
+            ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP | ImageFormat::Gif
+        )
+    });
+    if let Some(format) = image_format {
+        // Only the header is read, the image is never decoded
+        let (width, height) = ImageReader::with_format(Cursor::new(bytes), format)
+            .into_dimensions()
+            .map_err(|_| Error::BadRequest("Image could not be read".to_string()))?;
+
+        return Ok(Inspection {
+            kind: AttachmentKind::Image,
+            mime_type: format.to_mime_type().to_string(),
+            checksum,
+            width: i32::try_from(width).ok(),
+            height: i32::try_from(height).ok(),
+            duration_ms: None,
+        });
+    }
+
+    let mime_type = declared_mime_type
+        .and_then(normalize_mime_type)
+        .unwrap_or_else(|| DEFAULT_MIME_TYPE.to_string());
+    if !mime_type.starts_with("audio/") {
+        return Ok(Inspection {
+            kind: AttachmentKind::File,
+            mime_type,
+            checksum,
+            width: None,
+            height: None,
+            duration_ms: None,
+        });
+    }
+
+    let duration_ms = duration_ms
+        .filter(|duration_ms| (1..=MAX_VOICE_DURATION_MS).contains(duration_ms))
+        .ok_or_else(|| {
+            Error::BadRequest(format!(
+                "Voice notes need a duration of at most {} minutes",
+                MAX_VOICE_DURATION_MS / 60 / 1000
+            ))
+        })?;
+
+    Ok(Inspection {
+        kind: AttachmentKind::Voice,
+        mime_type,
+        checksum,
+        width: None,
+        height: None,
+        duration_ms: Some(duration_ms),
+    })
+}
+
+/// `type/subtype` of a declared content type without its parameters, none when it is malformed
+fn normalize_mime_type(mime_type: &str) -> Option<String> {
+    let mime_type = mime_type.split(';').next()?.trim().to_ascii_lowercase();
+    let (type_, subtype) = mime_type.split_once('/')?;
+    let is_token = |part: &str| {
+        !part.is_empty()
+            && part
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || b"!#$&^_.+-".contains(&byte))
+    };
+
+    (is_token(type_) && is_token(subtype)).then(|| mime_type.clone())
+}
+
+/// Name of the uploaded file without any directories the client sent along
+fn sanitize_file_name(file_name: Option<&str>) -> String {
+    let file_name = file_name
+        .unwrap_or_default()
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_FILE_NAME_LENGTH)
+        .collect::<String>();
+    let file_name = file_name.trim();
+
+    if file_name.is_empty() || file_name == "." || file_name == ".." {
+        "file".to_string()
+    } else {
+        file_name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Smallest valid PNG, a single transparent pixel
+    const PNG: &[u8] = &[
+        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1F,
+        0x15, 0xC4, 0x89, 0x00, 0x00, 0x00, 0x0A, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9C, 0x63, 0x00,
+        0x01, 0x00, 0x00, 0x05, 0x00, 0x01, 0x0D, 0x0A, 0x2D, 0xB4, 0x00, 0x00, 0x00, 0x00, 0x49,
+        0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+    ];
+
+    #[test]
+    fn recognizes_images_by_content_whatever_was_declared() {
+        let inspection = inspect(PNG, Some("text/html"), None).unwrap();
+
+        assert_eq!(inspection.kind, AttachmentKind::Image);
+        assert_eq!(inspection.mime_type, "image/png");
+        assert_eq!((inspection.width, inspection.height), (Some(1), Some(1)));
+    }
+
+    #[test]
+    fn keeps_the_declared_type_of_other_files() {
+        let inspection = inspect(b"%PDF-1.7", Some("Application/PDF; charset=x"), None).unwrap();
+        assert_eq!(inspection.kind, AttachmentKind::File);
+        assert_eq!(inspection.mime_type, "application/pdf");
+
+        let inspection = inspect(b"<html>", Some("not a type"), None).unwrap();
+        assert_eq!(inspection.mime_type, DEFAULT_MIME_TYPE);
+    }
+
+    #[test]
+    fn needs_a_duration_for_voice_notes() {
+        let inspection = inspect(b"OggS", Some("audio/ogg"), Some(1500)).unwrap();
+        assert_eq!(inspection.kind, AttachmentKind::Voice);
+        assert_eq!(inspection.duration_ms, Some(1500));
+
+        assert!(inspect(b"OggS", Some("audio/ogg"), None).is_err());
+        assert!(inspect(b"OggS", Some("audio/ogg"), Some(MAX_VOICE_DURATION_MS + 1)).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_files() {
+        assert!(inspect(b"", None, None).is_err());
+    }
+
+    #[test]
+    fn strips_directories_and_control_characters_from_file_names() {
+        assert_eq!(sanitize_file_name(Some("../../etc/passwd")), "passwd");
+        assert_eq!(
+            sanitize_file_name(Some("C:\\Users\\a\\report.pdf")),
+            "report.pdf"
+        );
+        assert_eq!(sanitize_file_name(Some("a\u{0}b\n.txt")), "ab.txt");
+        assert_eq!(sanitize_file_name(Some("dir/..")), "file");
+        assert_eq!(sanitize_file_name(None), "file");
+    }
+}
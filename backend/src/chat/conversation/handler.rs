@@ -1,50 +1,179 @@
-use crate::chat::conversation::model::CreateConversationRequest;
+use crate::auth::extractor::{Auth, VerifiedAuth};
+use crate::chat::conversation::model::{
+    CreateConversationRequest, InboxRequest, UpdateConversationRequest,
+};
 use crate::chat::conversation::service::read::ConversationReadService;
 use crate::chat::conversation::service::write::ConversationWriteService;
+use crate::chat::message::bus::MessageBus;
+use crate::chat::message::model::ServerEvent;
 use crate::common::json::IntoApiResponse;
+use crate::common::photo::{read_photo, MAX_PHOTO_UPLOAD_SIZE};
 use crate::common::state::AppState;
+use axum::extract::{DefaultBodyLimit, Multipart, Path, Query};
 use axum::response::IntoResponse;
-use axum::routing::post;
+use axum::routing::{delete, get, patch, post};
 use axum::{Json, Router};
 use std::sync::Arc;
+use tracing::error;
 
-pub struct ConversationHandler<T1, T2>
+pub struct ConversationHandler<T1, T2, B>
 where
     T1: ConversationWriteService + Send + Sync + 'static,
     T2: ConversationReadService + Send + Sync + 'static,
+    B: MessageBus + Send + Sync + 'static,
 {
     conversation_write_service: Arc<T1>,
     conversation_read_service: Arc<T2>,
+    message_bus: Arc<B>,
 }
 
-impl<T1, T2> ConversationHandler<T1, T2>
+impl<T1, T2, B> ConversationHandler<T1, T2, B>
 where
     T1: ConversationWriteService + Send + Sync + 'static,
     T2: ConversationReadService + Send + Sync + 'static,
+    B: MessageBus + Send + Sync + 'static,
 {
-    pub fn new(conversation_write_service: Arc<T1>, conversation_read_service: Arc<T2>) -> Self {
+    pub fn new(
+        conversation_write_service: Arc<T1>,
+        conversation_read_service: Arc<T2>,
+        message_bus: Arc<B>,
+    ) -> Self {
         Self {
             conversation_write_service,
             conversation_read_service,
+            message_bus,
         }
     }
 
-    async fn create(&self, req: CreateConversationRequest) -> impl IntoResponse {
+    async fn create(&self, user_id: i64, req: CreateConversationRequest) -> impl IntoResponse {
         self.conversation_write_service
-            .create(req)
+            .create(user_id, req)
             .await
             .into_json()
     }
 
+    async fn find_inbox(&self, user_id: i64, req: InboxRequest) -> impl IntoResponse {
+        self.conversation_read_service
+            .find_inbox(user_id, req)
+            .await
+            .into_json()
+    }
+
+    async fn find_by_id(&self, user_id: i64, conversation_id: i64) -> impl IntoResponse {
+        self.conversation_read_service
+            .find_by_id(user_id, conversation_id)
+            .await
+            .into_json()
+    }
+
+    async fn update(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        req: UpdateConversationRequest,
+    ) -> impl IntoResponse {
+        self.conversation_write_service
+            .update(user_id, conversation_id, req)
+            .await
+            .into_json()
+    }
+
+    async fn delete(&self, user_id: i64, conversation_id: i64) -> impl IntoResponse {
+        let result = self
+            .conversation_write_service
+            .delete(user_id, conversation_id)
+            .await;
+
+        // Ends the subscriptions of the participants' open websockets
+        if result.is_ok() {
+            let event = ServerEvent::ConversationDeleted { conversation_id };
+            if let Err(error) = self.message_bus.publish(conversation_id, event).await {
+                error!(%error, conversation_id, "Failed to publish event");
+            }
+        }
+
+        result.into_json()
+    }
+
+    async fn update_photo(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        multipart: Multipart,
+    ) -> impl IntoResponse {
+        let result = async {
+            let photo = read_photo(multipart).await?;
+            self.conversation_write_service
+                .update_photo(user_id, conversation_id, photo)
+                .await
+        };
+
+        result.await.into_json()
+    }
+
     pub fn create_route(handler: Arc<Self>, router: Router<AppState>) -> Router<AppState> {
-        router.route(
-            "/api/conversation",
-            post({
-                let handler = Arc::clone(&handler);
-                move |Json(req): Json<CreateConversationRequest>| async move {
-                    handler.create(req).await
-                }
-            }),
-        )
+        router
+            .route(
+                "/api/conversation",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |VerifiedAuth(auth): VerifiedAuth,
+                          Json(req): Json<CreateConversationRequest>| async move {
+                        handler.create(auth.user_id, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/conversations",
+                get({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth, Query(req): Query<InboxRequest>| async move {
+                        handler.find_inbox(auth.user_id, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/conversation/:conversation_id",
+                get({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth, Path(conversation_id): Path<i64>| async move {
+                        handler.find_by_id(auth.user_id, conversation_id).await
+                    }
+                }),
+            )
+            .route(
+                "/api/conversation/:conversation_id",
+                patch({
+                    let handler = Arc::clone(&handler);
+                    |VerifiedAuth(auth): VerifiedAuth,
+                     Path(conversation_id): Path<i64>,
+                     Json(req): Json<UpdateConversationRequest>| async move {
+                        handler.update(auth.user_id, conversation_id, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/conversation/:conversation_id",
+                delete({
+                    let handler = Arc::clone(&handler);
+                    |VerifiedAuth(auth): VerifiedAuth, Path(conversation_id): Path<i64>| async move {
+                        handler.delete(auth.user_id, conversation_id).await
+                    }
+                }),
+            )
+            .route(
+                "/api/conversation/:conversation_id/photo",
+                post({
+                    let handler = Arc::clone(&handler);
+                    move |VerifiedAuth(auth): VerifiedAuth,
+                          Path(conversation_id): Path<i64>,
+                          multipart: Multipart| async move {
+                        handler
+                            .update_photo(auth.user_id, conversation_id, multipart)
+                            .await
+                    }
+                })
+                .layer(DefaultBodyLimit::max(MAX_PHOTO_UPLOAD_SIZE)),
+            )
     }
 }
@@ -1,7 +1,8 @@
 use crate::common::model::Error;
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use sqlx::{FromRow, Type};
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 use validator::Validate;
 
@@ -13,6 +14,11 @@ pub struct Conversation {
     pub r#type: ConversationType,
     pub name: Option<String>,
     pub photo_url: Option<String>,
+    pub photo_thumbnail_url: Option<String>,
+    /// Latest message that is not deleted, none while there is none
+    pub last_message_id: Option<i64>,
+    /// When the latest message was sent, or the conversation was created while it has none
+    pub last_activity_at: DateTime<Utc>,
     pub deleted_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -21,9 +27,10 @@ pub struct Conversation {
 #[derive(Debug, Serialize, Deserialize, Type)]
 #[sqlx(type_name = "conversation_type")]
 #[sqlx(rename_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
 pub enum ConversationType {
-    PRIVATE,
-    GROUP,
+    Private,
+    Group,
 }
 
 impl FromStr for ConversationType {
@@ -31,8 +38,8 @@ impl FromStr for ConversationType {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_uppercase().as_str() {
-            "PRIVATE" => Ok(ConversationType::PRIVATE),
-            "GROUP" => Ok(ConversationType::GROUP),
+            "PRIVATE" => Ok(ConversationType::Private),
+            "GROUP" => Ok(ConversationType::Group),
             _ => Err(Error::InternalServerError(format!(
                 "unknown conversation type: {}",
                 s
@@ -49,6 +56,9 @@ pub struct ConversationResponse {
     pub r#type: ConversationType,
     pub name: Option<String>,
     pub photo_url: Option<String>,
+    pub photo_thumbnail_url: Option<String>,
+    pub last_message_id: Option<i64>,
+    pub last_activity_at: DateTime<Utc>,
     pub deleted_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -63,6 +73,9 @@ impl ConversationResponse {
             r#type: conversation.r#type,
             name: conversation.name,
             photo_url: conversation.photo_url,
+            photo_thumbnail_url: conversation.photo_thumbnail_url,
+            last_message_id: conversation.last_message_id,
+            last_activity_at: conversation.last_activity_at,
             deleted_at: conversation.deleted_at,
             created_at: conversation.created_at,
             updated_at: conversation.updated_at,
@@ -70,27 +83,192 @@ impl ConversationResponse {
     }
 }
 
+/// Characters of the latest message shown in the inbox
+pub const LAST_MESSAGE_PREVIEW_LENGTH: i32 = 100;
+
+/// A conversation in the user's inbox, with its latest message and how many messages from
+/// others the user has not read yet
+#[derive(Debug, FromRow)]
+pub struct InboxConversation {
+    #[sqlx(flatten)]
+    pub conversation: Conversation,
+    pub last_message_sender_id: Option<i64>,
+    pub last_message_sender_username: Option<String>,
+    pub last_message_sender_name: Option<String>,
+    /// Start of the text, see `LAST_MESSAGE_PREVIEW_LENGTH`
+    pub last_message_text: Option<String>,
+    pub last_message_attachment_count: Option<i64>,
+    pub last_message_created_at: Option<DateTime<Utc>>,
+    pub unread_count: i64,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct InboxRequest {
+    /// The `next_cursor` of the previous page
+    pub cursor: Option<InboxCursor>,
+    #[validate(range(min = 1, max = 50, message = "Size must be between 1 and 50."))]
+    pub size: Option<i32>,
+}
+
+impl InboxRequest {
+    pub fn size(&self) -> i32 {
+        self.size.unwrap_or(20)
+    }
+}
+
+/// Position in the inbox, the last activity and id of the last conversation on a page. Sent as
+/// `<microseconds since the epoch>_<id>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InboxCursor {
+    pub last_activity_at: DateTime<Utc>,
+    pub conversation_id: i64,
+}
+
+impl Display for InboxCursor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}_{}",
+            self.last_activity_at.timestamp_micros(),
+            self.conversation_id
+        )
+    }
+}
+
+impl FromStr for InboxCursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::BadRequest("Invalid cursor".to_string());
+        let (micros, conversation_id) = s.split_once('_').ok_or_else(invalid)?;
+
+        Ok(InboxCursor {
+            last_activity_at: micros
+                .parse::<i64>()
+                .ok()
+                .and_then(DateTime::from_timestamp_micros)
+                .ok_or_else(invalid)?,
+            conversation_id: conversation_id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl Serialize for InboxCursor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for InboxCursor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|error: Error| de::Error::custom(error))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LastMessageResponse {
+    pub id: i64,
+    pub sender_id: i64,
+    pub sender_username: String,
+    pub sender_name: String,
+    pub text: String,
+    pub attachment_count: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InboxConversationResponse {
+    #[serde(flatten)]
+    pub conversation: ConversationResponse,
+    pub last_message: Option<LastMessageResponse>,
+    pub unread_count: i64,
+}
+
+impl InboxConversationResponse {
+    pub fn from(inbox: InboxConversation) -> Self {
+        let last_message = match (
+            inbox.conversation.last_message_id,
+            inbox.last_message_sender_id,
+            inbox.last_message_created_at,
+        ) {
+            (Some(id), Some(sender_id), Some(created_at)) => Some(LastMessageResponse {
+                id,
+                sender_id,
+                sender_username: inbox.last_message_sender_username.unwrap_or_default(),
+                sender_name: inbox.last_message_sender_name.unwrap_or_default(),
+                text: inbox.last_message_text.unwrap_or_default(),
+                attachment_count: inbox.last_message_attachment_count.unwrap_or_default(),
+                created_at,
+            }),
+            _ => None,
+        };
+
+        Self {
+            conversation: ConversationResponse::from(inbox.conversation),
+            last_message,
+            unread_count: inbox.unread_count,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct CreateConversationRequest {
-    #[validate(range(min = 1))]
-    pub author_id: i64,
     pub r#type: ConversationType,
     #[validate(length(min = 3, max = 50))]
     pub name: Option<String>,
-    #[validate(url)]
-    pub photo_url: Option<String>,
+    /// Users to add besides the author, who is always a participant
     pub participants: Vec<i64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate)]
 pub struct UpdateConversationRequest {
-    pub r#type: ConversationType,
-    pub name: Option<String>,
-    pub photo_url: Option<String>,
+    #[validate(length(min = 3, max = 50))]
+    pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct DeleteConversationRequest {
-    pub author_id: i64,
-    pub conversation_id: i64,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inbox_cursor_round_trips() {
+        let cursor = InboxCursor {
+            last_activity_at: DateTime::from_timestamp_micros(1_742_290_000_123_456).unwrap(),
+            conversation_id: 42,
+        };
+
+        assert_eq!(cursor.to_string(), "1742290000123456_42");
+        assert_eq!(cursor.to_string().parse::<InboxCursor>().unwrap(), cursor);
+    }
+
+    #[test]
+    fn inbox_cursor_round_trips_through_json() {
+        let cursor = InboxCursor {
+            last_activity_at: DateTime::from_timestamp_micros(-1).unwrap(),
+            conversation_id: 7,
+        };
+
+        let json = serde_json::to_string(&cursor).unwrap();
+
+        assert_eq!(json, "\"-1_7\"");
+        assert_eq!(serde_json::from_str::<InboxCursor>(&json).unwrap(), cursor);
+    }
+
+    #[test]
+    fn malformed_inbox_cursors_are_rejected() {
+        for cursor in [
+            "",
+            "1",
+            "1_",
+            "_1",
+            "x_1",
+            "1_x",
+            "1_2_3",
+            "99999999999999999999_1",
+        ] {
+            assert!(cursor.parse::<InboxCursor>().is_err(), "{}", cursor);
+        }
+    }
 }
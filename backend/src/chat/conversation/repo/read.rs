@@ -1,5 +1,7 @@
-use crate::chat::conversation::model::Conversation;
-use crate::common::model::{Error, PageRequest, PageResponse};
+use crate::chat::conversation::model::{
+    Conversation, InboxConversation, InboxCursor, InboxRequest, LAST_MESSAGE_PREVIEW_LENGTH,
+};
+use crate::common::model::{Error, PageResponse};
 use sqlx::{Pool, Postgres};
 use std::future::Future;
 use std::sync::Arc;
@@ -10,11 +12,12 @@ pub trait ConversationReadRepo {
         conversation_id: i64,
     ) -> impl Future<Output = Result<Option<Conversation>, Error>> + Send;
 
-    fn find_by_author_id(
+    /// Conversations the user participates in, the most recently active first
+    fn find_inbox(
         &self,
-        author_id: i64,
-        req: PageRequest,
-    ) -> impl Future<Output = Result<PageResponse<Conversation>, Error>> + Send;
+        user_id: i64,
+        req: &InboxRequest,
+    ) -> impl Future<Output = Result<PageResponse<InboxConversation, InboxCursor>, Error>> + Send;
 
     fn exists_by_private_id(
         &self,
@@ -36,7 +39,8 @@ impl ConversationReadRepo for ConversationReadRepoPg {
     async fn find_by_id(&self, conversation_id: i64) -> Result<Option<Conversation>, Error> {
         let query = r#"
             SELECT 
-                id, author_id, type, name, photo_url, deleted_at, created_at, updated_at
+                id, private_id, author_id, type, name, photo_url, photo_thumbnail_url, last_message_id,
+                last_activity_at, deleted_at, created_at, updated_at
             FROM 
                 "conversation"
             WHERE 
@@ -50,40 +54,74 @@ impl ConversationReadRepo for ConversationReadRepoPg {
             .map_err(|e| Error::InternalServerError(e.to_string()))
     }
 
-    async fn find_by_author_id(
+    async fn find_inbox(
         &self,
-        author_id: i64,
-        req: PageRequest,
-    ) -> Result<PageResponse<Conversation>, Error> {
+        user_id: i64,
+        req: &InboxRequest,
+    ) -> Result<PageResponse<InboxConversation, InboxCursor>, Error> {
+        // Only messages from others after the last one the user read count as unread
         let query = r#"
             SELECT
-                id, author_id, type, name, photo_url, deleted_at, created_at, updated_at
+                c.id, c.private_id, c.author_id, c.type, c.name, c.photo_url,
+                c.photo_thumbnail_url, c.last_message_id, c.last_activity_at, c.deleted_at,
+                c.created_at, c.updated_at,
+                m.sender_id AS last_message_sender_id,
+                u.username AS last_message_sender_username,
+                u.name AS last_message_sender_name,
+                LEFT(m.text, $2) AS last_message_text,
+                (
+                    SELECT COUNT(*) FROM "attachment" a WHERE a.message_id = m.id
+                ) AS last_message_attachment_count,
+                m.created_at AS last_message_created_at,
+                (
+                    SELECT
+                        COUNT(*)
+                    FROM
+                        "message" um
+                    WHERE
+                        um.conversation_id = c.id
+                        AND um.id > COALESCE(p.last_read_message_id, 0)
+                        AND um.sender_id <> p.user_id
+                        AND um.deleted_at IS NULL
+                ) AS unread_count
             FROM
-                "conversation"
+                "conversation_participant" p
+            JOIN
+                "conversation" c ON c.id = p.conversation_id
+            LEFT JOIN
+                "message" m ON m.id = c.last_message_id
+            LEFT JOIN
+                "user" u ON u.id = m.sender_id
             WHERE
-                deleted_at IS NULL AND author_id = $1 AND id < $2
-            ORDER
-                BY id DESC
+                p.user_id = $1
+                AND p.deleted_at IS NULL
+                AND c.deleted_at IS NULL
+                AND ($3::TIMESTAMPTZ IS NULL OR (c.last_activity_at, c.id) < ($3, $4))
+            ORDER BY
+                c.last_activity_at DESC, c.id DESC
             LIMIT
-                $3
+                $5
         "#;
 
-        let conversations: Vec<Conversation> = sqlx::query_as::<_, Conversation>(query)
-            .bind(author_id)
-            .bind(req.cursor())
+        let conversations = sqlx::query_as::<_, InboxConversation>(query)
+            .bind(user_id)
+            .bind(LAST_MESSAGE_PREVIEW_LENGTH)
+            .bind(req.cursor.map(|cursor| cursor.last_activity_at))
+            .bind(req.cursor.map(|cursor| cursor.conversation_id))
             .bind(req.size())
             .fetch_all(&*self.pool)
             .await
             .map_err(|e| Error::InternalServerError(e.to_string()))?;
-        let next_cursor = conversations.last().map(|c| c.id);
+        let next_cursor = conversations.last().map(|inbox| InboxCursor {
+            last_activity_at: inbox.conversation.last_activity_at,
+            conversation_id: inbox.conversation.id,
+        });
 
-        let page = PageResponse {
+        Ok(PageResponse {
             data: conversations,
             size: req.size(),
             next_cursor,
-        };
-
-        Ok(page)
+        })
     }
 
     async fn exists_by_private_id(&self, private_id: &str) -> Result<bool, Error> {
@@ -94,7 +132,7 @@ impl ConversationReadRepo for ConversationReadRepoPg {
                 FROM 
                     "conversation" 
                 WHERE
-                    private_id = $1
+                    private_id = $1 AND deleted_at IS NULL
             )
         "#;
 
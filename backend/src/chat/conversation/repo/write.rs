@@ -1,7 +1,7 @@
 use crate::chat::conversation::model::Conversation;
-use crate::common::database::TRANSACTION;
+use crate::common::database::connection;
 use crate::common::model::Error;
-use sqlx::{Executor, Pool, Postgres};
+use sqlx::{Pool, Postgres};
 use std::future::Future;
 use std::sync::Arc;
 
@@ -16,7 +16,20 @@ pub trait ConversationWriteRepo {
         conversation: Conversation,
     ) -> impl Future<Output = Result<Conversation, Error>> + Send;
 
-    fn delete(&self, conversation_id: i64) -> impl Future<Output = Result<(), Error>> + Send;
+    /// Soft deletes the conversation, its messages become unreachable along with it. A private
+    /// conversation gives up its private id, so its participants can start a new one.
+    fn delete(
+        &self,
+        conversation_id: i64,
+    ) -> impl Future<Output = Result<Conversation, Error>> + Send;
+
+    /// Sets the URLs of a photo the server stored, or removes the photo when none
+    fn update_photo(
+        &self,
+        conversation_id: i64,
+        photo_url: Option<&str>,
+        photo_thumbnail_url: Option<&str>,
+    ) -> impl Future<Output = Result<Conversation, Error>> + Send;
 }
 
 pub struct ConversationWriteRepoPg {
@@ -33,77 +46,115 @@ impl ConversationWriteRepo for ConversationWriteRepoPg {
     async fn create(&self, conversation: Conversation) -> Result<Conversation, Error> {
         let query = r#"
             INSERT INTO "conversation" (
-                id, private_id, author_id, type, name, photo_url, deleted_at, created_at, updated_at
+                id, private_id, author_id, type, name, photo_url, last_activity_at, deleted_at,
+                created_at, updated_at
             ) VALUES (
-                default, $1, $2, $3, $4, $5, $6, $7, $8
+                default, $1, $2, $3, $4, $5, $6, $7, $8, $9
             )
-            RETURNING 
-                *
+            RETURNING
+                id, private_id, author_id, type, name, photo_url, photo_thumbnail_url, last_message_id,
+                last_activity_at, deleted_at, created_at, updated_at
         "#;
 
-        TRANSACTION
-            .with(|cell| async {
-                let tx = (*(cell.borrow_mut())).unwrap();
-                sqlx::query_as::<_, Conversation>(query)
-                    .bind(&conversation.private_id)
-                    .bind(&conversation.author_id)
-                    .bind(&conversation.r#type)
-                    .bind(&conversation.name)
-                    .bind(&conversation.photo_url)
-                    .bind(&conversation.deleted_at)
-                    .bind(&conversation.created_at)
-                    .bind(&conversation.updated_at)
-                    .fetch_one(&mut *tx)
-                    .await
-                    .map_err(|e| Error::InternalServerError(e.to_string()))
-            })
+        let query = sqlx::query_as::<_, Conversation>(query)
+            .bind(&conversation.private_id)
+            .bind(conversation.author_id)
+            .bind(&conversation.r#type)
+            .bind(&conversation.name)
+            .bind(&conversation.photo_url)
+            .bind(conversation.last_activity_at)
+            .bind(conversation.deleted_at)
+            .bind(conversation.created_at)
+            .bind(conversation.updated_at);
+
+        // Runs in the transaction that also adds the participants, if there is one
+        query
+            .fetch_one(&mut *connection(&self.pool).await?)
             .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
     }
 
     async fn update(&self, conversation: Conversation) -> Result<Conversation, Error> {
         let query = r#"
-            UPDATE 
+            UPDATE
                 "conversation"
-            SET 
+            SET
                 name = $1,
                 type = $2,
                 photo_url = $3,
-                deleted_at = $4
-                updated_at = $5,
+                deleted_at = $4,
+                updated_at = $5
             WHERE
                 id = $6
-            RETURNING 
-                id, author_id, type, name, photo_url, deleted_at, created_at, updated_at
+            RETURNING
+                id, private_id, author_id, type, name, photo_url, photo_thumbnail_url, last_message_id,
+                last_activity_at, deleted_at, created_at, updated_at
         "#;
 
         sqlx::query_as::<_, Conversation>(query)
             .bind(&conversation.name)
             .bind(&conversation.r#type)
             .bind(&conversation.photo_url)
-            .bind(&conversation.deleted_at)
-            .bind(&conversation.updated_at)
-            .bind(&conversation.id)
+            .bind(conversation.deleted_at)
+            .bind(conversation.updated_at)
+            .bind(conversation.id)
             .fetch_one(&*self.pool)
             .await
             .map_err(|e| Error::InternalServerError(e.to_string()))
     }
 
-    async fn delete(&self, conversation_id: i64) -> Result<(), Error> {
+    async fn delete(&self, conversation_id: i64) -> Result<Conversation, Error> {
+        // Releases the private id, so the two participants can start a new private conversation
         let query = r#"
             UPDATE
                 "conversation"
-            SET 
+            SET
+                private_id = NULL,
                 deleted_at = $1,
                 updated_at = $2
-            WHERE 
+            WHERE
                 id = $3
+            RETURNING
+                id, private_id, author_id, type, name, photo_url, photo_thumbnail_url, last_message_id,
+                last_activity_at, deleted_at, created_at, updated_at
+        "#;
+
+        sqlx::query_as::<_, Conversation>(query)
+            .bind(Some(chrono::Utc::now()))
+            .bind(chrono::Utc::now())
+            .bind(conversation_id)
+            .fetch_one(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn update_photo(
+        &self,
+        conversation_id: i64,
+        photo_url: Option<&str>,
+        photo_thumbnail_url: Option<&str>,
+    ) -> Result<Conversation, Error> {
+        let query = r#"
+            UPDATE
+                "conversation"
+            SET
+                photo_url = $1,
+                photo_thumbnail_url = $2,
+                updated_at = $3
+            WHERE
+                id = $4
+            RETURNING
+                id, private_id, author_id, type, name, photo_url, photo_thumbnail_url, last_message_id,
+                last_activity_at, deleted_at, created_at, updated_at
         "#;
 
-        sqlx::query(query)
+        sqlx::query_as::<_, Conversation>(query)
+            .bind(photo_url)
+            .bind(photo_thumbnail_url)
+            .bind(chrono::Utc::now())
             .bind(conversation_id)
-            .execute(&*self.pool)
+            .fetch_one(&*self.pool)
             .await
-            .map(|_| ())
             .map_err(|e| Error::InternalServerError(e.to_string()))
     }
 }
@@ -1,67 +1,99 @@
-use crate::chat::conversation::model::ConversationResponse;
+use crate::chat::conversation::model::{
+    ConversationResponse, InboxConversationResponse, InboxCursor, InboxRequest,
+};
 use crate::chat::conversation::repo::read::ConversationReadRepo;
-use crate::common::model::{Error, PageRequest, PageResponse};
+use crate::chat::participant::repo::read::ConversationParticipantReadRepo;
+use crate::common::model::{Error, PageResponse};
 use std::future::Future;
 use std::sync::Arc;
+use validator::Validate;
 
 pub trait ConversationReadService {
+    /// The conversation, for its participants
     fn find_by_id(
         &self,
+        user_id: i64,
         conversation_id: i64,
     ) -> impl Future<Output = Result<ConversationResponse, Error>> + Send;
 
-    fn find_by_author_id(
+    /// Every conversation the user participates in, not only the ones they started, the most
+    /// recently active first
+    fn find_inbox(
         &self,
-        author_id: i64,
-        req: PageRequest,
-    ) -> impl Future<Output = Result<PageResponse<ConversationResponse>, Error>> + Send;
+        user_id: i64,
+        req: InboxRequest,
+    ) -> impl Future<Output = Result<PageResponse<InboxConversationResponse, InboxCursor>, Error>> + Send;
 }
 
-pub struct ConversationReadServiceImpl<R>
+pub struct ConversationReadServiceImpl<R, P>
 where
     R: ConversationReadRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
 {
     pub conversation_read_repo: Arc<R>,
+    pub participant_read_repo: Arc<P>,
 }
 
-impl<R> ConversationReadServiceImpl<R>
+impl<R, P> ConversationReadServiceImpl<R, P>
 where
     R: ConversationReadRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
 {
-    pub fn new(conversation_read_repo: Arc<R>) -> Self {
+    pub fn new(conversation_read_repo: Arc<R>, participant_read_repo: Arc<P>) -> Self {
         Self {
             conversation_read_repo,
+            participant_read_repo,
         }
     }
 }
 
-impl<R> ConversationReadService for ConversationReadServiceImpl<R>
+impl<R, P> ConversationReadService for ConversationReadServiceImpl<R, P>
 where
     R: ConversationReadRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
 {
-    async fn find_by_id(&self, conversation_id: i64) -> Result<ConversationResponse, Error> {
-        self.conversation_read_repo
+    async fn find_by_id(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+    ) -> Result<ConversationResponse, Error> {
+        let conversation = self
+            .conversation_read_repo
             .find_by_id(conversation_id)
             .await?
-            .map(ConversationResponse::from)
-            .ok_or_else(|| Error::NotFound("Conversation not found".to_string()))
+            .ok_or_else(|| Error::NotFound("Conversation not found".to_string()))?;
+
+        let is_participant = self
+            .participant_read_repo
+            .exists_by_conversation_and_user(conversation.id, user_id)
+            .await?;
+        if !is_participant {
+            return Err(Error::Forbidden(
+                "You are not a participant of this conversation".to_string(),
+            ));
+        }
+
+        Ok(ConversationResponse::from(conversation))
     }
 
-    async fn find_by_author_id(
+    async fn find_inbox(
         &self,
-        author_id: i64,
-        req: PageRequest,
-    ) -> Result<PageResponse<ConversationResponse>, Error> {
+        user_id: i64,
+        req: InboxRequest,
+    ) -> Result<PageResponse<InboxConversationResponse, InboxCursor>, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+
         let conversations = self
             .conversation_read_repo
-            .find_by_author_id(author_id, req)
+            .find_inbox(user_id, &req)
             .await?;
 
         Ok(PageResponse {
             data: conversations
                 .data
                 .into_iter()
-                .map(ConversationResponse::from)
+                .map(InboxConversationResponse::from)
                 .collect(),
             next_cursor: conversations.next_cursor,
             size: conversations.size,
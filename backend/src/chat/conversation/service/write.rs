@@ -1,13 +1,16 @@
 use crate::chat::conversation::model::{
     Conversation, ConversationResponse, ConversationType, CreateConversationRequest,
-    DeleteConversationRequest,
+    UpdateConversationRequest,
 };
 use crate::chat::conversation::repo::read::ConversationReadRepo;
 use crate::chat::conversation::repo::write::ConversationWriteRepo;
-use crate::chat::participant::model::Participant;
+use crate::chat::participant::model::{Participant, ParticipantRole, ParticipantRoles};
+use crate::chat::participant::repo::read::ConversationParticipantReadRepo;
 use crate::chat::participant::repo::write::ParticipantWriteRepo;
 use crate::common::database::UnitOfWork;
 use crate::common::model::Error;
+use crate::common::photo::Photo;
+use crate::common::storage::BlobStore;
 use std::future::Future;
 use std::sync::Arc;
 use validator::Validate;
@@ -15,58 +18,121 @@ use validator::Validate;
 pub trait ConversationWriteService {
     fn create(
         &self,
+        author_id: i64,
         req: CreateConversationRequest,
     ) -> impl Future<Output = Result<ConversationResponse, Error>> + Send;
 
+    /// Renames a group conversation, for its admins
+    fn update(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        req: UpdateConversationRequest,
+    ) -> impl Future<Output = Result<ConversationResponse, Error>> + Send;
+
+    /// Deletes the conversation for every participant, for its admins
     fn delete(
         &self,
-        req: DeleteConversationRequest,
+        user_id: i64,
+        conversation_id: i64,
+    ) -> impl Future<Output = Result<ConversationResponse, Error>> + Send;
+
+    /// Replaces the photo of a group conversation with an uploaded image, for its admins
+    fn update_photo(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        photo: Vec<u8>,
     ) -> impl Future<Output = Result<ConversationResponse, Error>> + Send;
 }
 
-pub struct ConversationWriteServiceImpl<T1, T2, T3, U>
+pub struct ConversationWriteServiceImpl<T1, T2, T3, U, T5, B>
 where
     T1: ConversationWriteRepo + Send + Sync + 'static,
     T2: ConversationReadRepo + Send + Sync + 'static,
     T3: ParticipantWriteRepo + Send + Sync + 'static,
     U: UnitOfWork + Send + Sync + 'static,
+    T5: ConversationParticipantReadRepo + Send + Sync + 'static,
+    B: BlobStore + Send + Sync + 'static,
 {
     pub conversation_write_repo: Arc<T1>,
     pub conversation_read_repo: Arc<T2>,
     pub participant_write_repo: Arc<T3>,
     pub unit_of_work: Arc<U>,
+    pub participant_read_repo: Arc<T5>,
+    pub blob_store: Arc<B>,
 }
 
-impl<T1, T2, T3, T4> ConversationWriteServiceImpl<T1, T2, T3, T4>
+impl<T1, T2, T3, T4, T5, B> ConversationWriteServiceImpl<T1, T2, T3, T4, T5, B>
 where
     T1: ConversationWriteRepo + Send + Sync + 'static,
     T2: ConversationReadRepo + Send + Sync + 'static,
     T3: ParticipantWriteRepo + Send + Sync + 'static,
     T4: UnitOfWork + Send + Sync + 'static,
+    T5: ConversationParticipantReadRepo + Send + Sync + 'static,
+    B: BlobStore + Send + Sync + 'static,
 {
     pub fn new(
         conversation_write_repo: Arc<T1>,
         conversation_read_repo: Arc<T2>,
         participant_write_repo: Arc<T3>,
         unit_of_work: Arc<T4>,
+        participant_read_repo: Arc<T5>,
+        blob_store: Arc<B>,
     ) -> Self {
         Self {
             conversation_read_repo,
             conversation_write_repo,
             participant_write_repo,
             unit_of_work,
+            participant_read_repo,
+            blob_store,
         }
     }
+
+    /// Finds the conversation, failing unless the user is one of its admins
+    async fn find_as_admin(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        action: &str,
+    ) -> Result<Conversation, Error> {
+        let conversation = self
+            .conversation_read_repo
+            .find_by_id(conversation_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("Conversation not found".to_string()))?;
+
+        let participant = self
+            .participant_read_repo
+            .find_by_conversation_and_user(conversation.id, user_id)
+            .await?
+            .ok_or_else(|| {
+                Error::Forbidden("You are not a participant of this conversation".to_string())
+            })?;
+        if !participant.roles.0.contains(&ParticipantRole::Admin) {
+            return Err(Error::Forbidden(format!("Only admins can {}", action)));
+        }
+
+        Ok(conversation)
+    }
 }
 
-impl<T1, T2, T3, T4> ConversationWriteService for ConversationWriteServiceImpl<T1, T2, T3, T4>
+impl<T1, T2, T3, T4, T5, B> ConversationWriteService
+    for ConversationWriteServiceImpl<T1, T2, T3, T4, T5, B>
 where
     T1: ConversationWriteRepo + Send + Sync + 'static,
     T2: ConversationReadRepo + Send + Sync + 'static,
     T3: ParticipantWriteRepo + Send + Sync + 'static,
     T4: UnitOfWork + Send + Sync + 'static,
+    T5: ConversationParticipantReadRepo + Send + Sync + 'static,
+    B: BlobStore + Send + Sync + 'static,
 {
-    async fn create(&self, req: CreateConversationRequest) -> Result<ConversationResponse, Error> {
+    async fn create(
+        &self,
+        author_id: i64,
+        req: CreateConversationRequest,
+    ) -> Result<ConversationResponse, Error> {
         self.unit_of_work
             .run(async move {
                 req.validate()
@@ -74,11 +140,12 @@ where
 
                 // Remove duplicate participants
                 let mut participants = req.participants.clone();
-                participants.push(req.author_id);
+                participants.push(author_id);
+                participants.sort_unstable();
                 participants.dedup();
 
-                let (private_id, r#type) = if let ConversationType::PRIVATE = req.r#type {
-                    if req.participants.len() != 2 {
+                let (private_id, r#type) = if let ConversationType::Private = req.r#type {
+                    if participants.len() != 2 {
                         return Err(Error::BadRequest(
                             "Private chat must have 2 participants".to_string(),
                         ));
@@ -95,18 +162,21 @@ where
                         ));
                     }
 
-                    (Some(private_id), ConversationType::PRIVATE)
+                    (Some(private_id), ConversationType::Private)
                 } else {
-                    (None, ConversationType::GROUP)
+                    (None, ConversationType::Group)
                 };
 
                 let conversation = Conversation {
                     id: 0, // Will be replaced by database
                     private_id,
-                    author_id: req.author_id,
+                    author_id,
                     r#type,
                     name: req.name,
-                    photo_url: req.photo_url,
+                    photo_url: None,
+                    photo_thumbnail_url: None,
+                    last_message_id: None,
+                    last_activity_at: chrono::Utc::now(),
                     deleted_at: None,
                     created_at: chrono::Utc::now(),
                     updated_at: chrono::Utc::now(),
@@ -114,40 +184,125 @@ where
 
                 let conversation = self.conversation_write_repo.create(conversation).await?;
 
-                for user_id in req.participants {
+                for user_id in participants {
                     let participant = Participant {
-                        id: 0,
                         conversation_id: conversation.id,
                         user_id,
                         joined_at: chrono::Utc::now(),
-                        roles: if user_id == req.author_id {
-                            "ADMIN,PARTICIPANT".to_string()
+                        roles: if user_id == author_id {
+                            ParticipantRoles(vec![
+                                ParticipantRole::Admin,
+                                ParticipantRole::Participant,
+                            ])
                         } else {
-                            "PARTICIPANT".to_string()
+                            ParticipantRoles(vec![ParticipantRole::Participant])
                         },
-                        deleted_at: None,
+                        last_read_message_id: None,
+                        last_delivered_message_id: None,
                         created_at: chrono::Utc::now(),
                     };
 
                     let _ = self.participant_write_repo.create(participant).await?;
                 }
 
-                Err(Error::InternalServerError(format!("{}", conversation.id)))
+                Ok(ConversationResponse::from(conversation))
             })
             .await
     }
 
-    async fn delete(&self, req: DeleteConversationRequest) -> Result<ConversationResponse, Error> {
+    async fn update(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        req: UpdateConversationRequest,
+    ) -> Result<ConversationResponse, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+
         let conversation = self
-            .conversation_read_repo
-            .find_by_id(req.conversation_id)
-            .await?
-            .ok_or_else(|| Error::InternalServerError("Conversation not found".to_string()))?;
+            .find_as_admin(user_id, conversation_id, "rename the conversation")
+            .await?;
+        if let ConversationType::Private = conversation.r#type {
+            return Err(Error::BadRequest(
+                "Private conversations show the name of the other participant".to_string(),
+            ));
+        }
+
+        let conversation = self
+            .conversation_write_repo
+            .update(Conversation {
+                name: Some(req.name),
+                updated_at: chrono::Utc::now(),
+                ..conversation
+            })
+            .await?;
+
+        Ok(ConversationResponse::from(conversation))
+    }
+
+    async fn delete(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+    ) -> Result<ConversationResponse, Error> {
+        let conversation = self
+            .find_as_admin(user_id, conversation_id, "delete the conversation")
+            .await?;
 
-        self.conversation_write_repo.delete(conversation.id).await?;
+        let conversation = self.conversation_write_repo.delete(conversation.id).await?;
 
         Ok(ConversationResponse::from(conversation))
     }
+
+    async fn update_photo(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        photo: Vec<u8>,
+    ) -> Result<ConversationResponse, Error> {
+        let conversation = self
+            .find_as_admin(user_id, conversation_id, "change the conversation photo")
+            .await?;
+        if let ConversationType::Private = conversation.r#type {
+            return Err(Error::BadRequest(
+                "Private conversations show the photo of the other participant".to_string(),
+            ));
+        }
+
+        let (photo_url, photo_thumbnail_url) = Photo::process(photo)
+            .await?
+            .store(
+                &*self.blob_store,
+                &format!("conversations/{}", conversation.id),
+            )
+            .await?;
+        let updated_conversation = match self
+            .conversation_write_repo
+            .update_photo(
+                conversation.id,
+                Some(&photo_url),
+                Some(&photo_thumbnail_url),
+            )
+            .await
+        {
+            Ok(updated_conversation) => updated_conversation,
+            Err(err) => {
+                Photo::remove(
+                    &*self.blob_store,
+                    &[Some(photo_url), Some(photo_thumbnail_url)],
+                )
+                .await;
+                return Err(err);
+            }
+        };
+        Photo::remove(
+            &*self.blob_store,
+            &[conversation.photo_url, conversation.photo_thumbnail_url],
+        )
+        .await;
+
+        Ok(ConversationResponse::from(updated_conversation))
+    }
 }
 
 pub fn create_private_id(user_id1: i64, user_id2: i64) -> String {
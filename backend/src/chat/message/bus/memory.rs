@@ -0,0 +1,198 @@
+use crate::chat::message::bus::{MessageBus, MessageBusStats};
+use crate::chat::message::model::ServerEvent;
+use crate::common::model::Error;
+use std::collections::HashMap;
+use std::sync::{PoisonError, RwLock};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio::sync::watch;
+
+/// Number of independently locked partitions of the channel registry
+const SHARDS: usize = 32;
+
+/// Capacity of each conversation's channel before slow receivers start lagging
+const CHANNEL_CAPACITY: usize = 100;
+
+type Shard = RwLock<HashMap<i64, Sender<ServerEvent>>>;
+
+/// Delivers events only to subscribers connected to this process. Channels are spread over
+/// sharded locks that are never held across an await, so publishing to one conversation doesn't
+/// contend with subscribers joining or leaving unrelated ones.
+pub struct MessageBusMemory {
+    shards: Vec<Shard>,
+    resync: watch::Sender<()>,
+}
+
+impl MessageBusMemory {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| RwLock::new(HashMap::new())).collect(),
+            resync: watch::Sender::new(()),
+        }
+    }
+
+    /// Tells every subscriber to replay what it may have missed
+    pub fn resync(&self) {
+        self.resync.send_replace(());
+    }
+
+    fn shard(&self, conversation_id: i64) -> &Shard {
+        &self.shards[conversation_id.rem_euclid(SHARDS as i64) as usize]
+    }
+}
+
+impl MessageBus for MessageBusMemory {
+    async fn publish(&self, conversation_id: i64, event: ServerEvent) -> Result<(), Error> {
+        let shard = self
+            .shard(conversation_id)
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(tx) = shard.get(&conversation_id) {
+            // Sending fails only when there are no subscribers left, which is fine
+            let _ = tx.send(event);
+        }
+
+        Ok(())
+    }
+
+    async fn subscribe(&self, conversation_id: i64) -> Receiver<ServerEvent> {
+        let shard = self.shard(conversation_id);
+
+        if let Some(tx) = shard
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&conversation_id)
+        {
+            return tx.subscribe();
+        }
+
+        // Subscribing under the write lock keeps `release` from dropping the channel in between
+        shard
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(conversation_id)
+            .or_insert_with(|| broadcast::channel::<ServerEvent>(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    async fn release(&self, conversation_id: i64) {
+        let mut shard = self
+            .shard(conversation_id)
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(tx) = shard.get(&conversation_id) {
+            if tx.receiver_count() == 0 {
+                shard.remove(&conversation_id);
+            }
+        }
+    }
+
+    fn resyncs(&self) -> watch::Receiver<()> {
+        self.resync.subscribe()
+    }
+
+    fn stats(&self) -> MessageBusStats {
+        self.shards
+            .iter()
+            .fold(MessageBusStats::default(), |mut stats, shard| {
+                let shard = shard.read().unwrap_or_else(PoisonError::into_inner);
+                stats.channels += shard.len();
+                stats.subscribers += shard.values().map(Sender::receiver_count).sum::<usize>();
+                stats
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const CONVERSATIONS: i64 = 64;
+    const SUBSCRIBERS: usize = 50;
+    const EVENTS: i64 = 20;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn concurrent_publishes_reach_every_subscriber() {
+        let bus = Arc::new(MessageBusMemory::new());
+
+        let mut receivers = Vec::new();
+        for conversation_id in 0..CONVERSATIONS {
+            for _ in 0..SUBSCRIBERS {
+                receivers.push((conversation_id, bus.subscribe(conversation_id).await));
+            }
+        }
+        let stats = bus.stats();
+        assert_eq!(stats.channels, CONVERSATIONS as usize);
+        assert_eq!(stats.subscribers, CONVERSATIONS as usize * SUBSCRIBERS);
+
+        let publishers: Vec<_> = (0..CONVERSATIONS)
+            .map(|conversation_id| {
+                let bus = Arc::clone(&bus);
+                tokio::spawn(async move {
+                    for user_id in 0..EVENTS {
+                        let event = ServerEvent::TypingStart {
+                            conversation_id,
+                            user_id,
+                        };
+                        bus.publish(conversation_id, event).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for publisher in publishers {
+            publisher.await.unwrap();
+        }
+
+        for (conversation_id, receiver) in &mut receivers {
+            for expected_user_id in 0..EVENTS {
+                match receiver.try_recv() {
+                    Ok(ServerEvent::TypingStart {
+                        conversation_id: event_conversation_id,
+                        user_id,
+                    }) => {
+                        assert_eq!(event_conversation_id, *conversation_id);
+                        assert_eq!(user_id, expected_user_id);
+                    }
+                    other => panic!("Unexpected event {:?}", other.map(|_| ())),
+                }
+            }
+            assert!(receiver.try_recv().is_err());
+        }
+
+        drop(receivers);
+        for conversation_id in 0..CONVERSATIONS {
+            bus.release(conversation_id).await;
+        }
+        let stats = bus.stats();
+        assert_eq!(stats.channels, 0);
+        assert_eq!(stats.subscribers, 0);
+    }
+
+    #[tokio::test]
+    async fn release_keeps_channels_with_receivers() {
+        let bus = MessageBusMemory::new();
+        let kept = bus.subscribe(1).await;
+        let dropped = bus.subscribe(2).await;
+
+        drop(dropped);
+        bus.release(1).await;
+        bus.release(2).await;
+
+        assert_eq!(bus.stats().channels, 1);
+        drop(kept);
+    }
+
+    #[tokio::test]
+    async fn resyncs_reach_receivers_taken_before() {
+        let bus = MessageBusMemory::new();
+        let mut resyncs = bus.resyncs();
+        assert!(!resyncs.has_changed().unwrap());
+
+        bus.resync();
+
+        assert!(resyncs.has_changed().unwrap());
+        resyncs.mark_unchanged();
+        assert!(!bus.resyncs().has_changed().unwrap());
+    }
+}
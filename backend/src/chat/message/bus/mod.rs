@@ -0,0 +1,94 @@
+pub mod memory;
+pub mod postgres;
+
+use crate::chat::message::bus::memory::MessageBusMemory;
+use crate::chat::message::bus::postgres::MessageBusPg;
+use crate::chat::message::model::ServerEvent;
+use crate::common::config::{Config, MessageBusType};
+use crate::common::model::Error;
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::watch;
+
+/// Fans conversation events out to every subscriber, possibly across server instances
+pub trait MessageBus {
+    fn publish(
+        &self,
+        conversation_id: i64,
+        event: ServerEvent,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn subscribe(&self, conversation_id: i64)
+        -> impl Future<Output = Receiver<ServerEvent>> + Send;
+
+    /// Frees the conversation's channel once its last receiver has been dropped
+    fn release(&self, conversation_id: i64) -> impl Future<Output = ()> + Send;
+
+    /// Changes whenever events of any conversation may have been lost, e.g. while the bus was
+    /// reconnecting. Subscribers then replay what they missed from the database.
+    fn resyncs(&self) -> watch::Receiver<()>;
+
+    /// Snapshot of the channels and subscribers held by this server instance
+    fn stats(&self) -> MessageBusStats;
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MessageBusStats {
+    pub channels: usize,
+    pub subscribers: usize,
+}
+
+/// The bus implementation selected by `Config::message_bus`
+pub enum ConfiguredMessageBus {
+    Memory(MessageBusMemory),
+    Postgres(MessageBusPg),
+}
+
+impl ConfiguredMessageBus {
+    pub async fn init(config: Arc<Config>, pool: Arc<Pool<Postgres>>) -> Result<Self, Error> {
+        match config.message_bus {
+            MessageBusType::Memory => Ok(Self::Memory(MessageBusMemory::new())),
+            MessageBusType::Postgres => Ok(Self::Postgres(MessageBusPg::init(pool).await?)),
+        }
+    }
+}
+
+impl MessageBus for ConfiguredMessageBus {
+    async fn publish(&self, conversation_id: i64, event: ServerEvent) -> Result<(), Error> {
+        match self {
+            Self::Memory(bus) => bus.publish(conversation_id, event).await,
+            Self::Postgres(bus) => bus.publish(conversation_id, event).await,
+        }
+    }
+
+    async fn subscribe(&self, conversation_id: i64) -> Receiver<ServerEvent> {
+        match self {
+            Self::Memory(bus) => bus.subscribe(conversation_id).await,
+            Self::Postgres(bus) => bus.subscribe(conversation_id).await,
+        }
+    }
+
+    async fn release(&self, conversation_id: i64) {
+        match self {
+            Self::Memory(bus) => bus.release(conversation_id).await,
+            Self::Postgres(bus) => bus.release(conversation_id).await,
+        }
+    }
+
+    fn resyncs(&self) -> watch::Receiver<()> {
+        match self {
+            Self::Memory(bus) => bus.resyncs(),
+            Self::Postgres(bus) => bus.resyncs(),
+        }
+    }
+
+    fn stats(&self) -> MessageBusStats {
+        match self {
+            Self::Memory(bus) => bus.stats(),
+            Self::Postgres(bus) => bus.stats(),
+        }
+    }
+}
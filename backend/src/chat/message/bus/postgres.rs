@@ -0,0 +1,334 @@
+use crate::chat::attachment::model::AttachmentResponse;
+use crate::chat::attachment::repo::read::{AttachmentReadRepo, AttachmentReadRepoPg};
+use crate::chat::message::bus::memory::MessageBusMemory;
+use crate::chat::message::bus::{MessageBus, MessageBusStats};
+use crate::chat::message::model::{MessageResponse, ServerEvent};
+use crate::chat::message::repo::read::{MessageReadRepo, PostgresMessageReadRepo};
+use crate::common::model::Error;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgListener;
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::watch;
+use tracing::{error, warn};
+
+const CHANNEL: &str = "message_bus";
+
+/// Postgres rejects NOTIFY payloads of 8000 bytes or more
+const MAX_PAYLOAD_BYTES: usize = 7999;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Envelope {
+    /// The whole event, whenever it fits into a notification
+    Event {
+        conversation_id: i64,
+        event: ServerEvent,
+    },
+    /// A message event too large to be notified, every instance loads the message itself
+    Message {
+        conversation_id: i64,
+        message_id: i64,
+        change: MessageChange,
+    },
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MessageChange {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl Envelope {
+    /// Falls back to notifying only the id of large message events. Other events are small.
+    fn encode(conversation_id: i64, event: ServerEvent) -> Result<String, Error> {
+        let reference = match &event {
+            ServerEvent::MessageCreated { message } => Some((message.id, MessageChange::Created)),
+            ServerEvent::MessageUpdated { message } => Some((message.id, MessageChange::Updated)),
+            ServerEvent::MessageDeleted { message } => Some((message.id, MessageChange::Deleted)),
+            _ => None,
+        };
+
+        let payload = serde_json::to_string(&Envelope::Event {
+            conversation_id,
+            event,
+        })
+        .map_err(|e| Error::InternalServerError(e.to_string()))?;
+        if payload.len() <= MAX_PAYLOAD_BYTES {
+            return Ok(payload);
+        }
+
+        let Some((message_id, change)) = reference else {
+            return Err(Error::InternalServerError(
+                "Event is too large for the message bus".to_string(),
+            ));
+        };
+        serde_json::to_string(&Envelope::Message {
+            conversation_id,
+            message_id,
+            change,
+        })
+        .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+/// Rebuilds the message events that were notified by id
+struct MessageLoader {
+    message_read_repo: PostgresMessageReadRepo,
+    attachment_read_repo: AttachmentReadRepoPg,
+}
+
+impl MessageLoader {
+    async fn load(&self, message_id: i64, change: MessageChange) -> Result<ServerEvent, Error> {
+        let message = self
+            .message_read_repo
+            .find_any_by_id(message_id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("Message with id {} not found", message_id)))?;
+
+        // Attachments of deleted messages can no longer be downloaded
+        if let MessageChange::Deleted = change {
+            let message = MessageResponse::from(message, vec![]);
+            return Ok(ServerEvent::MessageDeleted { message });
+        }
+
+        let attachments = self
+            .attachment_read_repo
+            .find_by_message_ids(&[message.id])
+            .await?
+            .into_iter()
+            .map(AttachmentResponse::from)
+            .collect();
+        let message = MessageResponse::from(message, attachments);
+
+        Ok(match change {
+            MessageChange::Updated => ServerEvent::MessageUpdated { message },
+            _ => ServerEvent::MessageCreated { message },
+        })
+    }
+}
+
+/// Relays events through Postgres `LISTEN/NOTIFY` so every server instance sharing the database
+/// receives them. Each instance listens on a single channel and dispatches notifications to its
+/// local subscribers, including notifications it published itself.
+pub struct MessageBusPg {
+    pool: Arc<Pool<Postgres>>,
+    local: Arc<MessageBusMemory>,
+}
+
+impl MessageBusPg {
+    pub async fn init(pool: Arc<Pool<Postgres>>) -> Result<Self, Error> {
+        let mut listener = PgListener::connect_with(&pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+        listener
+            .listen(CHANNEL)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        let local = Arc::new(MessageBusMemory::new());
+        let loader = Arc::new(MessageLoader {
+            message_read_repo: PostgresMessageReadRepo::new(Arc::clone(&pool)),
+            attachment_read_repo: AttachmentReadRepoPg::new(Arc::clone(&pool)),
+        });
+
+        let dispatcher = Arc::clone(&local);
+        tokio::spawn(async move {
+            loop {
+                let notification = match listener.try_recv().await {
+                    Ok(Some(notification)) => notification,
+                    Ok(None) => {
+                        warn!("Message bus listener lost its connection");
+                        reconnect(&mut listener).await;
+                        // Events notified while the listener was disconnected are lost
+                        dispatcher.resync();
+                        continue;
+                    }
+                    Err(error) => {
+                        error!(%error, "Message bus listener failed");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        reconnect(&mut listener).await;
+                        dispatcher.resync();
+                        continue;
+                    }
+                };
+
+                match serde_json::from_str::<Envelope>(notification.payload()) {
+                    Ok(Envelope::Event {
+                        conversation_id,
+                        event,
+                    }) => {
+                        let _ = dispatcher.publish(conversation_id, event).await;
+                    }
+                    // Loaded aside, so a slow query doesn't hold up the events of other
+                    // conversations. The event may reach subscribers after later ones.
+                    Ok(Envelope::Message {
+                        conversation_id,
+                        message_id,
+                        change,
+                    }) => {
+                        let loader = Arc::clone(&loader);
+                        let dispatcher = Arc::clone(&dispatcher);
+                        tokio::spawn(async move {
+                            match loader.load(message_id, change).await {
+                                Ok(event) => {
+                                    let _ = dispatcher.publish(conversation_id, event).await;
+                                }
+                                Err(error) => {
+                                    error!(%error, conversation_id, "Failed to load message event")
+                                }
+                            }
+                        });
+                    }
+                    Err(error) => warn!(%error, "Dropping malformed message bus payload"),
+                }
+            }
+        });
+
+        Ok(Self { pool, local })
+    }
+}
+
+/// Connects the listener again, it listens on its channels as soon as it is connected
+async fn reconnect(listener: &mut PgListener) {
+    loop {
+        match sqlx::query("SELECT 1").execute(&mut *listener).await {
+            Ok(_) => return,
+            Err(error) => {
+                error!(%error, "Message bus listener failed to reconnect");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+impl MessageBus for MessageBusPg {
+    async fn publish(&self, conversation_id: i64, event: ServerEvent) -> Result<(), Error> {
+        let payload = Envelope::encode(conversation_id, event)?;
+
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(CHANNEL)
+            .bind(payload)
+            .execute(&*self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn subscribe(&self, conversation_id: i64) -> Receiver<ServerEvent> {
+        self.local.subscribe(conversation_id).await
+    }
+
+    async fn release(&self, conversation_id: i64) {
+        self.local.release(conversation_id).await
+    }
+
+    fn resyncs(&self) -> watch::Receiver<()> {
+        self.local.resyncs()
+    }
+
+    fn stats(&self) -> MessageBusStats {
+        self.local.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn message_event(text: String) -> ServerEvent {
+        let message = MessageResponse {
+            id: 42,
+            conversation_id: 7,
+            sender_id: 1,
+            text,
+            attachments: vec![],
+            deleted_at: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        ServerEvent::MessageCreated { message }
+    }
+
+    #[test]
+    fn small_events_travel_whole() {
+        let payload = Envelope::encode(7, message_event("hello".to_string())).unwrap();
+
+        match serde_json::from_str::<Envelope>(&payload).unwrap() {
+            Envelope::Event {
+                conversation_id,
+                event: ServerEvent::MessageCreated { message },
+            } => {
+                assert_eq!(conversation_id, 7);
+                assert_eq!(message.text, "hello");
+            }
+            _ => panic!("expected the whole event"),
+        }
+    }
+
+    #[test]
+    fn large_message_events_travel_by_id() {
+        // 4096 characters of four bytes each
+        let text = "\u{1F600}".repeat(4096);
+        let payload = Envelope::encode(7, message_event(text)).unwrap();
+
+        assert!(payload.len() <= MAX_PAYLOAD_BYTES);
+        match serde_json::from_str::<Envelope>(&payload).unwrap() {
+            Envelope::Message {
+                conversation_id,
+                message_id,
+                change: MessageChange::Created,
+            } => {
+                assert_eq!(conversation_id, 7);
+                assert_eq!(message_id, 42);
+            }
+            _ => panic!("expected a message reference"),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn lost_listener_connections_resync_subscribers() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let pool = Arc::new(sqlx::PgPool::connect(&url).await.unwrap());
+        let bus = MessageBusPg::init(Arc::clone(&pool)).await.unwrap();
+        let mut resyncs = bus.resyncs();
+
+        sqlx::query(
+            r#"
+            SELECT pg_terminate_backend(pid)
+            FROM pg_stat_activity
+            WHERE query LIKE 'LISTEN%message_bus%' AND pid <> pg_backend_pid()
+            "#,
+        )
+        .execute(&*pool)
+        .await
+        .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(10), resyncs.changed())
+            .await
+            .expect("no resync after the listener lost its connection")
+            .unwrap();
+
+        // Notifications arrive again on the new connection
+        let mut rx = bus.subscribe(7).await;
+        let event = ServerEvent::TypingStart {
+            conversation_id: 7,
+            user_id: 1,
+        };
+        bus.publish(7, event).await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            received,
+            ServerEvent::TypingStart { user_id: 1, .. }
+        ));
+    }
+}
@@ -1,116 +1,1065 @@
-use crate::chat::message::model::{CreateMessageRequest, MessageResponse};
+use crate::auth::extractor::{Auth, Authorized, ViewMetrics, WsAuth, ACCESS_TOKEN_PROTOCOL};
+use crate::chat::message::bus::MessageBus;
+use crate::chat::message::model::{
+    ClientEvent, ConnectRequest, CreateMessageRequest, MessageResponse, ServerEvent,
+    UpdateMessageRequest,
+};
+use crate::chat::message::service::read::MessageReadService;
+use crate::chat::message::service::write::MessageWriteService;
+use crate::chat::participant::model::{ReceiptRequest, ReceiptResponse};
+use crate::chat::participant::service::read::ParticipantReadService;
+use crate::chat::participant::service::write::ParticipantWriteService;
+use crate::common::json::{split_error, IntoApiResponse};
+use crate::common::model::{Error, PageRequest};
 use crate::common::state::AppState;
-use axum::extract::ws::{self, WebSocket, WebSocketUpgrade};
-use axum::extract::Path;
+use axum::extract::ws::{self, close_code, CloseFrame, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query};
 use axum::response::IntoResponse;
-use axum::routing::{any, post};
+use axum::routing::{any, get, post};
 use axum::{Json, Router};
+use chrono::{DateTime, TimeDelta, Utc};
 use futures_util::stream::StreamExt;
 use futures_util::SinkExt;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::broadcast::Sender;
-use tokio::sync::{broadcast, Mutex};
-use tracing::info;
-
-// TODO: Replace with better data structure
-type Tx = Mutex<HashMap<i64, Sender<MessageResponse>>>;
-
-pub struct MessageHandler {
-    tx_map: Tx,
-}
-
-impl MessageHandler {
-    pub fn new() -> Self {
-        let tx_map = Mutex::new(HashMap::new());
-        Self { tx_map }
-    }
-
-    async fn create_message(&self, req: CreateMessageRequest) -> impl IntoResponse {
-        let message = MessageResponse {
-            id: 0,
-            conversation_id: req.conversation_id,
-            sender_id: 0,
-            text: req.text,
-            deleted_at: None,
-            created_at: Default::default(),
-            updated_at: Default::default(),
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Number of messages fetched per query while replaying
+const REPLAY_PAGE_SIZE: i32 = 100;
+
+/// Number of delivered message ids a subscription remembers to skip duplicates that a replay
+/// and the live channel both deliver
+const DELIVERED_CAPACITY: usize = 1000;
+
+/// Slack for changes that were committed a while after their `updated_at` was taken
+const REPLAY_CHANGES_MARGIN_SECONDS: i64 = 5;
+
+/// Most receipts published in a single `receipts.updated` event, which keeps the event within
+/// the payload limit of the Postgres message bus
+const RECEIPTS_PER_EVENT: usize = 40;
+
+/// State of a single user's websocket connection
+struct Session {
+    user_id: i64,
+    outbound: mpsc::Sender<ServerEvent>,
+    subscriptions: HashMap<i64, JoinHandle<()>>,
+}
+
+impl Session {
+    /// Whether the conversation's events are still forwarded, a subscription ends by itself when
+    /// its conversation is deleted
+    fn is_subscribed(&self, conversation_id: i64) -> bool {
+        self.subscriptions
+            .get(&conversation_id)
+            .is_some_and(|forward_task| !forward_task.is_finished())
+    }
+}
+
+/// Where the forward task of a new subscription starts
+#[derive(Debug, Clone, Copy)]
+struct ReplayCursor {
+    last_message_id: i64,
+    /// Changes made since this time are replayed along with the messages
+    synced_at: DateTime<Utc>,
+    /// Whether to replay before forwarding live events
+    replay: bool,
+}
+
+/// What a subscription has sent to its session, so a replay resends only what was missed.
+/// Messages commit in a different order than their ids are assigned, so the live channel may
+/// deliver a smaller id after a larger one; deliveries are told apart by id rather than by a high
+/// water mark.
+struct Delivered {
+    /// Where the next replay starts, messages up to it are not replayed
+    cursor: i64,
+    /// Ids of the messages sent after `cursor`
+    message_ids: BTreeSet<i64>,
+    /// Changes made before this time have been sent
+    synced_at: DateTime<Utc>,
+}
+
+impl Delivered {
+    fn new(cursor: ReplayCursor) -> Self {
+        Self {
+            cursor: cursor.last_message_id,
+            message_ids: BTreeSet::new(),
+            synced_at: cursor.synced_at,
+        }
+    }
+
+    /// Whether the session has the message, either sent or known from before subscribing
+    fn contains(&self, message_id: i64) -> bool {
+        message_id <= self.cursor || self.message_ids.contains(&message_id)
+    }
+
+    /// Records a message as sent, `false` if it already was
+    fn insert(&mut self, message_id: i64) -> bool {
+        if !self.message_ids.insert(message_id) {
+            return false;
+        }
+
+        // Beyond capacity the oldest ids are forgotten and the next replay starts after them
+        while self.message_ids.len() > DELIVERED_CAPACITY {
+            if let Some(message_id) = self.message_ids.pop_first() {
+                self.cursor = self.cursor.max(message_id);
+            }
+        }
+
+        true
+    }
+
+    /// Id of the latest message the session has
+    fn last_message_id(&self) -> i64 {
+        self.message_ids
+            .last()
+            .map_or(self.cursor, |message_id| self.cursor.max(*message_id))
+    }
+}
+
+/// Delivered receipts waiting to be published. Every participant's clients report each message
+/// as delivered, so publishing the receipts one by one would send every subscriber a frame per
+/// participant and message. They are collected per conversation instead, keeping the latest
+/// receipt of each participant, and published together.
+#[derive(Default)]
+struct PendingReceipts {
+    receipts: Mutex<HashMap<i64, HashMap<i64, ReceiptResponse>>>,
+}
+
+impl PendingReceipts {
+    fn push(&self, receipt: ReceiptResponse) {
+        let mut receipts = self.receipts.lock().unwrap_or_else(PoisonError::into_inner);
+        let pending = receipts
+            .entry(receipt.conversation_id)
+            .or_default()
+            .entry(receipt.user_id)
+            .or_insert_with(|| receipt.clone());
+
+        // Receipts only move forward, but concurrent updates may be pushed out of order
+        let position = |receipt: &ReceiptResponse| {
+            (
+                receipt.last_delivered_message_id,
+                receipt.last_read_message_id,
+            )
+        };
+        if position(&receipt) > position(pending) {
+            *pending = receipt;
+        }
+    }
+
+    /// Takes the receipts collected so far, grouped by conversation
+    fn take(&self) -> HashMap<i64, Vec<ReceiptResponse>> {
+        std::mem::take(&mut *self.receipts.lock().unwrap_or_else(PoisonError::into_inner))
+            .into_iter()
+            .map(|(conversation_id, receipts)| (conversation_id, receipts.into_values().collect()))
+            .collect()
+    }
+}
+
+pub struct MessageHandler<W, R, P, PW, B>
+where
+    W: MessageWriteService + Send + Sync + 'static,
+    R: MessageReadService + Send + Sync + 'static,
+    P: ParticipantReadService + Send + Sync + 'static,
+    PW: ParticipantWriteService + Send + Sync + 'static,
+    B: MessageBus + Send + Sync + 'static,
+{
+    message_write_service: Arc<W>,
+    message_read_service: Arc<R>,
+    participant_read_service: Arc<P>,
+    participant_write_service: Arc<PW>,
+    message_bus: Arc<B>,
+    pending_receipts: PendingReceipts,
+}
+
+impl<W, R, P, PW, B> MessageHandler<W, R, P, PW, B>
+where
+    W: MessageWriteService + Send + Sync + 'static,
+    R: MessageReadService + Send + Sync + 'static,
+    P: ParticipantReadService + Send + Sync + 'static,
+    PW: ParticipantWriteService + Send + Sync + 'static,
+    B: MessageBus + Send + Sync + 'static,
+{
+    pub fn new(
+        message_write_service: Arc<W>,
+        message_read_service: Arc<R>,
+        participant_read_service: Arc<P>,
+        participant_write_service: Arc<PW>,
+        message_bus: Arc<B>,
+    ) -> Self {
+        Self {
+            message_write_service,
+            message_read_service,
+            participant_read_service,
+            participant_write_service,
+            message_bus,
+            pending_receipts: PendingReceipts::default(),
+        }
+    }
+
+    async fn create_message(&self, sender_id: i64, req: CreateMessageRequest) -> impl IntoResponse {
+        let result = self.message_write_service.create(sender_id, req).await;
+
+        // Only fan out messages that have been persisted
+        if let Ok(message) = &result {
+            self.broadcast(ServerEvent::MessageCreated {
+                message: message.clone(),
+            })
+            .await;
+        }
+
+        result.into_json()
+    }
+
+    async fn find_by_conversation_id(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        req: PageRequest,
+    ) -> impl IntoResponse {
+        self.message_read_service
+            .find_by_conversation_id(user_id, conversation_id, req)
+            .await
+            .into_json()
+    }
+
+    async fn find_receipts(&self, user_id: i64, message_id: i64) -> impl IntoResponse {
+        self.message_read_service
+            .find_receipts(user_id, message_id)
+            .await
+            .into_json()
+    }
+
+    async fn mark_read(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        req: ReceiptRequest,
+    ) -> impl IntoResponse {
+        let result = self
+            .participant_write_service
+            .mark_read(user_id, conversation_id, req)
+            .await;
+        self.publish_receipt(result).await.into_json()
+    }
+
+    /// Lets the other participants, and the user's other devices, know that a receipt moved
+    async fn publish_receipt(
+        &self,
+        result: Result<Option<ReceiptResponse>, Error>,
+    ) -> Result<Option<ReceiptResponse>, Error> {
+        if let Ok(Some(receipt)) = &result {
+            self.broadcast(ServerEvent::ReceiptUpdated {
+                receipt: receipt.clone(),
+            })
+            .await;
+        }
+
+        result
+    }
+
+    /// Publishes the delivered receipts collected since the last call, in a `receipts.updated`
+    /// event per conversation
+    pub async fn publish_pending_receipts(&self) {
+        for (conversation_id, receipts) in self.pending_receipts.take() {
+            for receipts in receipts.chunks(RECEIPTS_PER_EVENT) {
+                self.broadcast(ServerEvent::ReceiptsUpdated {
+                    conversation_id,
+                    receipts: receipts.to_vec(),
+                })
+                .await;
+            }
+        }
+    }
+
+    async fn stats(&self) -> impl IntoResponse {
+        Ok::<_, Error>(self.message_bus.stats()).into_json()
+    }
+
+    async fn broadcast(&self, event: ServerEvent) {
+        let conversation_id = match &event {
+            ServerEvent::MessageCreated { message }
+            | ServerEvent::MessageUpdated { message }
+            | ServerEvent::MessageDeleted { message } => message.conversation_id,
+            ServerEvent::TypingStart {
+                conversation_id, ..
+            }
+            | ServerEvent::TypingStop {
+                conversation_id, ..
+            } => *conversation_id,
+            ServerEvent::ReceiptUpdated { receipt } => receipt.conversation_id,
+            ServerEvent::ReceiptsUpdated {
+                conversation_id, ..
+            } => *conversation_id,
+            ServerEvent::ConversationDeleted { conversation_id } => *conversation_id,
+            // Replies are sent only to the client that triggered them
+            ServerEvent::Subscribed { .. }
+            | ServerEvent::Unsubscribed { .. }
+            | ServerEvent::Ack { .. }
+            | ServerEvent::Error { .. }
+            | ServerEvent::ReplayFailed { .. } => return,
         };
 
-        let channels_guard = self.tx_map.lock().await;
-        if let Some(tx) = channels_guard.get(&message.conversation_id) {
-            tx.send(message.clone()).unwrap();
+        if let Err(error) = self.message_bus.publish(conversation_id, event).await {
+            error!(%error, conversation_id, "Failed to publish event");
         }
+    }
+
+    /// Starts forwarding a conversation's events to the session. What the session missed since
+    /// `cursor` is replayed from the database first when the cursor asks for it, and whenever
+    /// the session falls too far behind the live channel.
+    async fn subscribe(&self, session: &mut Session, conversation_id: i64, cursor: ReplayCursor) {
+        if session.is_subscribed(conversation_id) {
+            return;
+        }
+        // Releases a subscription that ended by itself
+        self.unsubscribe(session, conversation_id).await;
+
+        let mut rx = self.message_bus.subscribe(conversation_id).await;
+        let mut resyncs = self.message_bus.resyncs();
+
+        let user_id = session.user_id;
+        let outbound = session.outbound.clone();
+        let message_read_service = Arc::clone(&self.message_read_service);
+        let forward_task = tokio::spawn(async move {
+            let mut delivered = Delivered::new(cursor);
+            if cursor.replay
+                && !replay(
+                    &*message_read_service,
+                    &outbound,
+                    user_id,
+                    conversation_id,
+                    &mut delivered,
+                )
+                .await
+            {
+                return;
+            }
+
+            loop {
+                // `None` when the session may have missed events and needs a replay
+                let event = tokio::select! {
+                    result = rx.recv() => match result {
+                        Ok(event) => Some(event),
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!(
+                                conversation_id,
+                                skipped, "Subscriber lagged, replaying messages"
+                            );
+                            None
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    // The bus may have lost events, e.g. while reconnecting to the database
+                    Ok(()) = resyncs.changed() => {
+                        warn!(conversation_id, "Message bus resynced, replaying messages");
+                        None
+                    }
+                };
+                let Some(event) = event else {
+                    if !replay(
+                        &*message_read_service,
+                        &outbound,
+                        user_id,
+                        conversation_id,
+                        &mut delivered,
+                    )
+                    .await
+                    {
+                        break;
+                    }
+                    continue;
+                };
+
+                // Skip messages that were already delivered by a replay
+                if let ServerEvent::MessageCreated { message } = &event {
+                    if !delivered.insert(message.id) {
+                        continue;
+                    }
+                }
+
+                let ends_subscription = matches!(event, ServerEvent::ConversationDeleted { .. });
+                if outbound.send(event).await.is_err() || ends_subscription {
+                    break;
+                }
+            }
+        });
+
+        session.subscriptions.insert(conversation_id, forward_task);
+    }
+
+    /// Stops forwarding a conversation's events to the session
+    async fn unsubscribe(&self, session: &mut Session, conversation_id: i64) {
+        let Some(forward_task) = session.subscriptions.remove(&conversation_id) else {
+            return;
+        };
 
-        Json(message)
+        // Wait for the task to drop its receiver before the bus counts the remaining ones
+        forward_task.abort();
+        let _ = forward_task.await;
+
+        self.message_bus.release(conversation_id).await;
     }
 
-    async fn publish_message(&self, socket: WebSocket, conversation_id: i64) {
-        let mut channels_guard = self.tx_map.lock().await;
+    /// Handles a single inbound frame and returns the reply for the sending client, if any
+    async fn handle_client_event(&self, session: &mut Session, text: &str) -> Option<ServerEvent> {
+        let event = match serde_json::from_str::<ClientEvent>(text) {
+            Ok(event) => event,
+            Err(error) => {
+                return Some(error_event(None, Error::BadRequest(error.to_string())));
+            }
+        };
+
+        let user_id = session.user_id;
+        match event {
+            ClientEvent::Subscribe {
+                correlation_id,
+                conversation_id,
+                since_message_id,
+            } => {
+                let result = self
+                    .participant_read_service
+                    .verify_participant(conversation_id, user_id)
+                    .await;
+                if let Err(error) = result {
+                    return Some(error_event(correlation_id, error));
+                }
+
+                let cursor = match self.replay_cursor(user_id, since_message_id).await {
+                    Ok(cursor) => cursor,
+                    Err(error) => return Some(error_event(correlation_id, error)),
+                };
+
+                self.subscribe(session, conversation_id, cursor).await;
+                Some(ServerEvent::Subscribed {
+                    correlation_id,
+                    conversation_id,
+                })
+            }
+            ClientEvent::Unsubscribe {
+                correlation_id,
+                conversation_id,
+            } => {
+                self.unsubscribe(session, conversation_id).await;
+                Some(ServerEvent::Unsubscribed {
+                    correlation_id,
+                    conversation_id,
+                })
+            }
+            ClientEvent::MessageSend {
+                correlation_id,
+                conversation_id,
+                text,
+                attachment_ids,
+            } => {
+                let req = CreateMessageRequest {
+                    conversation_id,
+                    text,
+                    attachment_ids,
+                };
+                let result = self.message_write_service.create(user_id, req).await;
+                Some(
+                    self.reply(correlation_id, result, |message| {
+                        ServerEvent::MessageCreated { message }
+                    })
+                    .await,
+                )
+            }
+            ClientEvent::MessageEdit {
+                correlation_id,
+                message_id,
+                text,
+            } => {
+                let req = UpdateMessageRequest { text };
+                let result = self
+                    .message_write_service
+                    .update(user_id, message_id, req)
+                    .await;
+                Some(
+                    self.reply(correlation_id, result, |message| {
+                        ServerEvent::MessageUpdated { message }
+                    })
+                    .await,
+                )
+            }
+            ClientEvent::MessageDelete {
+                correlation_id,
+                message_id,
+            } => {
+                let result = self.message_write_service.delete(user_id, message_id).await;
+                Some(
+                    self.reply(correlation_id, result, |message| {
+                        ServerEvent::MessageDeleted { message }
+                    })
+                    .await,
+                )
+            }
+            ClientEvent::Read {
+                correlation_id,
+                conversation_id,
+                message_id,
+            } => {
+                let req = ReceiptRequest { message_id };
+                let result = self
+                    .participant_write_service
+                    .mark_read(user_id, conversation_id, req)
+                    .await;
+                Some(receipt_reply(
+                    correlation_id,
+                    message_id,
+                    self.publish_receipt(result).await,
+                ))
+            }
+            ClientEvent::Delivered {
+                correlation_id,
+                conversation_id,
+                message_id,
+            } => {
+                let req = ReceiptRequest { message_id };
+                let result = self
+                    .participant_write_service
+                    .mark_delivered(user_id, conversation_id, req)
+                    .await;
+                // Published later along with the other delivered receipts of the conversation
+                if let Ok(Some(receipt)) = &result {
+                    self.pending_receipts.push(receipt.clone());
+                }
+                Some(receipt_reply(correlation_id, message_id, result))
+            }
+            ClientEvent::TypingStart { conversation_id }
+            | ClientEvent::TypingStop { conversation_id }
+                if !session.is_subscribed(conversation_id) =>
+            {
+                Some(error_event(
+                    None,
+                    Error::Forbidden("You are not subscribed to this conversation".to_string()),
+                ))
+            }
+            ClientEvent::TypingStart { conversation_id } => {
+                self.broadcast(ServerEvent::TypingStart {
+                    conversation_id,
+                    user_id,
+                })
+                .await;
+                None
+            }
+            ClientEvent::TypingStop { conversation_id } => {
+                self.broadcast(ServerEvent::TypingStop {
+                    conversation_id,
+                    user_id,
+                })
+                .await;
+                None
+            }
+        }
+    }
 
-        if !channels_guard.contains_key(&conversation_id) {
-            let (tx, _) = broadcast::channel::<MessageResponse>(100);
-            channels_guard.insert(conversation_id, tx);
+    /// Broadcasts the outcome of a message operation and builds the ack for its sender
+    async fn reply<F>(
+        &self,
+        correlation_id: Option<String>,
+        result: Result<MessageResponse, Error>,
+        into_event: F,
+    ) -> ServerEvent
+    where
+        F: FnOnce(MessageResponse) -> ServerEvent,
+    {
+        match result {
+            Ok(message) => {
+                let message_id = message.id;
+                self.broadcast(into_event(message)).await;
+                ServerEvent::Ack {
+                    correlation_id,
+                    message_id,
+                }
+            }
+            Err(error) => error_event(correlation_id, error),
         }
+    }
 
-        let tx = channels_guard.get_mut(&conversation_id).unwrap();
-        let mut rx = tx.subscribe();
+    /// Where the forward task of a new subscription starts. Without `since_message_id` only live
+    /// events are delivered, and the latest message id serves as the starting point for
+    /// recovering from lag. With it, changes made since that message was sent are replayed too,
+    /// as the client was around up to then.
+    async fn replay_cursor(
+        &self,
+        user_id: i64,
+        since_message_id: Option<i64>,
+    ) -> Result<ReplayCursor, Error> {
+        match since_message_id {
+            Some(since_message_id) => Ok(ReplayCursor {
+                last_message_id: since_message_id,
+                synced_at: self
+                    .message_read_service
+                    .find_sent_at(user_id, since_message_id)
+                    .await?
+                    .unwrap_or(DateTime::UNIX_EPOCH),
+                replay: true,
+            }),
+            None => Ok(ReplayCursor {
+                last_message_id: self.message_read_service.find_last_id().await?,
+                synced_at: replay_started_at(),
+                replay: false,
+            }),
+        }
+    }
 
-        // Early drop to prevent deadlock
-        drop(channels_guard);
+    async fn publish_message(
+        &self,
+        mut socket: WebSocket,
+        auth: Result<Auth, Error>,
+        since_message_id: Option<i64>,
+    ) {
+        let result = match auth {
+            Ok(auth) => self
+                .participant_read_service
+                .find_conversation_ids_by_user_id(auth.user_id)
+                .await
+                .map(|conversation_ids| (auth.user_id, conversation_ids)),
+            Err(error) => Err(error),
+        };
+        let result = match result {
+            Ok((user_id, conversation_ids)) => self
+                .replay_cursor(user_id, since_message_id)
+                .await
+                .map(|cursor| (user_id, conversation_ids, cursor)),
+            Err(error) => Err(error),
+        };
+        let (user_id, conversation_ids, cursor) = match result {
+            Ok(result) => result,
+            Err(error) => {
+                warn!(%error, "Rejecting websocket connection");
+                let _ = socket
+                    .send(ws::Message::Close(Some(close_frame(error))))
+                    .await;
+                return;
+            }
+        };
 
         let (mut sender, mut receiver) = socket.split();
+        let (outbound_tx, mut outbound_rx) = mpsc::channel::<ServerEvent>(64);
+
+        let mut session = Session {
+            user_id,
+            outbound: outbound_tx,
+            subscriptions: HashMap::new(),
+        };
+        for conversation_id in conversation_ids {
+            self.subscribe(&mut session, conversation_id, cursor).await;
+        }
 
-        // Receive messages from another users, and publish the message to current user
+        // Publish events from subscribed conversations and replies to the current user
         let mut send_task = tokio::spawn(async move {
-            while let Ok(res) = rx.recv().await {
-                let text = serde_json::ser::to_string(&res).unwrap();
+            while let Some(event) = outbound_rx.recv().await {
+                // Don't echo typing indicators back to the user who is typing
+                if let ServerEvent::TypingStart {
+                    user_id: typist, ..
+                }
+                | ServerEvent::TypingStop {
+                    user_id: typist, ..
+                } = &event
+                {
+                    if *typist == user_id {
+                        continue;
+                    }
+                }
+
+                let text = match serde_json::ser::to_string(&event) {
+                    Ok(text) => text,
+                    Err(error) => {
+                        error!(%error, "Failed to serialize event, skipping it");
+                        continue;
+                    }
+                };
                 if sender.send(ws::Message::Text(text)).await.is_err() {
                     break;
                 };
             }
         });
 
-        let mut recv_task = tokio::spawn(async move {
-            while let Some(Ok(ws::Message::Close(_))) = receiver.next().await {
-                break;
+        // Handle frames from the current user in place, so services can be borrowed from self
+        let recv_task = async {
+            while let Some(Ok(frame)) = receiver.next().await {
+                let reply = match frame {
+                    ws::Message::Text(text) => self.handle_client_event(&mut session, &text).await,
+                    ws::Message::Close(_) => break,
+                    _ => None,
+                };
+
+                if let Some(reply) = reply {
+                    if session.outbound.send(reply).await.is_err() {
+                        break;
+                    }
+                }
             }
-        });
+        };
 
         tokio::select! {
-            _ = &mut send_task => recv_task.abort(),
-            _ = &mut recv_task => send_task.abort(),
+            _ = &mut send_task => {},
+            _ = recv_task => send_task.abort(),
         }
 
-        let mut channels_guard = self.tx_map.lock().await;
-        let tx = channels_guard.get(&conversation_id).unwrap();
-        if tx.is_empty() {
-            channels_guard.remove(&conversation_id);
+        let conversation_ids: Vec<i64> = session.subscriptions.keys().copied().collect();
+        for conversation_id in conversation_ids {
+            self.unsubscribe(&mut session, conversation_id).await;
         }
     }
 
     pub fn create_route(handler: Arc<Self>, router: Router<AppState>) -> Router<AppState> {
         router
             .route(
-                "/ws/conversation/:conversation_id/messages",
+                "/ws",
                 any({
                     let handler = Arc::clone(&handler);
-                    |ws: WebSocketUpgrade, Path(conversation_id): Path<i64>| async move {
+                    |ws: WebSocketUpgrade,
+                     WsAuth(auth): WsAuth,
+                     Query(req): Query<ConnectRequest>| async move {
                         info!("Websocket upgrade requested");
-                        ws.on_upgrade(move |socket| async move {
-                            handler.publish_message(socket, conversation_id).await
-                        })
+                        ws.protocols([ACCESS_TOKEN_PROTOCOL])
+                            .on_upgrade(move |socket| async move {
+                                handler
+                                    .publish_message(socket, auth, req.since_message_id)
+                                    .await
+                            })
                     }
                 }),
             )
+            .route(
+                "/metrics/message_bus",
+                get({
+                    let handler = Arc::clone(&handler);
+                    |_: Authorized<ViewMetrics>| async move { handler.stats().await }
+                }),
+            )
             .route(
                 "/api/message",
                 post({
                     let handler = Arc::clone(&handler);
-                    |Json(req): Json<CreateMessageRequest>| async move {
-                        handler.create_message(req).await
+                    |auth: Auth, Json(req): Json<CreateMessageRequest>| async move {
+                        handler.create_message(auth.user_id, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/message/:message_id/receipts",
+                get({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth, Path(message_id): Path<i64>| async move {
+                        handler.find_receipts(auth.user_id, message_id).await
+                    }
+                }),
+            )
+            .route(
+                "/api/conversation/:conversation_id/read",
+                post({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth,
+                     Path(conversation_id): Path<i64>,
+                     Json(req): Json<ReceiptRequest>| async move {
+                        handler.mark_read(auth.user_id, conversation_id, req).await
                     }
                 }),
             )
+            .route(
+                "/api/conversation/:conversation_id/messages",
+                get({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth,
+                     Path(conversation_id): Path<i64>,
+                     Query(req): Query<PageRequest>| async move {
+                        handler
+                            .find_by_conversation_id(auth.user_id, conversation_id, req)
+                            .await
+                    }
+                }),
+            )
+    }
+}
+
+fn close_frame(error: Error) -> CloseFrame<'static> {
+    let (code, reason) = match error {
+        Error::BadRequest(message)
+        | Error::UnAuthorized(message)
+        | Error::Forbidden(message)
+        | Error::NotFound(message)
+        | Error::Conflict(message) => (close_code::POLICY, message),
+        Error::TooManyRequests(message) => (close_code::AGAIN, message),
+        Error::InternalServerError(message) => (close_code::ERROR, message),
+    };
+
+    CloseFrame {
+        code,
+        reason: Cow::from(reason),
+    }
+}
+
+/// Acknowledges a `read` or `delivered` frame, also when the receipt was already there
+fn receipt_reply(
+    correlation_id: Option<String>,
+    message_id: i64,
+    result: Result<Option<ReceiptResponse>, Error>,
+) -> ServerEvent {
+    match result {
+        Ok(_) => ServerEvent::Ack {
+            correlation_id,
+            message_id,
+        },
+        Err(error) => error_event(correlation_id, error),
+    }
+}
+
+fn error_event(correlation_id: Option<String>, error: Error) -> ServerEvent {
+    let (status, message) = split_error(error);
+
+    ServerEvent::Error {
+        correlation_id,
+        status: status.as_u16(),
+        message,
+    }
+}
+
+/// Current time less the margin for late commits, from when changes are to be replayed next
+fn replay_started_at() -> DateTime<Utc> {
+    Utc::now() - TimeDelta::seconds(REPLAY_CHANGES_MARGIN_SECONDS)
+}
+
+/// Sends the session what it missed of the conversation. A failure is reported to the session,
+/// which keeps receiving live events. Returns `false` once the session is gone.
+async fn replay<R>(
+    message_read_service: &R,
+    outbound: &mpsc::Sender<ServerEvent>,
+    user_id: i64,
+    conversation_id: i64,
+    delivered: &mut Delivered,
+) -> bool
+where
+    R: MessageReadService + Send + Sync + 'static,
+{
+    let started_at = replay_started_at();
+    let result = match replay_changes(
+        message_read_service,
+        outbound,
+        user_id,
+        conversation_id,
+        delivered,
+    )
+    .await
+    {
+        Ok(true) => {
+            replay_messages(
+                message_read_service,
+                outbound,
+                user_id,
+                conversation_id,
+                delivered,
+            )
+            .await
+        }
+        result => result,
+    };
+
+    match result {
+        Ok(is_open) => {
+            delivered.synced_at = started_at;
+            is_open
+        }
+        Err(error) => {
+            error!(%error, conversation_id, "Failed to replay messages");
+            let (status, message) = split_error(error);
+            let event = ServerEvent::ReplayFailed {
+                conversation_id,
+                status: status.as_u16(),
+                message,
+            };
+            outbound.send(event).await.is_ok()
+        }
+    }
+}
+
+/// Sends edits and deletions of messages the session has that were made since it was last
+/// synced. Returns `false` once the session is gone.
+async fn replay_changes<R>(
+    message_read_service: &R,
+    outbound: &mpsc::Sender<ServerEvent>,
+    user_id: i64,
+    conversation_id: i64,
+    delivered: &Delivered,
+) -> Result<bool, Error>
+where
+    R: MessageReadService + Send + Sync + 'static,
+{
+    let last_message_id = delivered.last_message_id();
+    let mut after_message_id = 0;
+    loop {
+        let messages = message_read_service
+            .find_changed_by_conversation_id(
+                user_id,
+                conversation_id,
+                delivered.synced_at,
+                last_message_id,
+                after_message_id,
+                REPLAY_PAGE_SIZE,
+            )
+            .await?;
+
+        let is_last_page = messages.len() < REPLAY_PAGE_SIZE as usize;
+        for message in messages {
+            after_message_id = message.id;
+            if !delivered.contains(message.id) {
+                continue;
+            }
+
+            let event = if message.deleted_at.is_some() {
+                ServerEvent::MessageDeleted { message }
+            } else {
+                ServerEvent::MessageUpdated { message }
+            };
+            if outbound.send(event).await.is_err() {
+                return Ok(false);
+            }
+        }
+
+        if is_last_page {
+            return Ok(true);
+        }
+    }
+}
+
+/// Sends every message of the conversation after the replay cursor the session doesn't have yet.
+/// Returns `false` once the session is gone.
+async fn replay_messages<R>(
+    message_read_service: &R,
+    outbound: &mpsc::Sender<ServerEvent>,
+    user_id: i64,
+    conversation_id: i64,
+    delivered: &mut Delivered,
+) -> Result<bool, Error>
+where
+    R: MessageReadService + Send + Sync + 'static,
+{
+    let mut after_message_id = delivered.cursor;
+    loop {
+        let messages = message_read_service
+            .find_by_conversation_id_after(
+                user_id,
+                conversation_id,
+                after_message_id,
+                REPLAY_PAGE_SIZE,
+            )
+            .await?;
+
+        let is_last_page = messages.len() < REPLAY_PAGE_SIZE as usize;
+        for message in messages {
+            after_message_id = message.id;
+            if !delivered.insert(message.id) {
+                continue;
+            }
+
+            if outbound
+                .send(ServerEvent::MessageCreated { message })
+                .await
+                .is_err()
+            {
+                return Ok(false);
+            }
+        }
+
+        if is_last_page {
+            return Ok(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delivered_after(last_message_id: i64) -> Delivered {
+        Delivered::new(ReplayCursor {
+            last_message_id,
+            synced_at: Utc::now(),
+            replay: false,
+        })
+    }
+
+    #[test]
+    fn out_of_order_messages_are_delivered() {
+        let mut delivered = delivered_after(10);
+
+        assert!(delivered.insert(12));
+        assert!(delivered.insert(11));
+        assert!(delivered.contains(11));
+        assert_eq!(delivered.last_message_id(), 12);
+    }
+
+    #[test]
+    fn replayed_messages_are_not_delivered_again() {
+        let mut delivered = delivered_after(10);
+
+        assert!(delivered.insert(11));
+        assert!(!delivered.insert(11));
+    }
+
+    #[test]
+    fn forgotten_ids_move_the_cursor() {
+        let mut delivered = delivered_after(0);
+        for message_id in 1..=DELIVERED_CAPACITY as i64 + 2 {
+            assert!(delivered.insert(message_id));
+        }
+
+        assert_eq!(delivered.cursor, 2);
+        assert_eq!(delivered.message_ids.len(), DELIVERED_CAPACITY);
+        assert!(delivered.contains(1));
+        assert_eq!(delivered.last_message_id(), DELIVERED_CAPACITY as i64 + 2);
+    }
+
+    fn receipt(conversation_id: i64, user_id: i64, delivered: i64) -> ReceiptResponse {
+        ReceiptResponse {
+            conversation_id,
+            user_id,
+            last_read_message_id: None,
+            last_delivered_message_id: Some(delivered),
+        }
+    }
+
+    #[test]
+    fn pending_receipts_keep_the_latest_per_participant() {
+        let pending = PendingReceipts::default();
+        pending.push(receipt(1, 10, 5));
+        pending.push(receipt(1, 10, 7));
+        pending.push(receipt(1, 10, 6));
+        pending.push(receipt(1, 11, 7));
+        pending.push(receipt(2, 10, 3));
+
+        let mut taken = pending.take();
+        let mut first = taken.remove(&1).unwrap();
+        first.sort_by_key(|receipt| receipt.user_id);
+
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[0].last_delivered_message_id, Some(7));
+        assert_eq!(first[1].user_id, 11);
+        assert_eq!(taken.remove(&2).unwrap().len(), 1);
+        assert!(taken.is_empty());
+        assert!(pending.take().is_empty());
+    }
+
+    #[test]
+    fn batched_receipts_fit_into_a_bus_notification() {
+        let receipts = (0..RECEIPTS_PER_EVENT as i64)
+            .map(|user_id| ReceiptResponse {
+                last_read_message_id: Some(i64::MAX),
+                ..receipt(i64::MAX, i64::MAX - user_id, i64::MAX)
+            })
+            .collect();
+        let event = ServerEvent::ReceiptsUpdated {
+            conversation_id: i64::MAX,
+            receipts,
+        };
+
+        // Postgres rejects NOTIFY payloads of 8000 bytes or more, the envelope adds a few more
+        assert!(serde_json::to_string(&event).unwrap().len() < 7900);
     }
 }
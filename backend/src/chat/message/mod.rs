@@ -1,4 +1,5 @@
+pub mod bus;
 pub mod handler;
 pub mod model;
-mod repo;
-mod service;
+pub mod repo;
+pub mod service;
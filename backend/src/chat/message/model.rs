@@ -1,6 +1,9 @@
+use crate::chat::attachment::model::AttachmentResponse;
+use crate::chat::participant::model::ReceiptResponse;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use validator::Validate;
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct Message {
@@ -13,11 +16,17 @@ pub struct Message {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
 pub struct CreateMessageRequest {
+    #[validate(range(min = 1))]
     pub conversation_id: i64,
-    pub sender_id: i64,
+    /// May be empty when the message carries attachments
+    #[validate(length(max = 4096, message = "Text length must be at most 4096 characters."))]
     pub text: String,
+    /// Uploaded attachments to send with the message
+    #[serde(default)]
+    #[validate(length(max = 10, message = "A message can carry at most 10 attachments."))]
+    pub attachment_ids: Vec<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -26,7 +35,153 @@ pub struct MessageResponse {
     pub conversation_id: i64,
     pub sender_id: i64,
     pub text: String,
+    pub attachments: Vec<AttachmentResponse>,
     pub deleted_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
+
+impl MessageResponse {
+    pub fn from(message: Message, attachments: Vec<AttachmentResponse>) -> Self {
+        Self {
+            id: message.id,
+            conversation_id: message.conversation_id,
+            sender_id: message.sender_id,
+            text: message.text,
+            attachments,
+            deleted_at: message.deleted_at,
+            created_at: message.created_at,
+            updated_at: message.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct UpdateMessageRequest {
+    #[validate(length(
+        min = 1,
+        max = 4096,
+        message = "Text length must be between 1 and 4096 characters."
+    ))]
+    pub text: String,
+}
+
+/// Query of the websocket upgrade request. With `since_message_id` every message sent after it
+/// is replayed before live events resume, so a reconnecting client doesn't miss anything.
+#[derive(Debug, Deserialize)]
+pub struct ConnectRequest {
+    pub since_message_id: Option<i64>,
+}
+
+/// Frames a client may send over the websocket. `correlation_id` is echoed back in the
+/// matching reply (`ack`, `subscribed`, `unsubscribed` or `error`) so the client can pair
+/// replies with its requests.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum ClientEvent {
+    #[serde(rename = "subscribe")]
+    Subscribe {
+        correlation_id: Option<String>,
+        conversation_id: i64,
+        since_message_id: Option<i64>,
+    },
+    #[serde(rename = "unsubscribe")]
+    Unsubscribe {
+        correlation_id: Option<String>,
+        conversation_id: i64,
+    },
+    #[serde(rename = "message.send")]
+    MessageSend {
+        correlation_id: Option<String>,
+        conversation_id: i64,
+        text: String,
+        #[serde(default)]
+        attachment_ids: Vec<i64>,
+    },
+    #[serde(rename = "message.edit")]
+    MessageEdit {
+        correlation_id: Option<String>,
+        message_id: i64,
+        text: String,
+    },
+    #[serde(rename = "message.delete")]
+    MessageDelete {
+        correlation_id: Option<String>,
+        message_id: i64,
+    },
+    #[serde(rename = "typing.start")]
+    TypingStart { conversation_id: i64 },
+    #[serde(rename = "typing.stop")]
+    TypingStop { conversation_id: i64 },
+    /// Marks messages up to `message_id` as read
+    #[serde(rename = "read")]
+    Read {
+        correlation_id: Option<String>,
+        conversation_id: i64,
+        message_id: i64,
+    },
+    /// Marks messages up to `message_id` as received by the client
+    #[serde(rename = "delivered")]
+    Delivered {
+        correlation_id: Option<String>,
+        conversation_id: i64,
+        message_id: i64,
+    },
+}
+
+/// Frames the server pushes over the websocket. Conversation events are tagged with their
+/// `conversation_id` (directly or through `message` or `receipt`) and broadcast to every
+/// subscriber; the remaining frames are replies sent only to the client that triggered them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum ServerEvent {
+    #[serde(rename = "message.created")]
+    MessageCreated { message: MessageResponse },
+    #[serde(rename = "message.updated")]
+    MessageUpdated { message: MessageResponse },
+    #[serde(rename = "message.deleted")]
+    MessageDeleted { message: MessageResponse },
+    #[serde(rename = "typing.start")]
+    TypingStart { conversation_id: i64, user_id: i64 },
+    #[serde(rename = "typing.stop")]
+    TypingStop { conversation_id: i64, user_id: i64 },
+    #[serde(rename = "receipt.updated")]
+    ReceiptUpdated { receipt: ReceiptResponse },
+    /// Delivered receipts of the conversation, collected and published together
+    #[serde(rename = "receipts.updated")]
+    ReceiptsUpdated {
+        conversation_id: i64,
+        receipts: Vec<ReceiptResponse>,
+    },
+    /// The conversation was deleted, its subscriptions end after this event
+    #[serde(rename = "conversation.deleted")]
+    ConversationDeleted { conversation_id: i64 },
+    #[serde(rename = "subscribed")]
+    Subscribed {
+        correlation_id: Option<String>,
+        conversation_id: i64,
+    },
+    #[serde(rename = "unsubscribed")]
+    Unsubscribed {
+        correlation_id: Option<String>,
+        conversation_id: i64,
+    },
+    #[serde(rename = "ack")]
+    Ack {
+        correlation_id: Option<String>,
+        message_id: i64,
+    },
+    #[serde(rename = "error")]
+    Error {
+        correlation_id: Option<String>,
+        status: u16,
+        message: String,
+    },
+    /// Messages the client missed could not be replayed, so it should reload the conversation
+    #[serde(rename = "replay.failed")]
+    ReplayFailed {
+        conversation_id: i64,
+        status: u16,
+        message: String,
+    },
+}
@@ -1,5 +1,6 @@
 use crate::chat::message::model::Message;
 use crate::common::model::{Error, PageRequest, PageResponse};
+use chrono::{DateTime, Utc};
 use sqlx::{Pool, Postgres};
 use std::future::Future;
 use std::sync::Arc;
@@ -10,11 +11,46 @@ pub trait MessageReadRepo {
         message_id: i64,
     ) -> impl Future<Output = Result<Option<Message>, Error>> + Send;
 
+    /// Also finds deleted messages, to rebuild events about them. Messages of deleted
+    /// conversations are never found.
+    fn find_any_by_id(
+        &self,
+        message_id: i64,
+    ) -> impl Future<Output = Result<Option<Message>, Error>> + Send;
+
     fn find_by_conversation_id(
         &self,
         conversation_id: i64,
         req: PageRequest,
     ) -> impl Future<Output = Result<PageResponse<Message>, Error>> + Send;
+
+    /// Oldest first, so replayed messages arrive in the order they were sent
+    fn find_by_conversation_id_after(
+        &self,
+        conversation_id: i64,
+        message_id: i64,
+        size: i32,
+    ) -> impl Future<Output = Result<Vec<Message>, Error>> + Send;
+
+    /// Messages up to `last_message_id` that were edited or deleted since `changed_since`,
+    /// including deleted ones, oldest first
+    fn find_changed_by_conversation_id(
+        &self,
+        conversation_id: i64,
+        changed_since: DateTime<Utc>,
+        last_message_id: i64,
+        after_message_id: i64,
+        size: i32,
+    ) -> impl Future<Output = Result<Vec<Message>, Error>> + Send;
+
+    fn find_last_id(&self) -> impl Future<Output = Result<i64, Error>> + Send;
+
+    /// When the latest message up to `message_id` in the conversations of `user_id` was created
+    fn find_last_created_at(
+        &self,
+        user_id: i64,
+        message_id: i64,
+    ) -> impl Future<Output = Result<Option<DateTime<Utc>>, Error>> + Send;
 }
 
 pub struct PostgresMessageReadRepo {
@@ -31,11 +67,34 @@ impl MessageReadRepo for PostgresMessageReadRepo {
     async fn find_by_id(&self, message_id: i64) -> Result<Option<Message>, Error> {
         let query = r#"
             SELECT 
-                id, conversation_id, sender_id, text, deleted_at, created_at, updated_at
+                m.id, m.conversation_id, m.sender_id, m.text, m.deleted_at, m.created_at,
+                m.updated_at
             FROM 
-                "message"
+                "message" m
+            JOIN 
+                "conversation" c ON c.id = m.conversation_id
             WHERE 
-                id = $1 AND deleted_at IS NULL
+                m.id = $1 AND m.deleted_at IS NULL AND c.deleted_at IS NULL
+        "#;
+
+        sqlx::query_as::<_, Message>(query)
+            .bind(message_id)
+            .fetch_optional(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn find_any_by_id(&self, message_id: i64) -> Result<Option<Message>, Error> {
+        let query = r#"
+            SELECT 
+                m.id, m.conversation_id, m.sender_id, m.text, m.deleted_at, m.created_at,
+                m.updated_at
+            FROM 
+                "message" m
+            JOIN 
+                "conversation" c ON c.id = m.conversation_id
+            WHERE 
+                m.id = $1 AND c.deleted_at IS NULL
         "#;
 
         sqlx::query_as::<_, Message>(query)
@@ -52,13 +111,17 @@ impl MessageReadRepo for PostgresMessageReadRepo {
     ) -> Result<PageResponse<Message>, Error> {
         let query = r#"
             SELECT 
-                id, conversation_id, sender_id, text, deleted_at, created_at, updated_at
+                m.id, m.conversation_id, m.sender_id, m.text, m.deleted_at, m.created_at,
+                m.updated_at
             FROM 
-                "message"
+                "message" m
+            JOIN 
+                "conversation" c ON c.id = m.conversation_id
             WHERE 
-                deleted_at IS NULL AND conversation_id = $1 AND id < $2
+                m.deleted_at IS NULL AND c.deleted_at IS NULL AND m.conversation_id = $1
+                AND m.id < $2
             ORDER BY 
-                id DESC
+                m.id DESC
             LIMIT 
                 $3
         "#;
@@ -81,4 +144,126 @@ impl MessageReadRepo for PostgresMessageReadRepo {
 
         Ok(page)
     }
+
+    async fn find_by_conversation_id_after(
+        &self,
+        conversation_id: i64,
+        message_id: i64,
+        size: i32,
+    ) -> Result<Vec<Message>, Error> {
+        let query = r#"
+            SELECT 
+                m.id, m.conversation_id, m.sender_id, m.text, m.deleted_at, m.created_at,
+                m.updated_at
+            FROM 
+                "message" m
+            JOIN 
+                "conversation" c ON c.id = m.conversation_id
+            WHERE 
+                m.deleted_at IS NULL AND c.deleted_at IS NULL AND m.conversation_id = $1
+                AND m.id > $2
+            ORDER BY 
+                m.id ASC
+            LIMIT 
+                $3
+        "#;
+
+        sqlx::query_as::<_, Message>(query)
+            .bind(conversation_id)
+            .bind(message_id)
+            .bind(size)
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn find_changed_by_conversation_id(
+        &self,
+        conversation_id: i64,
+        changed_since: DateTime<Utc>,
+        last_message_id: i64,
+        after_message_id: i64,
+        size: i32,
+    ) -> Result<Vec<Message>, Error> {
+        let query = r#"
+            SELECT 
+                m.id, m.conversation_id, m.sender_id, m.text, m.deleted_at, m.created_at,
+                m.updated_at
+            FROM 
+                "message" m
+            JOIN 
+                "conversation" c ON c.id = m.conversation_id
+            WHERE 
+                c.deleted_at IS NULL AND m.conversation_id = $1 AND m.updated_at > $2
+                AND m.updated_at > m.created_at AND m.id <= $3 AND m.id > $4
+            ORDER BY 
+                m.id ASC
+            LIMIT 
+                $5
+        "#;
+
+        sqlx::query_as::<_, Message>(query)
+            .bind(conversation_id)
+            .bind(changed_since)
+            .bind(last_message_id)
+            .bind(after_message_id)
+            .bind(size)
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn find_last_id(&self) -> Result<i64, Error> {
+        let query = r#"
+            SELECT 
+                COALESCE(MAX(id), 0)
+            FROM 
+                "message"
+        "#;
+
+        sqlx::query_scalar(query)
+            .fetch_one(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn find_last_created_at(
+        &self,
+        user_id: i64,
+        message_id: i64,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        let query = r#"
+            SELECT 
+                m.created_at
+            FROM 
+                "conversation_participant" p
+            JOIN 
+                "conversation" c ON c.id = p.conversation_id
+            CROSS JOIN LATERAL (
+                SELECT 
+                    id, created_at
+                FROM 
+                    "message"
+                WHERE 
+                    conversation_id = p.conversation_id AND id <= $2
+                ORDER BY 
+                    id DESC
+                LIMIT 
+                    1
+            ) m
+            WHERE 
+                p.user_id = $1 AND p.deleted_at IS NULL AND c.deleted_at IS NULL
+            ORDER BY 
+                m.id DESC
+            LIMIT 
+                1
+        "#;
+
+        sqlx::query_scalar(query)
+            .bind(user_id)
+            .bind(message_id)
+            .fetch_optional(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
 }
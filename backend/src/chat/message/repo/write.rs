@@ -1,5 +1,7 @@
 use crate::chat::message::model::Message;
+use crate::common::database::connection;
 use crate::common::model::Error;
+use chrono::Utc;
 use sqlx::{Pool, Postgres};
 use std::future::Future;
 use std::sync::Arc;
@@ -9,7 +11,7 @@ pub trait MessageWriteRepo {
 
     fn update(&self, message: Message) -> impl Future<Output = Result<Message, Error>> + Send;
 
-    fn delete(&self, message_id: i64) -> impl Future<Output = Result<(), Error>> + Send;
+    fn delete(&self, message_id: i64) -> impl Future<Output = Result<Message, Error>> + Send;
 }
 
 pub struct PostgresMessageWriteRepo {
@@ -24,26 +26,53 @@ impl PostgresMessageWriteRepo {
 
 impl MessageWriteRepo for PostgresMessageWriteRepo {
     async fn create(&self, message: Message) -> Result<Message, Error> {
+        // The conversation moves to the top of its participants' inboxes in the same statement.
+        // Nothing is inserted into a deleted conversation.
         let query = r#"
-            INSERT INTO "message" (
-                id, conversation_id, sender_id, text, deleted_at, created_at, updated_at
-            ) VALUES (
-                default, $1, $2, $3, $4, $5, $6
+            WITH inserted AS (
+                INSERT INTO "message" (
+                    conversation_id, sender_id, text, deleted_at, created_at, updated_at
+                )
+                SELECT
+                    c.id, $2, $3, $4, $5, $6
+                FROM
+                    "conversation" c
+                WHERE
+                    c.id = $1 AND c.deleted_at IS NULL
+                RETURNING
+                    id, conversation_id, sender_id, text, deleted_at, created_at, updated_at
+            ), activity AS (
+                UPDATE
+                    "conversation" c
+                SET
+                    last_message_id = inserted.id,
+                    last_activity_at = inserted.created_at
+                FROM
+                    inserted
+                WHERE
+                    c.id = inserted.conversation_id
+                    AND (c.last_message_id IS NULL OR c.last_message_id < inserted.id)
             )
-            RETURNING 
+            SELECT
                 *
+            FROM
+                inserted
         "#;
 
-        sqlx::query_as::<_, Message>(query)
-            .bind(&message.conversation_id)
-            .bind(&message.sender_id)
+        let query = sqlx::query_as::<_, Message>(query)
+            .bind(message.conversation_id)
+            .bind(message.sender_id)
             .bind(&message.text)
-            .bind(&message.deleted_at)
-            .bind(&message.created_at)
-            .bind(&message.updated_at)
-            .fetch_one(&*self.pool)
+            .bind(message.deleted_at)
+            .bind(message.created_at)
+            .bind(message.updated_at);
+
+        // Runs in the transaction that also links the message's attachments, if there is one
+        query
+            .fetch_optional(&mut *connection(&self.pool).await?)
             .await
-            .map_err(|e| Error::InternalServerError(e.to_string()))
+            .map_err(|e| Error::InternalServerError(e.to_string()))?
+            .ok_or_else(|| Error::NotFound("Conversation not found".to_string()))
     }
 
     async fn update(&self, message: Message) -> Result<Message, Error> {
@@ -56,38 +85,78 @@ impl MessageWriteRepo for PostgresMessageWriteRepo {
                 updated_at = $3
             WHERE
                 id = $4
+                AND EXISTS (
+                    SELECT 1 FROM "conversation" c WHERE c.id = conversation_id AND c.deleted_at IS NULL
+                )
             RETURNING 
                 id, conversation_id, sender_id, text, deleted_at, created_at, updated_at
         "#;
 
         sqlx::query_as::<_, Message>(query)
             .bind(&message.text)
-            .bind(&message.deleted_at)
-            .bind(&message.updated_at)
-            .bind(&message.id)
-            .fetch_one(&*self.pool)
+            .bind(message.deleted_at)
+            .bind(message.updated_at)
+            .bind(message.id)
+            .fetch_optional(&*self.pool)
             .await
-            .map_err(|e| Error::InternalServerError(e.to_string()))
+            .map_err(|e| Error::InternalServerError(e.to_string()))?
+            .ok_or_else(|| Error::NotFound(format!("Message with id {} not found", message.id)))
     }
 
-    async fn delete(&self, message_id: i64) -> Result<(), Error> {
+    async fn delete(&self, message_id: i64) -> Result<Message, Error> {
+        // A deleted latest message hands its place in the inbox to the one before it
         let query = r#"
-            UPDATE
-                "message"
-            SET 
-                deleted_at = $1,
-                updated_at = $2
-            WHERE 
-                id = $3
-            RETURNING 
-                id, conversation_id, sender_id, text, deleted_at, created_at, updated_at
+            WITH deleted AS (
+                UPDATE
+                    "message"
+                SET
+                    deleted_at = $1,
+                    updated_at = $2
+                WHERE
+                    id = $3
+                    AND EXISTS (
+                        SELECT 1 FROM "conversation" c WHERE c.id = conversation_id AND c.deleted_at IS NULL
+                    )
+                RETURNING
+                    id, conversation_id, sender_id, text, deleted_at, created_at, updated_at
+            ), activity AS (
+                UPDATE
+                    "conversation" c
+                SET
+                    last_message_id = latest.id,
+                    last_activity_at = COALESCE(latest.created_at, c.created_at)
+                FROM
+                    deleted
+                LEFT JOIN LATERAL (
+                    SELECT
+                        m.id, m.created_at
+                    FROM
+                        "message" m
+                    WHERE
+                        m.conversation_id = deleted.conversation_id
+                        AND m.id <> deleted.id
+                        AND m.deleted_at IS NULL
+                    ORDER BY
+                        m.id DESC
+                    LIMIT
+                        1
+                ) latest ON TRUE
+                WHERE
+                    c.id = deleted.conversation_id AND c.last_message_id = deleted.id
+            )
+            SELECT
+                *
+            FROM
+                deleted
         "#;
 
-        sqlx::query(query)
+        sqlx::query_as::<_, Message>(query)
+            .bind(Some(Utc::now()))
+            .bind(Utc::now())
             .bind(message_id)
-            .execute(&*self.pool)
+            .fetch_optional(&*self.pool)
             .await
-            .map(|_| ())
-            .map_err(|e| Error::InternalServerError(e.to_string()))
+            .map_err(|e| Error::InternalServerError(e.to_string()))?
+            .ok_or_else(|| Error::NotFound(format!("Message with id {} not found", message_id)))
     }
 }
@@ -1,2 +1,2 @@
-mod read;
-mod write;
+pub mod read;
+pub mod write;
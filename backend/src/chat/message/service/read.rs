@@ -1 +1,252 @@
+use crate::chat::attachment::model::AttachmentResponse;
+use crate::chat::attachment::repo::read::AttachmentReadRepo;
+use crate::chat::message::model::{Message, MessageResponse};
+use crate::chat::message::repo::read::MessageReadRepo;
+use crate::chat::participant::model::MessageReceiptResponse;
+use crate::chat::participant::repo::read::ConversationParticipantReadRepo;
+use crate::common::model::{Error, PageRequest, PageResponse};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use validator::Validate;
 
+pub trait MessageReadService {
+    fn find_by_conversation_id(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        req: PageRequest,
+    ) -> impl Future<Output = Result<PageResponse<MessageResponse>, Error>> + Send;
+
+    fn find_by_conversation_id_after(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        message_id: i64,
+        size: i32,
+    ) -> impl Future<Output = Result<Vec<MessageResponse>, Error>> + Send;
+
+    /// Messages up to `last_message_id` that were edited or deleted since `changed_since`,
+    /// oldest first. Deleted messages come without their attachments.
+    fn find_changed_by_conversation_id(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        changed_since: DateTime<Utc>,
+        last_message_id: i64,
+        after_message_id: i64,
+        size: i32,
+    ) -> impl Future<Output = Result<Vec<MessageResponse>, Error>> + Send;
+
+    /// Id of the most recent message across all conversations, `0` if there is none
+    fn find_last_id(&self) -> impl Future<Output = Result<i64, Error>> + Send;
+
+    /// When the latest message up to `message_id` in the user's conversations was sent, `None`
+    /// if there is none
+    fn find_sent_at(
+        &self,
+        user_id: i64,
+        message_id: i64,
+    ) -> impl Future<Output = Result<Option<DateTime<Utc>>, Error>> + Send;
+
+    /// The other participants a message reached, and whether they have seen it
+    fn find_receipts(
+        &self,
+        user_id: i64,
+        message_id: i64,
+    ) -> impl Future<Output = Result<Vec<MessageReceiptResponse>, Error>> + Send;
+}
+
+pub struct MessageReadServiceImpl<R, P, A>
+where
+    R: MessageReadRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
+    A: AttachmentReadRepo + Send + Sync + 'static,
+{
+    message_read_repo: Arc<R>,
+    participant_read_repo: Arc<P>,
+    attachment_read_repo: Arc<A>,
+}
+
+impl<R, P, A> MessageReadServiceImpl<R, P, A>
+where
+    R: MessageReadRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
+    A: AttachmentReadRepo + Send + Sync + 'static,
+{
+    pub fn new(
+        message_read_repo: Arc<R>,
+        participant_read_repo: Arc<P>,
+        attachment_read_repo: Arc<A>,
+    ) -> Self {
+        Self {
+            message_read_repo,
+            participant_read_repo,
+            attachment_read_repo,
+        }
+    }
+
+    /// Loads the attachments of all messages at once
+    async fn to_responses(&self, messages: Vec<Message>) -> Result<Vec<MessageResponse>, Error> {
+        let message_ids = messages.iter().map(|m| m.id).collect::<Vec<_>>();
+        if message_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut attachments = HashMap::<i64, Vec<AttachmentResponse>>::new();
+        for attachment in self
+            .attachment_read_repo
+            .find_by_message_ids(&message_ids)
+            .await?
+        {
+            if let Some(message_id) = attachment.message_id {
+                attachments
+                    .entry(message_id)
+                    .or_default()
+                    .push(AttachmentResponse::from(attachment));
+            }
+        }
+
+        Ok(messages
+            .into_iter()
+            .map(|message| {
+                let attachments = attachments.remove(&message.id).unwrap_or_default();
+                MessageResponse::from(message, attachments)
+            })
+            .collect())
+    }
+
+    async fn verify_participant(&self, conversation_id: i64, user_id: i64) -> Result<(), Error> {
+        let is_participant = self
+            .participant_read_repo
+            .exists_by_conversation_and_user(conversation_id, user_id)
+            .await?;
+        if !is_participant {
+            return Err(Error::Forbidden(
+                "You are not a participant of this conversation".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl<R, P, A> MessageReadService for MessageReadServiceImpl<R, P, A>
+where
+    R: MessageReadRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
+    A: AttachmentReadRepo + Send + Sync + 'static,
+{
+    async fn find_by_conversation_id(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        req: PageRequest,
+    ) -> Result<PageResponse<MessageResponse>, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+        self.verify_participant(conversation_id, user_id).await?;
+
+        let messages = self
+            .message_read_repo
+            .find_by_conversation_id(conversation_id, req)
+            .await?;
+
+        Ok(PageResponse {
+            data: self.to_responses(messages.data).await?,
+            next_cursor: messages.next_cursor,
+            size: messages.size,
+        })
+    }
+
+    async fn find_by_conversation_id_after(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        message_id: i64,
+        size: i32,
+    ) -> Result<Vec<MessageResponse>, Error> {
+        self.verify_participant(conversation_id, user_id).await?;
+
+        let messages = self
+            .message_read_repo
+            .find_by_conversation_id_after(conversation_id, message_id, size)
+            .await?;
+
+        self.to_responses(messages).await
+    }
+
+    async fn find_changed_by_conversation_id(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        changed_since: DateTime<Utc>,
+        last_message_id: i64,
+        after_message_id: i64,
+        size: i32,
+    ) -> Result<Vec<MessageResponse>, Error> {
+        self.verify_participant(conversation_id, user_id).await?;
+
+        let messages = self
+            .message_read_repo
+            .find_changed_by_conversation_id(
+                conversation_id,
+                changed_since,
+                last_message_id,
+                after_message_id,
+                size,
+            )
+            .await?;
+
+        Ok(self
+            .to_responses(messages)
+            .await?
+            .into_iter()
+            .map(|mut message| {
+                if message.deleted_at.is_some() {
+                    message.attachments.clear();
+                }
+                message
+            })
+            .collect())
+    }
+
+    async fn find_last_id(&self) -> Result<i64, Error> {
+        self.message_read_repo.find_last_id().await
+    }
+
+    async fn find_sent_at(
+        &self,
+        user_id: i64,
+        message_id: i64,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        self.message_read_repo
+            .find_last_created_at(user_id, message_id)
+            .await
+    }
+
+    async fn find_receipts(
+        &self,
+        user_id: i64,
+        message_id: i64,
+    ) -> Result<Vec<MessageReceiptResponse>, Error> {
+        let message = self
+            .message_read_repo
+            .find_by_id(message_id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("Message with id {} not found", message_id)))?;
+        self.verify_participant(message.conversation_id, user_id)
+            .await?;
+
+        let receipts = self
+            .participant_read_repo
+            .find_receipts_by_message(message.conversation_id, message.id, message.sender_id)
+            .await?;
+
+        Ok(receipts
+            .into_iter()
+            .map(|receipt| MessageReceiptResponse::from(receipt, message.id))
+            .collect())
+    }
+}
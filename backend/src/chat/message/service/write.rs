@@ -1 +1,255 @@
+use crate::chat::attachment::model::AttachmentResponse;
+use crate::chat::attachment::repo::read::AttachmentReadRepo;
+use crate::chat::attachment::repo::write::AttachmentWriteRepo;
+use crate::chat::message::model::{
+    CreateMessageRequest, Message, MessageResponse, UpdateMessageRequest,
+};
+use crate::chat::message::repo::read::MessageReadRepo;
+use crate::chat::message::repo::write::MessageWriteRepo;
+use crate::chat::participant::repo::read::ConversationParticipantReadRepo;
+use crate::common::database::UnitOfWork;
+use crate::common::model::Error;
+use std::future::Future;
+use std::sync::Arc;
+use validator::Validate;
 
+pub trait MessageWriteService {
+    fn create(
+        &self,
+        sender_id: i64,
+        req: CreateMessageRequest,
+    ) -> impl Future<Output = Result<MessageResponse, Error>> + Send;
+
+    fn update(
+        &self,
+        sender_id: i64,
+        message_id: i64,
+        req: UpdateMessageRequest,
+    ) -> impl Future<Output = Result<MessageResponse, Error>> + Send;
+
+    fn delete(
+        &self,
+        sender_id: i64,
+        message_id: i64,
+    ) -> impl Future<Output = Result<MessageResponse, Error>> + Send;
+}
+
+pub struct MessageWriteServiceImpl<W, R, P, AR, AW, U>
+where
+    W: MessageWriteRepo + Send + Sync + 'static,
+    R: MessageReadRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
+    AR: AttachmentReadRepo + Send + Sync + 'static,
+    AW: AttachmentWriteRepo + Send + Sync + 'static,
+    U: UnitOfWork + Send + Sync + 'static,
+{
+    message_write_repo: Arc<W>,
+    message_read_repo: Arc<R>,
+    participant_read_repo: Arc<P>,
+    attachment_read_repo: Arc<AR>,
+    attachment_write_repo: Arc<AW>,
+    unit_of_work: Arc<U>,
+}
+
+impl<W, R, P, AR, AW, U> MessageWriteServiceImpl<W, R, P, AR, AW, U>
+where
+    W: MessageWriteRepo + Send + Sync + 'static,
+    R: MessageReadRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
+    AR: AttachmentReadRepo + Send + Sync + 'static,
+    AW: AttachmentWriteRepo + Send + Sync + 'static,
+    U: UnitOfWork + Send + Sync + 'static,
+{
+    pub fn new(
+        message_write_repo: Arc<W>,
+        message_read_repo: Arc<R>,
+        participant_read_repo: Arc<P>,
+        attachment_read_repo: Arc<AR>,
+        attachment_write_repo: Arc<AW>,
+        unit_of_work: Arc<U>,
+    ) -> Self {
+        Self {
+            message_write_repo,
+            message_read_repo,
+            participant_read_repo,
+            attachment_read_repo,
+            attachment_write_repo,
+            unit_of_work,
+        }
+    }
+
+    /// Fails unless every attachment was uploaded by the sender to the conversation and is not
+    /// part of a message yet
+    async fn verify_attachments(
+        &self,
+        sender_id: i64,
+        conversation_id: i64,
+        attachment_ids: &[i64],
+    ) -> Result<(), Error> {
+        let attachments = self
+            .attachment_read_repo
+            .find_by_ids(attachment_ids)
+            .await?;
+
+        for attachment_id in attachment_ids {
+            let attachment = attachments
+                .iter()
+                .find(|attachment| attachment.id == *attachment_id)
+                .filter(|attachment| attachment.uploader_id == sender_id)
+                .ok_or_else(|| {
+                    Error::NotFound(format!("Attachment with id {} not found", attachment_id))
+                })?;
+            if attachment.conversation_id != conversation_id || attachment.message_id.is_some() {
+                return Err(Error::BadRequest(format!(
+                    "Attachment with id {} cannot be sent with this message",
+                    attachment_id
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn find_own_message(&self, sender_id: i64, message_id: i64) -> Result<Message, Error> {
+        let message = self
+            .message_read_repo
+            .find_by_id(message_id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("Message with id {} not found", message_id)))?;
+
+        if message.sender_id != sender_id {
+            return Err(Error::Forbidden(
+                "Only the sender can modify this message".to_string(),
+            ));
+        }
+
+        // Senders who left the conversation can no longer change what they wrote in it
+        let is_participant = self
+            .participant_read_repo
+            .exists_by_conversation_and_user(message.conversation_id, sender_id)
+            .await?;
+        if !is_participant {
+            return Err(Error::Forbidden(
+                "You are not a participant of this conversation".to_string(),
+            ));
+        }
+
+        Ok(message)
+    }
+}
+
+impl<W, R, P, AR, AW, U> MessageWriteService for MessageWriteServiceImpl<W, R, P, AR, AW, U>
+where
+    W: MessageWriteRepo + Send + Sync + 'static,
+    R: MessageReadRepo + Send + Sync + 'static,
+    P: ConversationParticipantReadRepo + Send + Sync + 'static,
+    AR: AttachmentReadRepo + Send + Sync + 'static,
+    AW: AttachmentWriteRepo + Send + Sync + 'static,
+    U: UnitOfWork + Send + Sync + 'static,
+{
+    async fn create(
+        &self,
+        sender_id: i64,
+        mut req: CreateMessageRequest,
+    ) -> Result<MessageResponse, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+        if req.text.is_empty() && req.attachment_ids.is_empty() {
+            return Err(Error::BadRequest(
+                "Message needs a text or an attachment".to_string(),
+            ));
+        }
+
+        let is_participant = self
+            .participant_read_repo
+            .exists_by_conversation_and_user(req.conversation_id, sender_id)
+            .await?;
+        if !is_participant {
+            return Err(Error::Forbidden(
+                "You are not a participant of this conversation".to_string(),
+            ));
+        }
+
+        req.attachment_ids.sort_unstable();
+        req.attachment_ids.dedup();
+
+        // Attachments are locked while they are checked, so no other message can take them
+        // before this one links them
+        self.unit_of_work
+            .run(async move {
+                self.verify_attachments(sender_id, req.conversation_id, &req.attachment_ids)
+                    .await?;
+
+                let message = Message {
+                    id: 0, // Will be replaced by database
+                    conversation_id: req.conversation_id,
+                    sender_id,
+                    text: req.text,
+                    deleted_at: None,
+                    created_at: chrono::Utc::now(),
+                    updated_at: chrono::Utc::now(),
+                };
+
+                let message = self.message_write_repo.create(message).await?;
+                let attachments = if req.attachment_ids.is_empty() {
+                    vec![]
+                } else {
+                    self.attachment_write_repo
+                        .attach(message.id, &req.attachment_ids)
+                        .await?
+                };
+                if attachments.len() != req.attachment_ids.len() {
+                    return Err(Error::BadRequest(
+                        "Attachments cannot be sent with this message".to_string(),
+                    ));
+                }
+
+                Ok(MessageResponse::from(
+                    message,
+                    attachments
+                        .into_iter()
+                        .map(AttachmentResponse::from)
+                        .collect(),
+                ))
+            })
+            .await
+    }
+
+    async fn update(
+        &self,
+        sender_id: i64,
+        message_id: i64,
+        req: UpdateMessageRequest,
+    ) -> Result<MessageResponse, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+
+        let message = self.find_own_message(sender_id, message_id).await?;
+
+        let message = Message {
+            text: req.text,
+            updated_at: chrono::Utc::now(),
+            ..message
+        };
+
+        let message = self.message_write_repo.update(message).await?;
+        let attachments = self
+            .attachment_read_repo
+            .find_by_message_ids(&[message.id])
+            .await?
+            .into_iter()
+            .map(AttachmentResponse::from)
+            .collect();
+
+        Ok(MessageResponse::from(message, attachments))
+    }
+
+    async fn delete(&self, sender_id: i64, message_id: i64) -> Result<MessageResponse, Error> {
+        let message = self.find_own_message(sender_id, message_id).await?;
+
+        let message = self.message_write_repo.delete(message.id).await?;
+
+        // Attachments of deleted messages can no longer be downloaded
+        Ok(MessageResponse::from(message, vec![]))
+    }
+}
@@ -1,3 +1,4 @@
+pub mod attachment;
 pub mod conversation;
 pub mod message;
 pub mod participant;
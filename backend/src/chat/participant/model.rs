@@ -1,10 +1,148 @@
+use crate::common::model::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Role of a participant within a conversation, unrelated to the global `auth::model::Role`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParticipantRole {
+    Admin,
+    Participant,
+}
+
+impl ParticipantRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParticipantRole::Admin => "ADMIN",
+            ParticipantRole::Participant => "PARTICIPANT",
+        }
+    }
+}
+
+impl FromStr for ParticipantRole {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "ADMIN" => Ok(ParticipantRole::Admin),
+            "PARTICIPANT" => Ok(ParticipantRole::Participant),
+            _ => Err(Error::InternalServerError(format!(
+                "Unknown participant role {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Roles of a participant, stored comma separated, e.g. `ADMIN,PARTICIPANT`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParticipantRoles(pub Vec<ParticipantRole>);
+
+impl TryFrom<String> for ParticipantRoles {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value
+            .split(',')
+            .filter(|role| !role.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map(ParticipantRoles)
+    }
+}
+
+impl Display for ParticipantRoles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let roles = self
+            .0
+            .iter()
+            .map(ParticipantRole::as_str)
+            .collect::<Vec<_>>();
+
+        write!(f, "{}", roles.join(","))
+    }
+}
+
 #[derive(Debug, Clone, sqlx::FromRow)]
 pub struct Participant {
-    pub id: i64,
     pub conversation_id: i64,
     pub user_id: i64,
     pub joined_at: chrono::DateTime<chrono::Utc>,
-    pub roles: String,
-    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[sqlx(try_from = "String")]
+    pub roles: ParticipantRoles,
+    /// Messages up to this one have been read by the participant
+    pub last_read_message_id: Option<i64>,
+    /// Messages up to this one have reached one of the participant's devices
+    pub last_delivered_message_id: Option<i64>,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
+
+/// Moves the participant's receipts up to `message_id`, reading a message delivers it as well
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReceiptRequest {
+    pub message_id: i64,
+}
+
+/// How far a participant has received and read a conversation, pushed to the other
+/// participants whenever it moves forward. Reading pushes a `receipt.updated` right away,
+/// receiving is pushed every second along with the rest of the conversation's receipts in a
+/// `receipts.updated`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReceiptResponse {
+    pub conversation_id: i64,
+    pub user_id: i64,
+    pub last_read_message_id: Option<i64>,
+    pub last_delivered_message_id: Option<i64>,
+}
+
+impl ReceiptResponse {
+    pub fn from(participant: Participant) -> Self {
+        Self {
+            conversation_id: participant.conversation_id,
+            user_id: participant.user_id,
+            last_read_message_id: participant.last_read_message_id,
+            last_delivered_message_id: participant.last_delivered_message_id,
+        }
+    }
+}
+
+/// Whether a message reached a participant or was also read by them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ReceiptStatus {
+    Delivered,
+    Read,
+}
+
+/// A participant other than the sender that a message reached
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct MessageReceipt {
+    pub user_id: i64,
+    pub username: String,
+    pub name: String,
+    pub last_read_message_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MessageReceiptResponse {
+    pub user_id: i64,
+    pub username: String,
+    pub name: String,
+    pub status: ReceiptStatus,
+}
+
+impl MessageReceiptResponse {
+    pub fn from(receipt: MessageReceipt, message_id: i64) -> Self {
+        let status = match receipt.last_read_message_id {
+            Some(last_read_message_id) if last_read_message_id >= message_id => ReceiptStatus::Read,
+            _ => ReceiptStatus::Delivered,
+        };
+
+        Self {
+            user_id: receipt.user_id,
+            username: receipt.username,
+            name: receipt.name,
+            status,
+        }
+    }
+}
@@ -1,24 +1,32 @@
-use crate::chat::participant::model::Participant;
-use crate::common::model::{Error, PageRequest, PageResponse};
+use crate::chat::participant::model::{MessageReceipt, Participant};
+use crate::common::model::Error;
 use axum::async_trait;
 use sqlx::{Pool, Postgres};
 use std::sync::Arc;
 
 #[async_trait]
 pub trait ConversationParticipantReadRepo {
-    async fn find_by_id(&self, id: i64) -> Result<Option<Participant>, Error>;
-
-    async fn find_by_conversation_id(
+    async fn exists_by_conversation_and_user(
         &self,
         conversation_id: i64,
-        req: PageRequest,
-    ) -> Result<PageResponse<Participant>, Error>;
+        user_id: i64,
+    ) -> Result<bool, Error>;
 
-    async fn exists_by_conversation_and_user(
+    async fn find_by_conversation_and_user(
         &self,
         conversation_id: i64,
         user_id: i64,
-    ) -> Result<bool, Error>;
+    ) -> Result<Option<Participant>, Error>;
+
+    async fn find_conversation_ids_by_user_id(&self, user_id: i64) -> Result<Vec<i64>, Error>;
+
+    /// Active participants other than the sender that the message was delivered to
+    async fn find_receipts_by_message(
+        &self,
+        conversation_id: i64,
+        message_id: i64,
+        sender_id: i64,
+    ) -> Result<Vec<MessageReceipt>, Error>;
 }
 
 pub struct ParticipantReadRepoPg {
@@ -33,80 +41,100 @@ impl ParticipantReadRepoPg {
 
 #[async_trait]
 impl ConversationParticipantReadRepo for ParticipantReadRepoPg {
-    async fn find_by_id(&self, id: i64) -> Result<Option<Participant>, Error> {
+    async fn exists_by_conversation_and_user(
+        &self,
+        conversation_id: i64,
+        user_id: i64,
+    ) -> Result<bool, Error> {
+        let query = r#"
+            SELECT EXISTS(
+                SELECT 
+                    1 
+                FROM 
+                    "conversation_participant" p
+                JOIN 
+                    "conversation" c ON c.id = p.conversation_id
+                WHERE 
+                    p.conversation_id = $1 AND p.user_id = $2 AND p.deleted_at IS NULL
+                    AND c.deleted_at IS NULL
+            )
+        "#;
+
+        sqlx::query_scalar(query)
+            .bind(conversation_id)
+            .bind(user_id)
+            .fetch_one(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn find_by_conversation_and_user(
+        &self,
+        conversation_id: i64,
+        user_id: i64,
+    ) -> Result<Option<Participant>, Error> {
         let query = r#"
             SELECT 
-                id, conversation_id, user_id, joined_at, roles, deleted_at, created_at
+                id, conversation_id, user_id, joined_at, roles, last_read_message_id,
+                last_delivered_message_id, deleted_at, created_at
             FROM 
                 "conversation_participant"
             WHERE 
-                id = $1 AND deleted_at IS NULL
+                conversation_id = $1 AND user_id = $2 AND deleted_at IS NULL
         "#;
 
         sqlx::query_as::<_, Participant>(query)
-            .bind(id)
+            .bind(conversation_id)
+            .bind(user_id)
             .fetch_optional(&*self.pool)
             .await
             .map_err(|e| Error::InternalServerError(e.to_string()))
     }
 
-    async fn find_by_conversation_id(
-        &self,
-        conversation_id: i64,
-        req: PageRequest,
-    ) -> Result<PageResponse<Participant>, Error> {
+    async fn find_conversation_ids_by_user_id(&self, user_id: i64) -> Result<Vec<i64>, Error> {
         let query = r#"
             SELECT 
-                id, conversation_id, user_id, joined_at, roles, deleted_at, created_at
+                conversation_id
             FROM 
                 "conversation_participant"
             WHERE 
-                deleted_at IS NULL AND conversation_id = $1 AND id < $2
-            ORDER BY 
-                id DESC
-            LIMIT 
-                $3
+                user_id = $1 AND deleted_at IS NULL
         "#;
 
-        let participants: Vec<Participant> = sqlx::query_as::<_, Participant>(query)
-            .bind(conversation_id)
-            .bind(req.cursor())
-            .bind(req.size())
+        sqlx::query_scalar(query)
+            .bind(user_id)
             .fetch_all(&*self.pool)
             .await
-            .map_err(|e| Error::InternalServerError(e.to_string()))?;
-
-        let next_cursor = participants.last().map(|p| p.id);
-
-        let page = PageResponse {
-            data: participants,
-            size: req.size(),
-            next_cursor,
-        };
-
-        Ok(page)
+            .map_err(|e| Error::InternalServerError(e.to_string()))
     }
 
-    async fn exists_by_conversation_and_user(
+    async fn find_receipts_by_message(
         &self,
         conversation_id: i64,
-        user_id: i64,
-    ) -> Result<bool, Error> {
+        message_id: i64,
+        sender_id: i64,
+    ) -> Result<Vec<MessageReceipt>, Error> {
         let query = r#"
-            SELECT EXISTS(
-                SELECT 
-                    1 
-                FROM 
-                    "conversation_participant"
-                WHERE 
-                    conversation_id = $1 AND user_id = $2 AND deleted_at IS NULL
-            )
+            SELECT 
+                p.user_id, u.username, u.name, p.last_read_message_id
+            FROM 
+                "conversation_participant" p
+            JOIN 
+                "user" u ON u.id = p.user_id
+            WHERE 
+                p.conversation_id = $1
+                AND p.user_id <> $3
+                AND p.deleted_at IS NULL
+                AND p.last_delivered_message_id >= $2
+            ORDER BY 
+                p.user_id
         "#;
 
-        sqlx::query_scalar(query)
+        sqlx::query_as::<_, MessageReceipt>(query)
             .bind(conversation_id)
-            .bind(user_id)
-            .fetch_one(&*self.pool)
+            .bind(message_id)
+            .bind(sender_id)
+            .fetch_all(&*self.pool)
             .await
             .map_err(|e| Error::InternalServerError(e.to_string()))
     }
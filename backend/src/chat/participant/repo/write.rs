@@ -1,18 +1,24 @@
 use crate::chat::participant::model::Participant;
+use crate::common::database::connection;
 use crate::common::model::Error;
 use axum::async_trait;
 use sqlx::{Pool, Postgres};
 use std::sync::Arc;
-use tracing::info;
-use crate::common::database::TRANSACTION;
 
 #[async_trait]
 pub trait ParticipantWriteRepo {
     async fn create(&self, participant: Participant) -> Result<Participant, Error>;
 
-    async fn update_roles(&self, participant_id: i64, roles: &str) -> Result<Participant, Error>;
-
-    async fn delete(&self, participant_id: i64) -> Result<(), Error>;
+    /// Moves the receipts of an active participant forward, to the conversation's latest message
+    /// at or before the given ids. `None` when the user isn't a participant or neither receipt
+    /// moved.
+    async fn update_receipts(
+        &self,
+        conversation_id: i64,
+        user_id: i64,
+        read_message_id: Option<i64>,
+        delivered_message_id: i64,
+    ) -> Result<Option<Participant>, Error>;
 }
 
 pub struct ParticipantWriteRepoPg {
@@ -34,71 +40,251 @@ impl ParticipantWriteRepo for ParticipantWriteRepoPg {
             ) VALUES (
                 $1, $2, $3, $4, $5
             )
-            RETURNING id, conversation_id, user_id, joined_at, roles, deleted_at, created_at
+            RETURNING id, conversation_id, user_id, joined_at, roles, last_read_message_id,
+                      last_delivered_message_id, deleted_at, created_at
         "#;
 
-
-        if let Some(mut tx) = TRANSACTION.with(|cell| cell.borrow_mut().take()) {
-            info!("p using tx");
-            sqlx::query_as::<_, Participant>(query)
-                .bind(participant.conversation_id)
-                .bind(participant.user_id)
-                .bind(participant.joined_at)
-                .bind(&participant.roles)
-                .bind(participant.created_at)
-                .fetch_one(&mut *tx)
-                .await
-                .map_err(|e| Error::InternalServerError(e.to_string()))
-        }else {
-            info!("p not using tx");
-            sqlx::query_as::<_, Participant>(query)
-                .bind(participant.conversation_id)
-                .bind(participant.user_id)
-                .bind(participant.joined_at)
-                .bind(&participant.roles)
-                .bind(participant.created_at)
-                .fetch_one(&*self.pool)
-                .await
-                .map_err(|e| Error::InternalServerError(e.to_string()))
-        }
+        sqlx::query_as::<_, Participant>(query)
+            .bind(participant.conversation_id)
+            .bind(participant.user_id)
+            .bind(participant.joined_at)
+            .bind(participant.roles.to_string())
+            .bind(participant.created_at)
+            .fetch_one(&mut *connection(&self.pool).await?)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
     }
 
-    async fn update_roles(&self, id: i64, roles: &str) -> Result<Participant, Error> {
+    async fn update_receipts(
+        &self,
+        conversation_id: i64,
+        user_id: i64,
+        read_message_id: Option<i64>,
+        delivered_message_id: i64,
+    ) -> Result<Option<Participant>, Error> {
         let query = r#"
             UPDATE 
-                "conversation_participant"
+                "conversation_participant" p
             SET 
-                roles = $1, 
-                deleted_at = NULL
+                last_read_message_id = GREATEST(p.last_read_message_id, r.read_id),
+                last_delivered_message_id = GREATEST(p.last_delivered_message_id, r.delivered_id)
+            FROM (
+                SELECT
+                    (SELECT MAX(m.id) FROM "message" m
+                     WHERE m.conversation_id = $1 AND m.id <= $3::BIGINT) AS read_id,
+                    (SELECT MAX(m.id) FROM "message" m
+                     WHERE m.conversation_id = $1 AND m.id <= $4) AS delivered_id
+            ) r
             WHERE 
-                id = $2
+                p.conversation_id = $1 AND p.user_id = $2 AND p.deleted_at IS NULL
+                AND (r.read_id > COALESCE(p.last_read_message_id, 0)
+                     OR r.delivered_id > COALESCE(p.last_delivered_message_id, 0))
             RETURNING 
-                id, conversation_id, user_id, joined_at, roles, deleted_at, created_at
+                p.id, p.conversation_id, p.user_id, p.joined_at, p.roles, p.last_read_message_id,
+                p.last_delivered_message_id, p.deleted_at, p.created_at
         "#;
 
-        sqlx::query_as::<_, Participant>(query)
-            .bind(roles)
-            .bind(id)
-            .fetch_one(&*self.pool)
+        let query = sqlx::query_as::<_, Participant>(query)
+            .bind(conversation_id)
+            .bind(user_id)
+            .bind(read_message_id)
+            .bind(delivered_message_id);
+
+        query
+            .fetch_optional(&mut *connection(&self.pool).await?)
             .await
             .map_err(|e| Error::InternalServerError(e.to_string()))
     }
+}
 
-    async fn delete(&self, participant_id: i64) -> Result<(), Error> {
-        let query = r#"
-            UPDATE 
-                "conversation_participant"
-            SET 
-                deleted_at = NOW()
-            WHERE 
-                id = $1
-        "#;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::database::TRANSACTION;
+    use sqlx::PgPool;
+    use std::future::Future;
+    use tokio::sync::Mutex;
 
-        sqlx::query(query)
-            .bind(participant_id)
-            .execute(&*self.pool)
-            .await
-            .map(|_| ())
-            .map_err(|e| Error::InternalServerError(e.to_string()))
+    const CONVERSATION_ID: i64 = 900_001;
+    const OTHER_CONVERSATION_ID: i64 = 900_002;
+    const USER_ID: i64 = 900_001;
+    const LEFT_USER_ID: i64 = 900_002;
+
+    /// Runs the test against the database at `TEST_DATABASE_URL` in a transaction that is rolled
+    /// back afterwards
+    async fn with_database<F, Fut>(test: F)
+    where
+        F: FnOnce(ParticipantWriteRepoPg) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let pool = Arc::new(PgPool::connect(&url).await.unwrap());
+        let tx = pool.begin().await.unwrap();
+
+        TRANSACTION
+            .scope(Arc::new(Mutex::new(Some(tx))), async {
+                execute(&pool, &format!(
+                    r#"
+                    INSERT INTO "user" (id, username, email, password, name, created_at, updated_at)
+                    VALUES
+                        ({user}, 'receipts_a', 'receipts_a@example.com', '-', 'A', now(), now()),
+                        ({left}, 'receipts_b', 'receipts_b@example.com', '-', 'B', now(), now());
+                    INSERT INTO "conversation" (id, author_id, type, last_activity_at, created_at, updated_at)
+                    VALUES
+                        ({conversation}, {user}, 'GROUP', now(), now(), now()),
+                        ({other}, {user}, 'GROUP', now(), now(), now());
+                    INSERT INTO "conversation_participant" (conversation_id, user_id, joined_at, roles, created_at, deleted_at)
+                    VALUES
+                        ({conversation}, {user}, now(), 'PARTICIPANT', now(), NULL),
+                        ({conversation}, {left}, now(), 'PARTICIPANT', now(), now());
+                    INSERT INTO "message" (id, conversation_id, sender_id, text, created_at, updated_at)
+                    VALUES
+                        (900001, {conversation}, {left}, 'a', now(), now()),
+                        (900002, {conversation}, {left}, 'b', now(), now()),
+                        (900004, {conversation}, {left}, 'c', now(), now()),
+                        (900005, {other}, {left}, 'd', now(), now());
+                    "#,
+                    user = USER_ID,
+                    left = LEFT_USER_ID,
+                    conversation = CONVERSATION_ID,
+                    other = OTHER_CONVERSATION_ID,
+                ))
+                .await;
+
+                test(ParticipantWriteRepoPg::new(Arc::clone(&pool))).await;
+            })
+            .await;
+    }
+
+    async fn execute(pool: &PgPool, query: &str) {
+        let mut conn = connection(pool).await.unwrap();
+        sqlx::raw_sql(query).execute(&mut *conn).await.unwrap();
+    }
+
+    /// Last read and delivered message ids of the test user
+    async fn receipts(repo: &ParticipantWriteRepoPg) -> (Option<i64>, Option<i64>) {
+        sqlx::query_as(
+            r#"
+            SELECT last_read_message_id, last_delivered_message_id
+            FROM "conversation_participant"
+            WHERE conversation_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(CONVERSATION_ID)
+        .bind(USER_ID)
+        .fetch_one(&mut *connection(&repo.pool).await.unwrap())
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn receipts_move_forward() {
+        with_database(|repo| async move {
+            let participant = repo
+                .update_receipts(CONVERSATION_ID, USER_ID, Some(900001), 900002)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(participant.last_read_message_id, Some(900001));
+            assert_eq!(participant.last_delivered_message_id, Some(900002));
+
+            let participant = repo
+                .update_receipts(CONVERSATION_ID, USER_ID, Some(900002), 900002)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(participant.last_read_message_id, Some(900002));
+            assert_eq!(participant.last_delivered_message_id, Some(900002));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn receipts_never_move_back() {
+        with_database(|repo| async move {
+            repo.update_receipts(CONVERSATION_ID, USER_ID, Some(900002), 900004)
+                .await
+                .unwrap()
+                .unwrap();
+
+            let participant = repo
+                .update_receipts(CONVERSATION_ID, USER_ID, Some(900001), 900001)
+                .await
+                .unwrap();
+
+            assert!(participant.is_none());
+            assert_eq!(receipts(&repo).await, (Some(900002), Some(900004)));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_lagging_read_keeps_delivered_moving() {
+        with_database(|repo| async move {
+            repo.update_receipts(CONVERSATION_ID, USER_ID, Some(900002), 900002)
+                .await
+                .unwrap()
+                .unwrap();
+
+            let participant = repo
+                .update_receipts(CONVERSATION_ID, USER_ID, Some(900001), 900004)
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(participant.last_read_message_id, Some(900002));
+            assert_eq!(participant.last_delivered_message_id, Some(900004));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn delivered_receipts_leave_the_read_receipt_alone() {
+        with_database(|repo| async move {
+            let participant = repo
+                .update_receipts(CONVERSATION_ID, USER_ID, None, 900002)
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(participant.last_read_message_id, None);
+            assert_eq!(participant.last_delivered_message_id, Some(900002));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn receipts_stop_at_the_conversations_latest_message() {
+        with_database(|repo| async move {
+            // 900003 does not exist and 900005 belongs to another conversation
+            let participant = repo
+                .update_receipts(CONVERSATION_ID, USER_ID, Some(900003), 900_005)
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(participant.last_read_message_id, Some(900002));
+            assert_eq!(participant.last_delivered_message_id, Some(900004));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn participants_who_left_get_no_receipts() {
+        with_database(|repo| async move {
+            let participant = repo
+                .update_receipts(CONVERSATION_ID, LEFT_USER_ID, Some(900002), 900002)
+                .await
+                .unwrap();
+
+            assert!(participant.is_none());
+        })
+        .await;
     }
 }
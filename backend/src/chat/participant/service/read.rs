@@ -0,0 +1,61 @@
+use crate::chat::participant::repo::read::ConversationParticipantReadRepo;
+use crate::common::model::Error;
+use std::future::Future;
+use std::sync::Arc;
+
+pub trait ParticipantReadService {
+    /// Fails with `Forbidden` unless the user is an active participant of the conversation
+    fn verify_participant(
+        &self,
+        conversation_id: i64,
+        user_id: i64,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn find_conversation_ids_by_user_id(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = Result<Vec<i64>, Error>> + Send;
+}
+
+pub struct ParticipantReadServiceImpl<R>
+where
+    R: ConversationParticipantReadRepo + Send + Sync + 'static,
+{
+    participant_read_repo: Arc<R>,
+}
+
+impl<R> ParticipantReadServiceImpl<R>
+where
+    R: ConversationParticipantReadRepo + Send + Sync + 'static,
+{
+    pub fn new(participant_read_repo: Arc<R>) -> Self {
+        Self {
+            participant_read_repo,
+        }
+    }
+}
+
+impl<R> ParticipantReadService for ParticipantReadServiceImpl<R>
+where
+    R: ConversationParticipantReadRepo + Send + Sync + 'static,
+{
+    async fn verify_participant(&self, conversation_id: i64, user_id: i64) -> Result<(), Error> {
+        let is_participant = self
+            .participant_read_repo
+            .exists_by_conversation_and_user(conversation_id, user_id)
+            .await?;
+        if !is_participant {
+            return Err(Error::Forbidden(
+                "You are not a participant of this conversation".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn find_conversation_ids_by_user_id(&self, user_id: i64) -> Result<Vec<i64>, Error> {
+        self.participant_read_repo
+            .find_conversation_ids_by_user_id(user_id)
+            .await
+    }
+}
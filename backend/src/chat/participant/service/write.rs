@@ -0,0 +1,113 @@
+use crate::chat::participant::model::{ReceiptRequest, ReceiptResponse};
+use crate::chat::participant::repo::read::ConversationParticipantReadRepo;
+use crate::chat::participant::repo::write::ParticipantWriteRepo;
+use crate::common::model::Error;
+use std::future::Future;
+use std::sync::Arc;
+
+pub trait ParticipantWriteService {
+    /// Marks messages up to `req.message_id` as read, and so delivered. `None` when the
+    /// receipts were already there.
+    fn mark_read(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        req: ReceiptRequest,
+    ) -> impl Future<Output = Result<Option<ReceiptResponse>, Error>> + Send;
+
+    /// Marks messages up to `req.message_id` as delivered. `None` when the receipt was already
+    /// there.
+    fn mark_delivered(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        req: ReceiptRequest,
+    ) -> impl Future<Output = Result<Option<ReceiptResponse>, Error>> + Send;
+}
+
+pub struct ParticipantWriteServiceImpl<R, W>
+where
+    R: ConversationParticipantReadRepo + Send + Sync + 'static,
+    W: ParticipantWriteRepo + Send + Sync + 'static,
+{
+    participant_read_repo: Arc<R>,
+    participant_write_repo: Arc<W>,
+}
+
+impl<R, W> ParticipantWriteServiceImpl<R, W>
+where
+    R: ConversationParticipantReadRepo + Send + Sync + 'static,
+    W: ParticipantWriteRepo + Send + Sync + 'static,
+{
+    pub fn new(participant_read_repo: Arc<R>, participant_write_repo: Arc<W>) -> Self {
+        Self {
+            participant_read_repo,
+            participant_write_repo,
+        }
+    }
+
+    async fn update_receipts(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        read_message_id: Option<i64>,
+        delivered_message_id: i64,
+    ) -> Result<Option<ReceiptResponse>, Error> {
+        let participant = self
+            .participant_write_repo
+            .update_receipts(
+                conversation_id,
+                user_id,
+                read_message_id,
+                delivered_message_id,
+            )
+            .await?;
+        if let Some(participant) = participant {
+            return Ok(Some(ReceiptResponse::from(participant)));
+        }
+
+        // Nothing was updated, tell apart receipts that are already there from outsiders
+        let is_participant = self
+            .participant_read_repo
+            .exists_by_conversation_and_user(conversation_id, user_id)
+            .await?;
+        if !is_participant {
+            return Err(Error::Forbidden(
+                "You are not a participant of this conversation".to_string(),
+            ));
+        }
+
+        Ok(None)
+    }
+}
+
+impl<R, W> ParticipantWriteService for ParticipantWriteServiceImpl<R, W>
+where
+    R: ConversationParticipantReadRepo + Send + Sync + 'static,
+    W: ParticipantWriteRepo + Send + Sync + 'static,
+{
+    async fn mark_read(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        req: ReceiptRequest,
+    ) -> Result<Option<ReceiptResponse>, Error> {
+        self.update_receipts(
+            user_id,
+            conversation_id,
+            Some(req.message_id),
+            req.message_id,
+        )
+        .await
+    }
+
+    async fn mark_delivered(
+        &self,
+        user_id: i64,
+        conversation_id: i64,
+        req: ReceiptRequest,
+    ) -> Result<Option<ReceiptResponse>, Error> {
+        self.update_receipts(user_id, conversation_id, None, req.message_id)
+            .await
+    }
+}
@@ -1,7 +1,15 @@
+use ipnet::IpNet;
 use std::env;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
 use std::time::Duration;
 
-#[derive(Debug, Clone)]
+/// Stands in for secrets when the configuration is logged
+const REDACTED: &str = "<redacted>";
+
+#[derive(Clone)]
 pub struct Config {
     pub port: u16,
     pub database_url: String,
@@ -9,13 +17,277 @@ pub struct Config {
     pub acquire_timeout: Duration,
     pub max_connections: u32,
     pub min_connections: u32,
-    pub access_token_key_secret: String,
+    pub access_token_key_secret: Option<String>,
+    pub jwt_keys_dir: Option<PathBuf>,
+    pub jwt_signing_key_id: Option<String>,
     pub refresh_token_key_secret: String,
+    pub email_token_key_secret: String,
+    /// Signs the challenge tokens of sign ins that still need the second factor
+    pub mfa_token_key_secret: String,
+    pub session_cache_ttl: Duration,
+    /// Reverse proxies whose `X-Forwarded-For` is believed, listed in `TRUSTED_PROXIES` as
+    /// addresses or CIDR ranges
+    pub trusted_proxies: Vec<IpNet>,
+    pub message_bus: MessageBusType,
+    pub mailer: MailerType,
+    pub smtp_url: Option<String>,
+    pub mail_from: String,
+    pub mail_dir: Option<PathBuf>,
+    /// Base URL of the web app, links in mails point there
+    pub app_url: String,
+    /// Whether accounts must verify their email before they can e.g. create conversations
+    pub require_verified_email: bool,
+    /// Issuer shown by authenticator apps next to the account
+    pub totp_issuer: String,
+    /// Identity providers users can sign in with, listed in `OAUTH_PROVIDERS`
+    pub oauth_providers: Vec<OAuthProviderConfig>,
+    /// Signs the `state` of social sign ins, required along with `OAUTH_PROVIDERS`
+    pub oauth_state_key_secret: String,
+    /// Account granted `SUPERADMIN` on startup, so a fresh install has someone to manage roles
+    pub superadmin_email: Option<String>,
+    pub blob_store: BlobStoreType,
+    /// Where the local blob store keeps uploads
+    pub blob_dir: PathBuf,
+    /// URL uploads are served from, defaults to this server for the local blob store and to
+    /// the bucket for S3
+    pub blob_public_url: Option<String>,
+    /// Where the local blob store keeps message attachments, outside of `blob_dir` so they are
+    /// only downloaded through the API
+    pub attachment_dir: PathBuf,
+    /// How long uploaded attachments wait to be sent with a message before they are removed
+    pub pending_attachment_ttl: Duration,
+    pub s3_endpoint: Option<String>,
+    pub s3_bucket: Option<String>,
+    /// Private bucket for message attachments
+    pub s3_attachment_bucket: Option<String>,
+    pub s3_region: String,
+    pub s3_access_key_id: Option<String>,
+    pub s3_secret_access_key: Option<String>,
+}
+
+/// Written out by hand so logging the configuration does not log its secrets
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("port", &self.port)
+            .field("database_url", &REDACTED)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("acquire_timeout", &self.acquire_timeout)
+            .field("max_connections", &self.max_connections)
+            .field("min_connections", &self.min_connections)
+            .field(
+                "access_token_key_secret",
+                &self.access_token_key_secret.as_ref().map(|_| REDACTED),
+            )
+            .field("jwt_keys_dir", &self.jwt_keys_dir)
+            .field("jwt_signing_key_id", &self.jwt_signing_key_id)
+            .field("refresh_token_key_secret", &REDACTED)
+            .field("email_token_key_secret", &REDACTED)
+            .field("mfa_token_key_secret", &REDACTED)
+            .field("session_cache_ttl", &self.session_cache_ttl)
+            .field("trusted_proxies", &self.trusted_proxies)
+            .field("message_bus", &self.message_bus)
+            .field("mailer", &self.mailer)
+            .field("smtp_url", &self.smtp_url.as_ref().map(|_| REDACTED))
+            .field("mail_from", &self.mail_from)
+            .field("mail_dir", &self.mail_dir)
+            .field("app_url", &self.app_url)
+            .field("require_verified_email", &self.require_verified_email)
+            .field("totp_issuer", &self.totp_issuer)
+            .field("oauth_providers", &self.oauth_providers)
+            .field("oauth_state_key_secret", &REDACTED)
+            .field("superadmin_email", &self.superadmin_email)
+            .field("blob_store", &self.blob_store)
+            .field("blob_dir", &self.blob_dir)
+            .field("blob_public_url", &self.blob_public_url)
+            .field("attachment_dir", &self.attachment_dir)
+            .field("pending_attachment_ttl", &self.pending_attachment_ttl)
+            .field("s3_endpoint", &self.s3_endpoint)
+            .field("s3_bucket", &self.s3_bucket)
+            .field("s3_attachment_bucket", &self.s3_attachment_bucket)
+            .field("s3_region", &self.s3_region)
+            .field("s3_access_key_id", &self.s3_access_key_id)
+            .field(
+                "s3_secret_access_key",
+                &self.s3_secret_access_key.as_ref().map(|_| REDACTED),
+            )
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum OAuthProviderKind {
+    /// Standard OpenID Connect user info
+    Oidc,
+    /// GitHub's user API, which is not OpenID Connect
+    Github,
+}
+
+impl FromStr for OAuthProviderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "oidc" => Ok(OAuthProviderKind::Oidc),
+            "github" => Ok(OAuthProviderKind::Github),
+            _ => Err(format!("unknown oauth provider kind: {}", s)),
+        }
+    }
+}
+
+/// An OAuth2 / OpenID Connect identity provider, read from `OAUTH_<NAME>_*`. `google` and
+/// `github` default to the public endpoints, every endpoint can be overridden, e.g. to point
+/// at a local mock provider.
+#[derive(Clone)]
+pub struct OAuthProviderConfig {
+    pub name: String,
+    pub kind: OAuthProviderKind,
+    pub client_id: String,
+    pub client_secret: String,
+    pub authorization_url: String,
+    pub token_url: String,
+    pub userinfo_url: String,
+    pub scopes: String,
+    /// Page of the web app the provider sends the user back to with the code
+    pub redirect_url: String,
+}
+
+impl fmt::Debug for OAuthProviderConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OAuthProviderConfig")
+            .field("name", &self.name)
+            .field("kind", &self.kind)
+            .field("client_id", &self.client_id)
+            .field("client_secret", &REDACTED)
+            .field("authorization_url", &self.authorization_url)
+            .field("token_url", &self.token_url)
+            .field("userinfo_url", &self.userinfo_url)
+            .field("scopes", &self.scopes)
+            .field("redirect_url", &self.redirect_url)
+            .finish()
+    }
+}
+
+impl OAuthProviderConfig {
+    fn init(name: &str, app_url: &str) -> Self {
+        let var = |key: &str| env::var(format!("OAUTH_{}_{}", name.to_uppercase(), key)).ok();
+        let (kind, authorization_url, token_url, userinfo_url, scopes) = match name {
+            "google" => (
+                OAuthProviderKind::Oidc,
+                "https://accounts.google.com/o/oauth2/v2/auth",
+                "https://oauth2.googleapis.com/token",
+                "https://openidconnect.googleapis.com/v1/userinfo",
+                "openid email profile",
+            ),
+            "github" => (
+                OAuthProviderKind::Github,
+                "https://github.com/login/oauth/authorize",
+                "https://github.com/login/oauth/access_token",
+                "https://api.github.com/user",
+                "read:user user:email",
+            ),
+            _ => (OAuthProviderKind::Oidc, "", "", "", "openid email profile"),
+        };
+        let endpoint = |key: &str, default: &str| {
+            var(key)
+                .or_else(|| Some(default.to_string()).filter(|url| !url.is_empty()))
+                .unwrap_or_else(|| panic!("OAUTH_{}_{} must be set", name.to_uppercase(), key))
+        };
+
+        OAuthProviderConfig {
+            name: name.to_string(),
+            kind: var("KIND")
+                .map(|v| {
+                    v.parse::<OAuthProviderKind>()
+                        .expect("OAUTH_<NAME>_KIND must be valid")
+                })
+                .unwrap_or(kind),
+            client_id: var("CLIENT_ID").expect("OAUTH_<NAME>_CLIENT_ID must be set"),
+            client_secret: var("CLIENT_SECRET").expect("OAUTH_<NAME>_CLIENT_SECRET must be set"),
+            authorization_url: endpoint("AUTHORIZATION_URL", authorization_url),
+            token_url: endpoint("TOKEN_URL", token_url),
+            userinfo_url: endpoint("USERINFO_URL", userinfo_url),
+            scopes: var("SCOPES").unwrap_or_else(|| scopes.to_string()),
+            redirect_url: var("REDIRECT_URL")
+                .unwrap_or_else(|| format!("{}/oauth/{}/callback", app_url, name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MessageBusType {
+    /// Single instance deployments, events never leave the process
+    Memory,
+    /// Horizontally scaled deployments, events are relayed through `LISTEN/NOTIFY`
+    Postgres,
+}
+
+impl FromStr for MessageBusType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "memory" => Ok(MessageBusType::Memory),
+            "postgres" => Ok(MessageBusType::Postgres),
+            _ => Err(format!("unknown message bus: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MailerType {
+    /// Local development, mails are only logged and optionally written to `MAIL_DIR`
+    Log,
+    /// Production, mails are sent through `SMTP_URL`
+    Smtp,
+}
+
+impl FromStr for MailerType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "log" => Ok(MailerType::Log),
+            "smtp" => Ok(MailerType::Smtp),
+            _ => Err(format!("unknown mailer: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BlobStoreType {
+    /// Single instance deployments, uploads are written to `BLOB_DIR` and served by this server
+    Local,
+    /// Uploads are put into an S3 compatible bucket, e.g. AWS S3 or MinIO
+    S3,
+}
+
+impl FromStr for BlobStoreType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "local" => Ok(BlobStoreType::Local),
+            "s3" => Ok(BlobStoreType::S3),
+            _ => Err(format!("unknown blob store: {}", s)),
+        }
+    }
 }
 
 impl Config {
     pub fn init() -> Self {
-        Config {
+        let app_url = env::var("APP_URL").unwrap_or_else(|_| "http://localhost:3000".to_string());
+        let oauth_providers: Vec<OAuthProviderConfig> = env::var("OAUTH_PROVIDERS")
+            .map(|v| {
+                v.split(',')
+                    .map(|name| name.trim().to_lowercase())
+                    .filter(|name| !name.is_empty())
+                    .map(|name| OAuthProviderConfig::init(&name, &app_url))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let config = Config {
             port: env::var("PORT")
                 .map(|v| v.parse::<u16>().unwrap_or(8080))
                 .expect("PORT must be set"),
@@ -34,10 +306,137 @@ impl Config {
             min_connections: env::var("MIN_CONNECTIONS")
                 .map(|v| v.parse::<u32>().unwrap_or(1))
                 .expect("MIN_CONNECTIONS must be set"),
-            access_token_key_secret: env::var("ACCESS_TOKEN_KEY")
-                .expect("ACCESS_TOKEN_KEY must be set"),
+            access_token_key_secret: env::var("ACCESS_TOKEN_KEY").ok(),
+            jwt_keys_dir: env::var("JWT_KEYS_DIR").map(PathBuf::from).ok(),
+            jwt_signing_key_id: env::var("JWT_SIGNING_KEY_ID").ok(),
             refresh_token_key_secret: env::var("REFRESH_TOKEN_KEY")
                 .expect("REFRESH_TOKEN_KEY must be set"),
+            email_token_key_secret: env::var("EMAIL_TOKEN_KEY")
+                .expect("EMAIL_TOKEN_KEY must be set"),
+            mfa_token_key_secret: env::var("MFA_TOKEN_KEY").expect("MFA_TOKEN_KEY must be set"),
+            session_cache_ttl: env::var("SESSION_CACHE_TTL")
+                .map(|v| v.parse::<u64>().unwrap_or(30))
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(30)),
+            trusted_proxies: env::var("TRUSTED_PROXIES")
+                .map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|proxy| !proxy.is_empty())
+                        .map(|proxy| {
+                            proxy
+                                .parse::<IpNet>()
+                                .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+                                .expect("TRUSTED_PROXIES must be valid")
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            message_bus: env::var("MESSAGE_BUS")
+                .map(|v| {
+                    v.parse::<MessageBusType>()
+                        .expect("MESSAGE_BUS must be valid")
+                })
+                .unwrap_or(MessageBusType::Memory),
+            mailer: env::var("MAILER")
+                .map(|v| v.parse::<MailerType>().expect("MAILER must be valid"))
+                .unwrap_or(MailerType::Log),
+            smtp_url: env::var("SMTP_URL").ok(),
+            mail_from: env::var("MAIL_FROM")
+                .unwrap_or_else(|_| "Medhia <no-reply@localhost>".to_string()),
+            mail_dir: env::var("MAIL_DIR").map(PathBuf::from).ok(),
+            oauth_state_key_secret: env::var("OAUTH_STATE_KEY")
+                .ok()
+                .or_else(|| oauth_providers.is_empty().then(String::new))
+                .expect("OAUTH_STATE_KEY must be set along with OAUTH_PROVIDERS"),
+            oauth_providers,
+            app_url,
+            superadmin_email: env::var("SUPERADMIN_EMAIL").ok(),
+            require_verified_email: env::var("REQUIRE_VERIFIED_EMAIL")
+                .map(|v| v.parse::<bool>().unwrap_or(false))
+                .unwrap_or(false),
+            totp_issuer: env::var("TOTP_ISSUER").unwrap_or_else(|_| "Medhia".to_string()),
+            blob_store: env::var("BLOB_STORE")
+                .map(|v| {
+                    v.parse::<BlobStoreType>()
+                        .expect("BLOB_STORE must be valid")
+                })
+                .unwrap_or(BlobStoreType::Local),
+            blob_dir: env::var("BLOB_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| PathBuf::from("uploads")),
+            blob_public_url: env::var("BLOB_PUBLIC_URL").ok(),
+            attachment_dir: env::var("ATTACHMENT_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| PathBuf::from("attachments")),
+            pending_attachment_ttl: env::var("PENDING_ATTACHMENT_TTL")
+                .map(|v| v.parse::<u64>().unwrap_or(24 * 60 * 60))
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(24 * 60 * 60)),
+            s3_endpoint: env::var("S3_ENDPOINT").ok(),
+            s3_bucket: env::var("S3_BUCKET").ok(),
+            s3_attachment_bucket: env::var("S3_ATTACHMENT_BUCKET").ok(),
+            s3_region: env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            s3_access_key_id: env::var("S3_ACCESS_KEY_ID").ok(),
+            s3_secret_access_key: env::var("S3_SECRET_ACCESS_KEY").ok(),
+        };
+
+        // Everything below `BLOB_DIR` is served without checking who asks
+        if matches!(config.blob_store, BlobStoreType::Local)
+            && is_below(&config.attachment_dir, &config.blob_dir)
+        {
+            panic!("ATTACHMENT_DIR must not be below BLOB_DIR");
+        }
+
+        config
+    }
+}
+
+/// Whether `path` is `dir` or below it. Compares the absolute paths with `.` and `..` resolved,
+/// as the directories need not exist yet.
+fn is_below(path: &Path, dir: &Path) -> bool {
+    normalize(path).starts_with(normalize(dir))
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
         }
     }
+
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attachment_dirs_below_the_blob_dir_are_caught() {
+        assert!(is_below(
+            Path::new("uploads/attachments"),
+            Path::new("uploads")
+        ));
+        assert!(is_below(Path::new("./uploads"), Path::new("uploads/")));
+        assert!(is_below(
+            Path::new("files/../uploads/a"),
+            Path::new("uploads")
+        ));
+        assert!(!is_below(Path::new("attachments"), Path::new("uploads")));
+        assert!(!is_below(
+            Path::new("uploads-private"),
+            Path::new("uploads")
+        ));
+        assert!(!is_below(
+            Path::new("/srv/attachments"),
+            Path::new("/srv/uploads")
+        ));
+    }
 }
@@ -1,11 +1,14 @@
 use crate::common::config::Config;
 use crate::common::model::Error;
+use sqlx::pool::PoolConnection;
 use sqlx::postgres::PgPoolOptions;
-use sqlx::{Acquire, Executor, PgPool, Pool, Postgres, Transaction};
-use std::cell::RefCell;
+use sqlx::{PgConnection, PgPool, Pool, Postgres, Transaction};
 use std::future::Future;
+use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMappedMutexGuard, OwnedMutexGuard};
 use tokio::task_local;
+use tracing::warn;
 
 pub(crate) struct Database {
     pub(crate) pool: Pool<Postgres>,
@@ -32,7 +35,69 @@ impl Database {
 }
 
 task_local! {
-    pub static TRANSACTION: RefCell<Option<Transaction<'static, Postgres>>>;
+    /// The transaction of the unit of work the task runs in. Statements borrow it through
+    /// [`connection`] and never take it out, so a cancelled statement leaves it in place for the
+    /// rest of the unit of work.
+    pub static TRANSACTION: Arc<Mutex<Option<Transaction<'static, Postgres>>>>;
+
+    /// What [`after_commit`] deferred until the unit of work the task runs in commits
+    static AFTER_COMMIT: Arc<std::sync::Mutex<Vec<Box<dyn FnOnce() + Send>>>>;
+}
+
+/// Runs `f` once the surrounding unit of work has committed, or right away outside of one. For
+/// changes outside the database, such as caches, that must not be made if it rolls back.
+pub fn after_commit(f: impl FnOnce() + Send + 'static) {
+    let mut f = Some(Box::new(f) as Box<dyn FnOnce() + Send>);
+    let _ = AFTER_COMMIT.try_with(|hooks| hooks.lock().unwrap().extend(f.take()));
+    if let Some(f) = f {
+        f();
+    }
+}
+
+/// A connection to run a single statement on, derefs to the connection itself
+pub enum Connection {
+    Transaction(
+        OwnedMappedMutexGuard<
+            Option<Transaction<'static, Postgres>>,
+            Transaction<'static, Postgres>,
+        >,
+    ),
+    Pool(Box<PoolConnection<Postgres>>),
+}
+
+impl Deref for Connection {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Connection::Transaction(tx) => tx,
+            Connection::Pool(conn) => conn,
+        }
+    }
+}
+
+impl DerefMut for Connection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Connection::Transaction(tx) => tx,
+            Connection::Pool(conn) => conn,
+        }
+    }
+}
+
+/// The transaction of the surrounding unit of work, or a connection from the pool outside of one
+pub async fn connection(pool: &PgPool) -> Result<Connection, Error> {
+    let Ok(cell) = TRANSACTION.try_with(Arc::clone) else {
+        return pool
+            .acquire()
+            .await
+            .map(|conn| Connection::Pool(Box::new(conn)))
+            .map_err(|e| Error::InternalServerError(e.to_string()));
+    };
+
+    OwnedMutexGuard::try_map(cell.lock_owned().await, Option::as_mut)
+        .map(Connection::Transaction)
+        .map_err(|_| Error::InternalServerError("The unit of work has already ended".to_string()))
 }
 
 pub trait UnitOfWork {
@@ -58,28 +123,115 @@ impl UnitOfWork for UnitOfWorkPg {
         F: Future<Output = Result<R, Error>> + Send,
         R: Send,
     {
-        let tx = self.pool.begin().await.unwrap();
+        let tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+        let cell = Arc::new(Mutex::new(Some(tx)));
+        let hooks = Arc::new(std::sync::Mutex::new(Vec::new()));
 
         let result = TRANSACTION
-            .scope(RefCell::new(Some(tx)), async {
-                let result = f.await;
-
-                match result {
-                    Ok(_) => {
-                        if let Some(tx) = TRANSACTION.with(|cell| cell.borrow_mut().take()) {
-                            tx.commit().await.unwrap();
-                        }
-                    }
-                    Err(_) => {
-                        if let Some(tx) = TRANSACTION.with(|cell| cell.borrow_mut().take()) {
-                            tx.rollback().await.unwrap();
-                        }
-                    }
+            .scope(Arc::clone(&cell), AFTER_COMMIT.scope(Arc::clone(&hooks), f))
+            .await;
+
+        let Some(tx) = cell.lock().await.take() else {
+            return result;
+        };
+        match result {
+            Ok(value) => {
+                tx.commit()
+                    .await
+                    .map_err(|e| Error::InternalServerError(e.to_string()))?;
+                for hook in std::mem::take(&mut *hooks.lock().unwrap()) {
+                    hook();
+                }
+                Ok(value)
+            }
+            Err(error) => {
+                // Dropping the transaction rolls it back as well, failing to do so explicitly
+                // only means the connection is closed
+                if let Err(rollback_error) = tx.rollback().await {
+                    warn!(%rollback_error, "Failed to roll back a unit of work");
                 }
-                result
+                Err(error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn cancelled_statements_keep_the_transaction() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let pool = Arc::new(PgPool::connect(&url).await.unwrap());
+        let unit_of_work = UnitOfWorkPg::new(Arc::clone(&pool));
+
+        let result = unit_of_work
+            .run(async {
+                let mut conn = connection(&pool).await?;
+                sqlx::query("CREATE TEMPORARY TABLE unit_of_work_test (id INT)")
+                    .execute(&mut *conn)
+                    .await
+                    .unwrap();
+                drop(conn);
+
+                let slow = async {
+                    let mut conn = connection(&pool).await?;
+                    sqlx::query("SELECT pg_sleep(1)")
+                        .execute(&mut *conn)
+                        .await
+                        .map_err(|e| Error::InternalServerError(e.to_string()))
+                };
+                let cancelled = tokio::time::timeout(Duration::from_millis(50), slow).await;
+                assert!(cancelled.is_err());
+
+                // Only visible on the connection of the transaction that created the table
+                let mut conn = connection(&pool).await?;
+                sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM unit_of_work_test")
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(|e| Error::InternalServerError(e.to_string()))
+            })
+            .await;
+
+        assert_eq!(result.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn after_commit_waits_for_the_commit() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let pool = Arc::new(PgPool::connect(&url).await.unwrap());
+        let unit_of_work = UnitOfWorkPg::new(Arc::clone(&pool));
+        let ran = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let hook = |name: &'static str| {
+            let ran = Arc::clone(&ran);
+            move || ran.lock().unwrap().push(name)
+        };
+        unit_of_work
+            .run(async {
+                after_commit(hook("committed"));
+                assert!(ran.lock().unwrap().is_empty());
+                Ok(())
+            })
+            .await
+            .unwrap();
+        let result = unit_of_work
+            .run(async {
+                after_commit(hook("rolled back"));
+                Err::<(), _>(Error::BadRequest("rolled back".to_string()))
             })
             .await;
+        after_commit(hook("outside"));
 
-        result
+        assert!(result.is_err());
+        assert_eq!(*ran.lock().unwrap(), ["committed", "outside"]);
     }
 }
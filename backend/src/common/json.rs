@@ -26,12 +26,10 @@ where
         code: u16,
         message: String,
     ) -> (StatusCode, Json<ApiResponse<Option<T>>>) {
-        let result = match self {
+        match self {
             Ok(data) => extract_success(code, data, message),
             Err(error) => extract_error(error),
-        };
-
-        result
+        }
     }
 
     fn into_json(self) -> (StatusCode, Json<ApiResponse<Option<T>>>) {
@@ -78,14 +76,7 @@ fn extract_error<T>(error: Error) -> (StatusCode, Json<ApiResponse<Option<T>>>)
 where
     T: Serialize,
 {
-    let (status, message) = match error {
-        Error::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
-        Error::UnAuthorized(message) => (StatusCode::UNAUTHORIZED, message),
-        Error::Forbidden(message) => (StatusCode::FORBIDDEN, message),
-        Error::NotFound(message) => (StatusCode::NOT_FOUND, message),
-        Error::Conflict(message) => (StatusCode::CONFLICT, message),
-        Error::InternalServerError(message) => (StatusCode::INTERNAL_SERVER_ERROR, message),
-    };
+    let (status, message) = split_error(error);
 
     (
         status,
@@ -96,3 +87,15 @@ where
         }),
     )
 }
+
+pub fn split_error(error: Error) -> (StatusCode, String) {
+    match error {
+        Error::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+        Error::UnAuthorized(message) => (StatusCode::UNAUTHORIZED, message),
+        Error::Forbidden(message) => (StatusCode::FORBIDDEN, message),
+        Error::NotFound(message) => (StatusCode::NOT_FOUND, message),
+        Error::Conflict(message) => (StatusCode::CONFLICT, message),
+        Error::TooManyRequests(message) => (StatusCode::TOO_MANY_REQUESTS, message),
+        Error::InternalServerError(message) => (StatusCode::INTERNAL_SERVER_ERROR, message),
+    }
+}
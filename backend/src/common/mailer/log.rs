@@ -0,0 +1,47 @@
+use crate::common::mailer::{Mail, Mailer};
+use crate::common::model::Error;
+use chrono::Utc;
+use std::path::PathBuf;
+use tracing::info;
+
+/// Local development mailer, mails are logged and, when `dir` is set, also written there as one
+/// file per mail so tests and developers can pick up the links they contain
+pub struct MailerLog {
+    dir: Option<PathBuf>,
+}
+
+impl MailerLog {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self { dir }
+    }
+}
+
+impl Mailer for MailerLog {
+    async fn send(&self, mail: Mail) -> Result<(), Error> {
+        info!(to = %mail.to, subject = %mail.subject, body = %mail.body, "Sending mail");
+
+        if let Some(dir) = &self.dir {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+            let name = format!(
+                "{}-{}.txt",
+                Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+                mail.to.replace(
+                    |c: char| !c.is_ascii_alphanumeric() && c != '@' && c != '.',
+                    "_"
+                )
+            );
+            let content = format!(
+                "To: {}\nSubject: {}\n\n{}\n",
+                mail.to, mail.subject, mail.body
+            );
+            tokio::fs::write(dir.join(name), content)
+                .await
+                .map_err(|e| Error::InternalServerError(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+}
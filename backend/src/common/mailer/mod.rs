@@ -0,0 +1,45 @@
+pub mod log;
+pub mod smtp;
+
+use crate::common::config::{Config, MailerType};
+use crate::common::mailer::log::MailerLog;
+use crate::common::mailer::smtp::MailerSmtp;
+use crate::common::model::Error;
+use std::future::Future;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers plain text mails to users
+pub trait Mailer: Send + Sync {
+    fn send(&self, mail: Mail) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+/// The mailer implementation selected by `Config::mailer`
+pub enum ConfiguredMailer {
+    Log(MailerLog),
+    Smtp(MailerSmtp),
+}
+
+impl ConfiguredMailer {
+    pub fn init(config: Arc<Config>) -> Result<Self, Error> {
+        match config.mailer {
+            MailerType::Log => Ok(Self::Log(MailerLog::new(config.mail_dir.clone()))),
+            MailerType::Smtp => Ok(Self::Smtp(MailerSmtp::init(&config)?)),
+        }
+    }
+}
+
+impl Mailer for ConfiguredMailer {
+    async fn send(&self, mail: Mail) -> Result<(), Error> {
+        match self {
+            Self::Log(mailer) => mailer.send(mail).await,
+            Self::Smtp(mailer) => mailer.send(mail).await,
+        }
+    }
+}
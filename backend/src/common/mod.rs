@@ -1,5 +1,8 @@
 pub mod config;
 pub mod database;
 pub mod json;
+pub mod mailer;
 pub mod model;
+pub mod photo;
 pub mod state;
+pub mod storage;
@@ -1,13 +1,18 @@
-use std::fmt::{Display, Formatter};
 use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use validator::Validate;
 
 #[derive(Serialize, Deserialize, Debug)]
+// Variants are named after the HTTP status each one is answered with, so `InternalServerError`
+// keeps the word its status has in common with the enum
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     BadRequest(String),
     UnAuthorized(String),
     Forbidden(String),
     NotFound(String),
     Conflict(String),
+    TooManyRequests(String),
     InternalServerError(String),
 }
 
@@ -17,34 +22,31 @@ impl Display for Error {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Validate)]
 pub struct PageRequest {
     pub cursor: Option<i64>,
+    #[validate(range(min = 1, max = 50, message = "Size must be between 1 and 50."))]
     pub size: Option<i32>,
 }
 
 impl PageRequest {
     pub fn size(&self) -> i32 {
-        if let Some(size) = self.size {
-            size
-        } else {
-            10
-        }
+        self.size.unwrap_or(10)
     }
 
     pub fn cursor(&self) -> i64 {
-        if let Some(cursor) = self.cursor {
-            cursor
-        } else {
-            i64::MAX
-        }
+        self.cursor.unwrap_or(i64::MAX)
     }
 }
 
+/// A page of results, `next_cursor` is the cursor of the following page. Lists ordered by id
+/// page by the last id, the default `C = i64` that `PageRequest` takes. Lists in another order
+/// page by their sort columns plus the id, in a cursor type of their own that is sent as an
+/// opaque string, see `InboxCursor` and `UserSearchCursor`.
 #[derive(Serialize, Deserialize)]
-pub struct PageResponse<T> {
+pub struct PageResponse<T, C = i64> {
     pub data: Vec<T>,
-    pub next_cursor: Option<i64>,
+    pub next_cursor: Option<C>,
     pub size: i32,
 }
 
@@ -54,3 +56,23 @@ pub struct ApiResponse<T> {
     pub status: u16,
     pub message: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(size: Option<i32>) -> PageRequest {
+        PageRequest { cursor: None, size }
+    }
+
+    #[test]
+    fn page_sizes_are_bounded() {
+        assert!(page(None).validate().is_ok());
+        assert!(page(Some(1)).validate().is_ok());
+        assert!(page(Some(50)).validate().is_ok());
+
+        assert!(page(Some(0)).validate().is_err());
+        assert!(page(Some(-1)).validate().is_err());
+        assert!(page(Some(51)).validate().is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use crate::common::model::Error;
+use crate::common::storage::BlobStore;
+use axum::extract::Multipart;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits, Rgb, RgbImage};
+use rand::RngCore;
+use std::io::Cursor;
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+/// Largest photo accepted for upload
+pub const MAX_PHOTO_SIZE: usize = 5 * 1024 * 1024;
+
+/// Body limit of photo uploads, the photo plus room for the multipart framing
+pub const MAX_PHOTO_UPLOAD_SIZE: usize = MAX_PHOTO_SIZE + 64 * 1024;
+
+/// Longest side of a stored photo, larger ones are scaled down
+const PHOTO_SIZE: u32 = 1024;
+
+/// Side of the square thumbnails
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// Guards against images that are small files but decode into huge bitmaps, a photo at the
+/// limit takes 64 MB as RGBA
+const MAX_PHOTO_DIMENSION: u32 = 4096;
+
+/// Memory a single decode may allocate
+const MAX_DECODE_ALLOC: u64 = 80 * 1024 * 1024;
+
+/// Photos decoded at the same time, bounding the memory uploads can take all at once
+static DECODE_PERMITS: Semaphore = Semaphore::const_new(2);
+
+const JPEG_QUALITY: u8 = 85;
+
+/// An uploaded photo, re-encoded as JPEG in its stored size and as a thumbnail
+pub struct Photo {
+    pub image: Vec<u8>,
+    pub thumbnail: Vec<u8>,
+}
+
+impl Photo {
+    pub const CONTENT_TYPE: &'static str = "image/jpeg";
+
+    /// Validates an uploaded JPEG, PNG, WebP or GIF and scales it down. Re-encoding also drops
+    /// metadata like the location in EXIF.
+    pub async fn process(bytes: Vec<u8>) -> Result<Photo, Error> {
+        let _permit = DECODE_PERMITS
+            .acquire()
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+        tokio::task::spawn_blocking(move || Self::process_blocking(&bytes))
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?
+    }
+
+    fn process_blocking(bytes: &[u8]) -> Result<Photo, Error> {
+        if bytes.len() > MAX_PHOTO_SIZE {
+            return Err(Error::BadRequest(format!(
+                "Photo must be at most {} MB",
+                MAX_PHOTO_SIZE / 1024 / 1024
+            )));
+        }
+        let format = image::guess_format(bytes)
+            .ok()
+            .filter(|format| {
+                matches!(
+                    format,
+                    ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP | ImageFormat::Gif
+                )
+            })
+            .ok_or_else(|| {
+                Error::BadRequest("Photo must be a JPEG, PNG, WebP or GIF image".to_string())
+            })?;
+
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(MAX_PHOTO_DIMENSION);
+        limits.max_image_height = Some(MAX_PHOTO_DIMENSION);
+        limits.max_alloc = Some(MAX_DECODE_ALLOC);
+        let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+        reader.limits(limits);
+        let image = reader
+            .decode()
+            .map_err(|_| Error::BadRequest("Photo could not be read".to_string()))?;
+
+        let thumbnail = image.resize_to_fill(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Lanczos3);
+        let image = if image.width() > PHOTO_SIZE || image.height() > PHOTO_SIZE {
+            image.resize(PHOTO_SIZE, PHOTO_SIZE, FilterType::Lanczos3)
+        } else {
+            image
+        };
+
+        Ok(Photo {
+            image: encode_jpeg(&image)?,
+            thumbnail: encode_jpeg(&thumbnail)?,
+        })
+    }
+
+    /// Stores the photo and its thumbnail below `prefix` under a new name, so no cache ever
+    /// serves a replaced photo. Returns the URLs of both.
+    pub async fn store<B: BlobStore>(
+        self,
+        blob_store: &B,
+        prefix: &str,
+    ) -> Result<(String, String), Error> {
+        let mut name = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut name);
+        let name = URL_SAFE_NO_PAD.encode(name);
+
+        let photo_key = format!("{}/{}.jpg", prefix, name);
+        let thumbnail_key = format!("{}/{}_thumb.jpg", prefix, name);
+        let url = |key: &str| {
+            blob_store.url(key).ok_or_else(|| {
+                Error::InternalServerError("Photos need a public blob store".to_string())
+            })
+        };
+        let photo_url = url(&photo_key)?;
+        let thumbnail_url = url(&thumbnail_key)?;
+
+        blob_store
+            .put(&photo_key, Self::CONTENT_TYPE, self.image)
+            .await?;
+        if let Err(err) = blob_store
+            .put(&thumbnail_key, Self::CONTENT_TYPE, self.thumbnail)
+            .await
+        {
+            Self::delete(blob_store, &photo_key).await;
+            return Err(err);
+        }
+
+        Ok((photo_url, thumbnail_url))
+    }
+
+    /// Removes a replaced photo, or a stored one that never made it into the database. Failures
+    /// are only logged, the caller already succeeded or failed for another reason. URLs outside
+    /// the blob store, e.g. avatars of identity providers, are left alone.
+    pub async fn remove<B: BlobStore>(blob_store: &B, urls: &[Option<String>]) {
+        for key in urls
+            .iter()
+            .flatten()
+            .filter_map(|url| blob_store.key_of(url))
+        {
+            Self::delete(blob_store, &key).await;
+        }
+    }
+
+    async fn delete<B: BlobStore>(blob_store: &B, key: &str) {
+        if let Err(err) = blob_store.delete(key).await {
+            warn!(error = %err, key, "Failed to remove photo");
+        }
+    }
+}
+
+/// Reads the `photo` field of a multipart upload
+pub async fn read_photo(mut multipart: Multipart) -> Result<Vec<u8>, Error> {
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| Error::BadRequest(e.body_text()))?
+    {
+        if field.name() == Some("photo") {
+            return field
+                .bytes()
+                .await
+                .map(|bytes| bytes.to_vec())
+                .map_err(|e| Error::BadRequest(e.body_text()));
+        }
+    }
+
+    Err(Error::BadRequest("Photo is missing".to_string()))
+}
+
+/// JPEG has no alpha channel, transparent parts are put on white
+fn encode_jpeg(image: &DynamicImage) -> Result<Vec<u8>, Error> {
+    let rgba = image.to_rgba8();
+    let rgb = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u16 * a as u16 + 255 * (255 - a as u16)) / 255) as u8;
+        Rgb([blend(r), blend(g), blend(b)])
+    });
+
+    let mut bytes = Vec::new();
+    rgb.write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY))
+        .map_err(|e| Error::InternalServerError(e.to_string()))?;
+
+    Ok(bytes)
+}
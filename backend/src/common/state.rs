@@ -1,7 +1,13 @@
+use crate::auth::repo::{RoleReadRepoPg, SessionReadRepoPg, SessionWriteRepoPg};
 use crate::auth::service::AuthReadServiceImpl;
+use crate::common::config::Config;
+use crate::user::repo::UserReadRepoPg;
 use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub auth_read_service: Arc<AuthReadServiceImpl>,
+    pub auth_read_service: Arc<
+        AuthReadServiceImpl<SessionReadRepoPg, UserReadRepoPg, RoleReadRepoPg, SessionWriteRepoPg>,
+    >,
+    pub config: Arc<Config>,
 }
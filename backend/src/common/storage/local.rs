@@ -0,0 +1,168 @@
+use crate::common::config::Config;
+use crate::common::model::Error;
+use crate::common::storage::{key_below, BlobStore, BlobStream};
+use axum::body::Bytes;
+use futures_util::{stream, StreamExt};
+use std::path::{Component, Path, PathBuf};
+use tokio::io::AsyncReadExt;
+
+/// Path this server serves the local blob store's directory at
+pub const LOCAL_BLOB_PATH: &str = "/uploads";
+
+/// Size of the chunks blobs are read in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Single instance blob store, blobs are files below `dir`. The server serves the public
+/// store's directory itself at `LOCAL_BLOB_PATH`.
+pub struct BlobStoreLocal {
+    dir: PathBuf,
+    public_url: Option<String>,
+}
+
+impl BlobStoreLocal {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            dir: config.blob_dir.clone(),
+            public_url: Some(
+                config
+                    .blob_public_url
+                    .clone()
+                    .unwrap_or_else(|| {
+                        format!("http://localhost:{}{}", config.port, LOCAL_BLOB_PATH)
+                    })
+                    .trim_end_matches('/')
+                    .to_string(),
+            ),
+        }
+    }
+
+    /// Keeps blobs in `attachment_dir`, which must not be below `blob_dir` to stay unserved
+    pub fn private(config: &Config) -> Self {
+        Self {
+            dir: config.attachment_dir.clone(),
+            public_url: None,
+        }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, Error> {
+        let is_relative = Path::new(key)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if key.is_empty() || !is_relative {
+            return Err(Error::InternalServerError(format!(
+                "Invalid blob key {}",
+                key
+            )));
+        }
+
+        Ok(self.dir.join(key))
+    }
+}
+
+impl BlobStore for BlobStoreLocal {
+    async fn put(&self, key: &str, _content_type: &str, bytes: Vec<u8>) -> Result<(), Error> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| Error::InternalServerError(e.to_string()))?;
+        }
+        tokio::fs::write(&path, bytes)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<BlobStream>, Error> {
+        let file = match tokio::fs::File::open(self.path(key)?).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::InternalServerError(e.to_string())),
+        };
+
+        let chunks = stream::try_unfold(file, |mut file| async move {
+            let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+            if file.read_buf(&mut chunk).await? == 0 {
+                return Ok(None);
+            }
+
+            Ok(Some((Bytes::from(chunk), file)))
+        });
+
+        Ok(Some(chunks.boxed()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::InternalServerError(e.to_string())),
+        }
+    }
+
+    fn url(&self, key: &str) -> Option<String> {
+        self.public_url
+            .as_ref()
+            .map(|public_url| format!("{}/{}", public_url, key))
+    }
+
+    fn key_of(&self, url: &str) -> Option<String> {
+        key_below(self.public_url.as_deref(), url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::TryStreamExt;
+
+    fn store() -> BlobStoreLocal {
+        BlobStoreLocal {
+            dir: std::env::temp_dir().join(format!("blobs-{}", rand::random::<u64>())),
+            public_url: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_a_stored_blob_back_in_chunks() {
+        let store = store();
+        let bytes = (0..CHUNK_SIZE * 2 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        store
+            .put(
+                "conversations/1/blob",
+                "application/octet-stream",
+                bytes.clone(),
+            )
+            .await
+            .unwrap();
+
+        let chunks = store
+            .get("conversations/1/blob")
+            .await
+            .unwrap()
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks.concat(), bytes);
+        std::fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn finds_no_blob_under_an_unknown_key() {
+        assert!(store()
+            .get("conversations/1/missing")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_keys_leaving_the_directory() {
+        assert!(store().get("../secret").await.is_err());
+        assert!(store().get("/etc/passwd").await.is_err());
+    }
+}
@@ -0,0 +1,108 @@
+pub mod local;
+pub mod s3;
+
+use crate::common::config::{BlobStoreType, Config};
+use crate::common::model::Error;
+use crate::common::storage::local::BlobStoreLocal;
+use crate::common::storage::s3::BlobStoreS3;
+use axum::body::Bytes;
+use futures_util::stream::BoxStream;
+use std::future::Future;
+use std::sync::Arc;
+
+/// Contents of a blob, in chunks as they are read
+pub type BlobStream = BoxStream<'static, Result<Bytes, std::io::Error>>;
+
+/// Stores uploaded files under server generated keys, e.g. `users/1/photo.jpg`. Public stores
+/// serve them at URLs, private ones only hand them back through `get`.
+pub trait BlobStore: Send + Sync {
+    /// Stores the blob, replacing any under the same key
+    fn put(
+        &self,
+        key: &str,
+        content_type: &str,
+        bytes: Vec<u8>,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Contents of the blob, none when there is no blob under the key. They are streamed so
+    /// large blobs never have to fit in memory.
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<BlobStream>, Error>> + Send;
+
+    fn delete(&self, key: &str) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Public URL of the blob, none for private stores
+    fn url(&self, key: &str) -> Option<String>;
+
+    /// Key of the blob at a public URL, none when the URL does not point into this store
+    fn key_of(&self, url: &str) -> Option<String>;
+}
+
+/// The blob store implementation selected by `Config::blob_store`
+pub enum ConfiguredBlobStore {
+    Local(BlobStoreLocal),
+    S3(BlobStoreS3),
+}
+
+impl ConfiguredBlobStore {
+    /// Store for photos, readable by anyone at the URLs it returns
+    pub fn init(config: Arc<Config>) -> Result<Self, Error> {
+        match config.blob_store {
+            BlobStoreType::Local => Ok(Self::Local(BlobStoreLocal::new(&config))),
+            BlobStoreType::S3 => Ok(Self::S3(BlobStoreS3::init(&config)?)),
+        }
+    }
+
+    /// Store for message attachments, which only participants of the conversation may download
+    /// through the API
+    pub fn init_private(config: Arc<Config>) -> Result<Self, Error> {
+        match config.blob_store {
+            BlobStoreType::Local => Ok(Self::Local(BlobStoreLocal::private(&config))),
+            BlobStoreType::S3 => Ok(Self::S3(BlobStoreS3::init_private(&config)?)),
+        }
+    }
+}
+
+impl BlobStore for ConfiguredBlobStore {
+    async fn put(&self, key: &str, content_type: &str, bytes: Vec<u8>) -> Result<(), Error> {
+        match self {
+            Self::Local(store) => store.put(key, content_type, bytes).await,
+            Self::S3(store) => store.put(key, content_type, bytes).await,
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<BlobStream>, Error> {
+        match self {
+            Self::Local(store) => store.get(key).await,
+            Self::S3(store) => store.get(key).await,
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        match self {
+            Self::Local(store) => store.delete(key).await,
+            Self::S3(store) => store.delete(key).await,
+        }
+    }
+
+    fn url(&self, key: &str) -> Option<String> {
+        match self {
+            Self::Local(store) => store.url(key),
+            Self::S3(store) => store.url(key),
+        }
+    }
+
+    fn key_of(&self, url: &str) -> Option<String> {
+        match self {
+            Self::Local(store) => store.key_of(url),
+            Self::S3(store) => store.key_of(url),
+        }
+    }
+}
+
+/// Key of the blob at `url` below `public_url`
+fn key_below(public_url: Option<&str>, url: &str) -> Option<String> {
+    url.strip_prefix(public_url?)?
+        .strip_prefix('/')
+        .filter(|key| !key.is_empty())
+        .map(str::to_string)
+}
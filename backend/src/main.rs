@@ -3,31 +3,63 @@ mod chat;
 mod common;
 mod user;
 
+use crate::auth::cache::SessionCache;
 use crate::auth::handler::AuthHandler;
-use crate::auth::service::{AuthReadServiceImpl, AuthWriteServiceImpl};
+use crate::auth::keys::KeySet;
+use crate::auth::oauth::OAuthClient;
+use crate::auth::repo::{
+    PasswordResetWriteRepoPg, RoleReadRepoPg, RoleWriteRepoPg, SessionReadRepoPg,
+    SessionWriteRepoPg, SignInAttemptWriteRepoPg, TotpReadRepoPg, TotpWriteRepoPg,
+    UserIdentityReadRepoPg, UserIdentityWriteRepoPg,
+};
+use crate::auth::service::{
+    AuthReadServiceImpl, AuthWriteServiceImpl, MfaServiceImpl, OAuthServiceImpl, RoleService,
+    RoleServiceImpl,
+};
+use crate::auth::throttle::SignInThrottle;
+use crate::chat::attachment::handler::AttachmentHandler;
+use crate::chat::attachment::repo::read::AttachmentReadRepoPg;
+use crate::chat::attachment::repo::write::AttachmentWriteRepoPg;
+use crate::chat::attachment::service::read::AttachmentReadServiceImpl;
+use crate::chat::attachment::service::write::{AttachmentWriteService, AttachmentWriteServiceImpl};
 use crate::chat::conversation::handler::ConversationHandler;
 use crate::chat::conversation::repo::read::ConversationReadRepoPg;
 use crate::chat::conversation::repo::write::ConversationWriteRepoPg;
 use crate::chat::conversation::service::read::ConversationReadServiceImpl;
 use crate::chat::conversation::service::write::ConversationWriteServiceImpl;
+use crate::chat::message::bus::ConfiguredMessageBus;
 use crate::chat::message::handler::MessageHandler;
+use crate::chat::message::repo::read::PostgresMessageReadRepo;
+use crate::chat::message::repo::write::PostgresMessageWriteRepo;
+use crate::chat::message::service::read::MessageReadServiceImpl;
+use crate::chat::message::service::write::MessageWriteServiceImpl;
 use crate::chat::participant::repo::read::ParticipantReadRepoPg;
 use crate::chat::participant::repo::write::ParticipantWriteRepoPg;
-use crate::common::config::Config;
+use crate::chat::participant::service::read::ParticipantReadServiceImpl;
+use crate::chat::participant::service::write::ParticipantWriteServiceImpl;
+use crate::common::config::{BlobStoreType, Config};
 use crate::common::database::{Database, UnitOfWorkPg};
+use crate::common::mailer::ConfiguredMailer;
 use crate::common::state::AppState;
+use crate::common::storage::local::LOCAL_BLOB_PATH;
+use crate::common::storage::ConfiguredBlobStore;
 use crate::user::handler::UserHandler;
+use crate::user::repo::ModerationLogWriteRepoPg;
+use crate::user::repo::UserPrivacyReadRepoPg;
+use crate::user::repo::UserPrivacyWriteRepoPg;
 use crate::user::repo::UserReadRepoPg;
 use crate::user::repo::UserWriteRepoPg;
+use crate::user::service::UserAdminServiceImpl;
 use crate::user::service::UserReadServiceImpl;
 use crate::user::service::UserWriteServiceImpl;
 use axum::routing::get;
 use axum::Router;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use tower_http::services::ServeDir;
 use tower_http::trace::TraceLayer;
 use tracing::{error, info, Level};
-use tracing_subscriber;
 
 #[tokio::main]
 async fn main() {
@@ -49,56 +81,267 @@ async fn main() {
         }
     };
 
+    // Initialize token signing keys
+    let key_set = match KeySet::init(&config) {
+        Ok(key_set) => Arc::new(key_set),
+        Err(err) => {
+            error!(error = %err, "Failed to load token signing keys");
+            return;
+        }
+    };
+
+    // Initialize message bus
+    let message_bus =
+        match ConfiguredMessageBus::init(Arc::clone(&config), Arc::clone(&database)).await {
+            Ok(bus) => Arc::new(bus),
+            Err(err) => {
+                error!(error = %err, "Failed to initialize message bus");
+                return;
+            }
+        };
+
+    // Initialize mailer
+    let mailer = match ConfiguredMailer::init(Arc::clone(&config)) {
+        Ok(mailer) => Arc::new(mailer),
+        Err(err) => {
+            error!(error = %err, "Failed to initialize mailer");
+            return;
+        }
+    };
+
+    // Initialize blob store
+    let blob_store = match ConfiguredBlobStore::init(Arc::clone(&config)) {
+        Ok(blob_store) => Arc::new(blob_store),
+        Err(err) => {
+            error!(error = %err, "Failed to initialize blob store");
+            return;
+        }
+    };
+
+    // Initialize attachment store
+    let attachment_store = match ConfiguredBlobStore::init_private(Arc::clone(&config)) {
+        Ok(attachment_store) => Arc::new(attachment_store),
+        Err(err) => {
+            error!(error = %err, "Failed to initialize attachment store");
+            return;
+        }
+    };
+
+    // Initialize identity provider client
+    let oauth_client = match OAuthClient::init(&config) {
+        Ok(oauth_client) => Arc::new(oauth_client),
+        Err(err) => {
+            error!(error = %err, "Failed to initialize identity provider client");
+            return;
+        }
+    };
+
     // Initialize repositories
     let user_read_repo = Arc::new(UserReadRepoPg::new(Arc::clone(&database)));
     let user_write_repo = Arc::new(UserWriteRepoPg::new(Arc::clone(&database)));
+    let user_privacy_read_repo = Arc::new(UserPrivacyReadRepoPg::new(Arc::clone(&database)));
+    let user_privacy_write_repo = Arc::new(UserPrivacyWriteRepoPg::new(Arc::clone(&database)));
+    let moderation_log_write_repo = Arc::new(ModerationLogWriteRepoPg::new(Arc::clone(&database)));
+    let session_read_repo = Arc::new(SessionReadRepoPg::new(Arc::clone(&database)));
+    let session_write_repo = Arc::new(SessionWriteRepoPg::new(Arc::clone(&database)));
+    let password_reset_write_repo = Arc::new(PasswordResetWriteRepoPg::new(Arc::clone(&database)));
+    let sign_in_attempt_write_repo = Arc::new(SignInAttemptWriteRepoPg::new(Arc::clone(&database)));
+    let user_identity_read_repo = Arc::new(UserIdentityReadRepoPg::new(Arc::clone(&database)));
+    let user_identity_write_repo = Arc::new(UserIdentityWriteRepoPg::new(Arc::clone(&database)));
+    let role_read_repo = Arc::new(RoleReadRepoPg::new(Arc::clone(&database)));
+    let role_write_repo = Arc::new(RoleWriteRepoPg::new(Arc::clone(&database)));
+    let totp_read_repo = Arc::new(TotpReadRepoPg::new(Arc::clone(&database)));
+    let totp_write_repo = Arc::new(TotpWriteRepoPg::new(Arc::clone(&database)));
     let participant_read_repo = Arc::new(ParticipantReadRepoPg::new(Arc::clone(&database)));
     let participant_write_repo = Arc::new(ParticipantWriteRepoPg::new(Arc::clone(&database)));
     let conversation_read_repo = Arc::new(ConversationReadRepoPg::new(Arc::clone(&database)));
     let conversation_write_repo = Arc::new(ConversationWriteRepoPg::new(Arc::clone(&database)));
+    let message_read_repo = Arc::new(PostgresMessageReadRepo::new(Arc::clone(&database)));
+    let message_write_repo = Arc::new(PostgresMessageWriteRepo::new(Arc::clone(&database)));
+    let attachment_read_repo = Arc::new(AttachmentReadRepoPg::new(Arc::clone(&database)));
+    let attachment_write_repo = Arc::new(AttachmentWriteRepoPg::new(Arc::clone(&database)));
 
     let unit_of_work = Arc::new(UnitOfWorkPg::new(Arc::clone(&database)));
 
+    let session_cache = Arc::new(SessionCache::new(config.session_cache_ttl));
+    let sign_in_throttle = Arc::new(SignInThrottle::new());
+    let password_reset_throttle = Arc::new(SignInThrottle::new());
+
     // Initialize services
-    let user_read_service = Arc::new(UserReadServiceImpl::new(Arc::clone(&user_read_repo)));
+    let user_read_service = Arc::new(UserReadServiceImpl::new(
+        Arc::clone(&user_read_repo),
+        Arc::clone(&user_privacy_read_repo),
+    ));
     let user_write_service = Arc::new(UserWriteServiceImpl::new(
         Arc::clone(&user_write_repo),
         Arc::clone(&user_read_repo),
+        Arc::clone(&user_privacy_read_repo),
+        Arc::clone(&user_privacy_write_repo),
+        Arc::clone(&blob_store),
     ));
+    let mfa_service = Arc::new(MfaServiceImpl::new(
+        Arc::clone(&user_read_repo),
+        Arc::clone(&totp_read_repo),
+        Arc::clone(&totp_write_repo),
+        Arc::clone(&config),
+    ));
+    let oauth_service = Arc::new(OAuthServiceImpl::new(
+        Arc::clone(&user_read_repo),
+        Arc::clone(&user_write_repo),
+        Arc::clone(&user_identity_read_repo),
+        Arc::clone(&user_identity_write_repo),
+        Arc::clone(&oauth_client),
+        Arc::clone(&config),
+    ));
+    let role_service = Arc::new(RoleServiceImpl::new(
+        Arc::clone(&role_read_repo),
+        Arc::clone(&role_write_repo),
+        Arc::clone(&user_read_repo),
+    ));
+    if let Some(email) = &config.superadmin_email {
+        match role_service.seed_super_admin(email).await {
+            Ok(true) => info!(%email, "Granted SUPERADMIN"),
+            Ok(false) => {}
+            Err(err) => error!(error = %err, "Failed to grant SUPERADMIN"),
+        }
+    }
     let auth_write_service = Arc::new(AuthWriteServiceImpl::new(
         Arc::clone(&user_write_repo),
         Arc::clone(&user_read_repo),
+        Arc::clone(&session_write_repo),
+        Arc::clone(&password_reset_write_repo),
+        Arc::clone(&sign_in_attempt_write_repo),
+        Arc::clone(&role_read_repo),
+        Arc::clone(&mfa_service),
+        Arc::clone(&oauth_service),
+        Arc::clone(&mailer),
+        Arc::clone(&unit_of_work),
+        Arc::clone(&session_cache),
+        Arc::clone(&sign_in_throttle),
+        Arc::clone(&password_reset_throttle),
+        Arc::clone(&key_set),
         Arc::clone(&config),
     ));
-    let auth_read_service = Arc::new(AuthReadServiceImpl::new(Arc::clone(&config)));
+    let user_admin_service = Arc::new(UserAdminServiceImpl::new(
+        Arc::clone(&user_read_repo),
+        Arc::clone(&user_write_repo),
+        Arc::clone(&moderation_log_write_repo),
+        Arc::clone(&role_read_repo),
+        Arc::clone(&auth_write_service),
+        Arc::clone(&unit_of_work),
+    ));
+    let auth_read_service = Arc::new(AuthReadServiceImpl::new(
+        Arc::clone(&session_read_repo),
+        Arc::clone(&user_read_repo),
+        Arc::clone(&role_read_repo),
+        Arc::clone(&session_write_repo),
+        Arc::clone(&session_cache),
+        Arc::clone(&key_set),
+    ));
     let conversation_write_service = Arc::new(ConversationWriteServiceImpl::new(
         Arc::clone(&conversation_write_repo),
         Arc::clone(&conversation_read_repo),
         Arc::clone(&participant_write_repo),
         Arc::clone(&unit_of_work),
+        Arc::clone(&participant_read_repo),
+        Arc::clone(&blob_store),
+    ));
+    let conversation_read_service = Arc::new(ConversationReadServiceImpl::new(
+        Arc::clone(&conversation_read_repo),
+        Arc::clone(&participant_read_repo),
     ));
-    let conversation_read_service = Arc::new(ConversationReadServiceImpl::new(Arc::clone(
-        &conversation_read_repo,
+    let participant_read_service = Arc::new(ParticipantReadServiceImpl::new(Arc::clone(
+        &participant_read_repo,
     )));
+    let participant_write_service = Arc::new(ParticipantWriteServiceImpl::new(
+        Arc::clone(&participant_read_repo),
+        Arc::clone(&participant_write_repo),
+    ));
+    let message_write_service = Arc::new(MessageWriteServiceImpl::new(
+        Arc::clone(&message_write_repo),
+        Arc::clone(&message_read_repo),
+        Arc::clone(&participant_read_repo),
+        Arc::clone(&attachment_read_repo),
+        Arc::clone(&attachment_write_repo),
+        Arc::clone(&unit_of_work),
+    ));
+    let message_read_service = Arc::new(MessageReadServiceImpl::new(
+        Arc::clone(&message_read_repo),
+        Arc::clone(&participant_read_repo),
+        Arc::clone(&attachment_read_repo),
+    ));
+    let attachment_write_service = Arc::new(AttachmentWriteServiceImpl::new(
+        Arc::clone(&attachment_write_repo),
+        Arc::clone(&participant_read_repo),
+        Arc::clone(&attachment_store),
+        Arc::clone(&config),
+    ));
+    // Uploads that were never sent with a message are removed in the background
+    let attachment_sweeper = Arc::clone(&attachment_write_service);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            match attachment_sweeper.sweep_pending().await {
+                Ok(0) => {}
+                Ok(removed) => info!(removed, "Removed pending attachments"),
+                Err(err) => error!(error = %err, "Failed to remove pending attachments"),
+            }
+        }
+    });
+    let attachment_read_service = Arc::new(AttachmentReadServiceImpl::new(
+        Arc::clone(&attachment_read_repo),
+        Arc::clone(&message_read_repo),
+        Arc::clone(&participant_read_repo),
+        Arc::clone(&attachment_store),
+    ));
 
     // Initialize handlers
     let user_handler = Arc::new(UserHandler::new(
         Arc::clone(&user_write_service),
         Arc::clone(&user_read_service),
+        Arc::clone(&user_admin_service),
+    ));
+    let auth_handler = Arc::new(AuthHandler::new(
+        Arc::clone(&auth_write_service),
+        Arc::clone(&auth_read_service),
+        Arc::clone(&mfa_service),
+        Arc::clone(&oauth_service),
+        Arc::clone(&role_service),
     ));
-    let auth_handler = Arc::new(AuthHandler::new(Arc::clone(&auth_write_service)));
-    let message_handler = Arc::new(MessageHandler::new());
+    let message_handler = Arc::new(MessageHandler::new(
+        Arc::clone(&message_write_service),
+        Arc::clone(&message_read_service),
+        Arc::clone(&participant_read_service),
+        Arc::clone(&participant_write_service),
+        Arc::clone(&message_bus),
+    ));
+    // Delivered receipts are published in batches, see `MessageHandler::publish_pending_receipts`
+    let receipt_publisher = Arc::clone(&message_handler);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            receipt_publisher.publish_pending_receipts().await;
+        }
+    });
     let conversation_handler = Arc::new(ConversationHandler::new(
         Arc::clone(&conversation_write_service),
         Arc::clone(&conversation_read_service),
+        Arc::clone(&message_bus),
+    ));
+    let attachment_handler = Arc::new(AttachmentHandler::new(
+        Arc::clone(&attachment_write_service),
+        Arc::clone(&attachment_read_service),
     ));
 
     let app_state = AppState {
         auth_read_service: Arc::clone(&auth_read_service),
+        config: Arc::clone(&config),
     };
 
     // Create Axum app
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/", get(|| async { "Welcome to social media" }))
         .merge(UserHandler::create_route(
             user_handler,
@@ -116,14 +359,22 @@ async fn main() {
             conversation_handler,
             Router::new().with_state(app_state.clone()),
         ))
+        .merge(AttachmentHandler::create_route(
+            attachment_handler,
+            Router::new().with_state(app_state.clone()),
+        ))
         .layer(TraceLayer::new_for_http())
         .with_state(app_state);
+    if let BlobStoreType::Local = config.blob_store {
+        app = app.nest_service(LOCAL_BLOB_PATH, ServeDir::new(&config.blob_dir));
+    }
 
     // Start server
     let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
     info!(%addr, "Starting the server");
     match tokio::net::TcpListener::bind(addr).await {
         Ok(listener) => {
+            let app = app.into_make_service_with_connect_info::<SocketAddr>();
             if let Err(err) = axum::serve(listener, app).await {
                 error!(error = %err, "Server encountered an error");
             }
@@ -1,35 +1,48 @@
-use crate::auth::extractor::Auth;
+use crate::auth::extractor::{Auth, Authorized, ModerateUsers};
 use crate::common::json::IntoApiResponse;
 use crate::common::model::PageRequest;
+use crate::common::photo::{read_photo, MAX_PHOTO_UPLOAD_SIZE};
 use crate::common::state::AppState;
-use crate::user::model::UpdateUserRequest;
+use crate::user::model::{
+    AdminUserSearchRequest, ModerationRequest, SuspendUserRequest, UpdateUserPrivacyRequest,
+    UpdateUserRequest, UserSearchRequest,
+};
+use crate::user::service::UserAdminService;
 use crate::user::service::UserReadService;
 use crate::user::service::UserWriteService;
-use axum::extract::{Path, Query};
+use axum::extract::{DefaultBodyLimit, Multipart, Path, Query};
 use axum::response::IntoResponse;
-use axum::routing::{delete, get, patch};
+use axum::routing::{delete, get, patch, post};
 use axum::{Json, Router};
 use std::sync::Arc;
 
 #[derive(Clone)]
-pub struct UserHandler<W, R>
+pub struct UserHandler<W, R, A>
 where
     W: UserWriteService + Send + Sync + 'static,
     R: UserReadService + Send + Sync + 'static,
+    A: UserAdminService + Send + Sync + 'static,
 {
     user_write_service: Arc<W>,
     user_read_service: Arc<R>,
+    user_admin_service: Arc<A>,
 }
 
-impl<W, R> UserHandler<W, R>
+impl<W, R, A> UserHandler<W, R, A>
 where
     W: UserWriteService + Send + Sync + 'static,
     R: UserReadService + Send + Sync + 'static,
+    A: UserAdminService + Send + Sync + 'static,
 {
-    pub fn new(user_write_service: Arc<W>, user_read_service: Arc<R>) -> Self {
+    pub fn new(
+        user_write_service: Arc<W>,
+        user_read_service: Arc<R>,
+        user_admin_service: Arc<A>,
+    ) -> Self {
         Self {
             user_write_service,
             user_read_service,
+            user_admin_service,
         }
     }
 
@@ -37,8 +50,64 @@ where
         self.user_read_service.find_by_id(user_id).await.into_json()
     }
 
-    async fn find_all(&self, req: PageRequest) -> impl IntoResponse {
-        self.user_read_service.find_all(req).await.into_json()
+    async fn find_profile(&self, viewer_id: i64, user_id: i64) -> impl IntoResponse {
+        self.user_read_service
+            .find_profile(viewer_id, user_id)
+            .await
+            .into_json()
+    }
+
+    async fn find_all(&self, viewer_id: i64, req: PageRequest) -> impl IntoResponse {
+        self.user_read_service
+            .find_all(viewer_id, req)
+            .await
+            .into_json()
+    }
+
+    async fn search(&self, viewer_id: i64, req: UserSearchRequest) -> impl IntoResponse {
+        self.user_read_service
+            .search(viewer_id, req)
+            .await
+            .into_json()
+    }
+
+    async fn find_blocked(&self, user_id: i64) -> impl IntoResponse {
+        self.user_read_service
+            .find_blocked(user_id)
+            .await
+            .into_json()
+    }
+
+    async fn block(&self, user_id: i64, blocked_user_id: i64) -> impl IntoResponse {
+        self.user_write_service
+            .block(user_id, blocked_user_id)
+            .await
+            .into_json()
+    }
+
+    async fn unblock(&self, user_id: i64, blocked_user_id: i64) -> impl IntoResponse {
+        self.user_write_service
+            .unblock(user_id, blocked_user_id)
+            .await
+            .into_json()
+    }
+
+    async fn find_privacy(&self, user_id: i64) -> impl IntoResponse {
+        self.user_read_service
+            .find_privacy(user_id)
+            .await
+            .into_json()
+    }
+
+    async fn update_privacy(
+        &self,
+        user_id: i64,
+        req: UpdateUserPrivacyRequest,
+    ) -> impl IntoResponse {
+        self.user_write_service
+            .update_privacy(user_id, req)
+            .await
+            .into_json()
     }
 
     async fn update(&self, user_id: i64, req: UpdateUserRequest) -> impl IntoResponse {
@@ -48,17 +117,108 @@ where
             .into_json()
     }
 
+    async fn update_photo(&self, user_id: i64, multipart: Multipart) -> impl IntoResponse {
+        let result = async {
+            let photo = read_photo(multipart).await?;
+            self.user_write_service.update_photo(user_id, photo).await
+        };
+
+        result.await.into_json()
+    }
+
     async fn delete(&self, user_id: i64) -> impl IntoResponse {
         self.user_write_service.delete(user_id).await.into_json()
     }
 
+    async fn admin_search(&self, req: AdminUserSearchRequest) -> impl IntoResponse {
+        self.user_admin_service.search(req).await.into_json()
+    }
+
+    async fn admin_find_by_id(&self, user_id: i64) -> impl IntoResponse {
+        self.user_admin_service
+            .find_by_id(user_id)
+            .await
+            .into_json()
+    }
+
+    async fn admin_suspend(
+        &self,
+        auth: Auth,
+        user_id: i64,
+        req: SuspendUserRequest,
+    ) -> impl IntoResponse {
+        self.user_admin_service
+            .suspend(auth.user_id, user_id, req)
+            .await
+            .into_json()
+    }
+
+    async fn admin_unsuspend(
+        &self,
+        auth: Auth,
+        user_id: i64,
+        req: ModerationRequest,
+    ) -> impl IntoResponse {
+        self.user_admin_service
+            .unsuspend(auth.user_id, user_id, req)
+            .await
+            .into_json()
+    }
+
+    async fn admin_restore(
+        &self,
+        auth: Auth,
+        user_id: i64,
+        req: ModerationRequest,
+    ) -> impl IntoResponse {
+        self.user_admin_service
+            .restore(auth.user_id, user_id, req)
+            .await
+            .into_json()
+    }
+
+    async fn admin_force_password_reset(
+        &self,
+        auth: Auth,
+        user_id: i64,
+        req: ModerationRequest,
+    ) -> impl IntoResponse {
+        self.user_admin_service
+            .force_password_reset(auth.user_id, user_id, req)
+            .await
+            .into_json()
+    }
+
+    async fn admin_revoke_sessions(
+        &self,
+        auth: Auth,
+        user_id: i64,
+        req: ModerationRequest,
+    ) -> impl IntoResponse {
+        self.user_admin_service
+            .revoke_sessions(auth.user_id, user_id, req)
+            .await
+            .into_json()
+    }
+
     pub fn create_route(handler: Arc<Self>, router: Router<AppState>) -> Router<AppState> {
         router
             .route(
                 "/api/users",
                 get({
                     let handler = Arc::clone(&handler);
-                    move |Query(req): Query<PageRequest>| async move { handler.find_all(req).await }
+                    move |auth: Auth, Query(req): Query<PageRequest>| async move {
+                        handler.find_all(auth.user_id, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/users/search",
+                get({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth, Query(req): Query<UserSearchRequest>| async move {
+                        handler.search(auth.user_id, req).await
+                    }
                 }),
             )
             .route(
@@ -72,7 +232,9 @@ where
                 "/api/user/:user_id",
                 get({
                     let handler = Arc::clone(&handler);
-                    |Path(user_id): Path<i64>| async move { handler.find_by_id(user_id).await }
+                    |auth: Auth, Path(user_id): Path<i64>| async move {
+                        handler.find_profile(auth.user_id, user_id).await
+                    }
                 }),
             )
             .route(
@@ -84,6 +246,57 @@ where
                     }
                 }),
             )
+            .route(
+                "/api/user/photo",
+                post({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth, multipart: Multipart| async move {
+                        handler.update_photo(auth.user_id, multipart).await
+                    }
+                })
+                .layer(DefaultBodyLimit::max(MAX_PHOTO_UPLOAD_SIZE)),
+            )
+            .route(
+                "/api/user/blocks",
+                get({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth| async move { handler.find_blocked(auth.user_id).await }
+                }),
+            )
+            .route(
+                "/api/user/:user_id/block",
+                post({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth, Path(user_id): Path<i64>| async move {
+                        handler.block(auth.user_id, user_id).await
+                    }
+                }),
+            )
+            .route(
+                "/api/user/:user_id/block",
+                delete({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth, Path(user_id): Path<i64>| async move {
+                        handler.unblock(auth.user_id, user_id).await
+                    }
+                }),
+            )
+            .route(
+                "/api/user/privacy",
+                get({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth| async move { handler.find_privacy(auth.user_id).await }
+                }),
+            )
+            .route(
+                "/api/user/privacy",
+                patch({
+                    let handler = Arc::clone(&handler);
+                    |auth: Auth, Json(req): Json<UpdateUserPrivacyRequest>| async move {
+                        handler.update_privacy(auth.user_id, req).await
+                    }
+                }),
+            )
             .route(
                 "/api/user",
                 delete({
@@ -91,5 +304,82 @@ where
                     |auth: Auth| async move { handler.delete(auth.user_id).await }
                 }),
             )
+            .route(
+                "/api/admin/users",
+                get({
+                    let handler = Arc::clone(&handler);
+                    |_: Authorized<ModerateUsers>, Query(req): Query<AdminUserSearchRequest>| async move {
+                        handler.admin_search(req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/admin/users/:user_id",
+                get({
+                    let handler = Arc::clone(&handler);
+                    |_: Authorized<ModerateUsers>, Path(user_id): Path<i64>| async move {
+                        handler.admin_find_by_id(user_id).await
+                    }
+                }),
+            )
+            .route(
+                "/api/admin/users/:user_id/suspend",
+                post({
+                    let handler = Arc::clone(&handler);
+                    |Authorized(auth, _): Authorized<ModerateUsers>,
+                     Path(user_id): Path<i64>,
+                     Json(req): Json<SuspendUserRequest>| async move {
+                        handler.admin_suspend(auth, user_id, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/admin/users/:user_id/unsuspend",
+                post({
+                    let handler = Arc::clone(&handler);
+                    |Authorized(auth, _): Authorized<ModerateUsers>,
+                     Path(user_id): Path<i64>,
+                     req: Option<Json<ModerationRequest>>| async move {
+                        let req = req.map(|Json(req)| req).unwrap_or_default();
+                        handler.admin_unsuspend(auth, user_id, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/admin/users/:user_id/restore",
+                post({
+                    let handler = Arc::clone(&handler);
+                    |Authorized(auth, _): Authorized<ModerateUsers>,
+                     Path(user_id): Path<i64>,
+                     req: Option<Json<ModerationRequest>>| async move {
+                        let req = req.map(|Json(req)| req).unwrap_or_default();
+                        handler.admin_restore(auth, user_id, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/admin/users/:user_id/password_reset",
+                post({
+                    let handler = Arc::clone(&handler);
+                    |Authorized(auth, _): Authorized<ModerateUsers>,
+                     Path(user_id): Path<i64>,
+                     req: Option<Json<ModerationRequest>>| async move {
+                        let req = req.map(|Json(req)| req).unwrap_or_default();
+                        handler.admin_force_password_reset(auth, user_id, req).await
+                    }
+                }),
+            )
+            .route(
+                "/api/admin/users/:user_id/sessions/revoke",
+                post({
+                    let handler = Arc::clone(&handler);
+                    |Authorized(auth, _): Authorized<ModerateUsers>,
+                     Path(user_id): Path<i64>,
+                     req: Option<Json<ModerationRequest>>| async move {
+                        let req = req.map(|Json(req)| req).unwrap_or_default();
+                        handler.admin_revoke_sessions(auth, user_id, req).await
+                    }
+                }),
+            )
     }
 }
@@ -1,6 +1,10 @@
+use crate::common::model::Error;
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use sqlx::FromRow;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use validator::Validate;
 
 #[derive(Clone, FromRow)]
 pub struct User {
@@ -10,11 +14,26 @@ pub struct User {
     pub password: String,
     pub name: String,
     pub photo_url: Option<String>,
+    pub photo_thumbnail_url: Option<String>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub suspended_at: Option<DateTime<Utc>>,
+    /// End of a suspension, none for a ban
+    pub suspended_until: Option<DateTime<Utc>>,
+    pub suspension_reason: Option<String>,
     pub deleted_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+impl User {
+    pub fn is_suspended(&self) -> bool {
+        self.suspended_at.is_some()
+            && self
+                .suspended_until
+                .is_none_or(|suspended_until| suspended_until > Utc::now())
+    }
+}
+
 #[derive(Clone)]
 pub struct CreateUserRequest {
     pub username: String,
@@ -24,15 +43,109 @@ pub struct CreateUserRequest {
     pub photo_url: Option<String>,
 }
 
-#[derive(Clone, Deserialize)]
+/// Profile fields a user can change freely. The email and password need the current password,
+/// see `ChangeEmailRequest` and `ChangePasswordRequest`, the photo is uploaded on its own.
+#[derive(Clone, Deserialize, Validate)]
 pub struct UpdateUserRequest {
+    #[validate(length(
+        min = 1,
+        max = 10,
+        message = "Username length must be between 1 and 10 characters."
+    ))]
     pub username: Option<String>,
-    pub email: Option<String>,
-    pub password: Option<String>,
+
+    #[validate(length(
+        min = 1,
+        max = 64,
+        message = "Name length must be between 1 and 64 characters."
+    ))]
     pub name: Option<String>,
-    pub photo_url: Option<String>,
 }
 
+#[derive(Clone, Deserialize, Validate)]
+pub struct UserSearchRequest {
+    /// Matched against the start of the username or of any word of the name, and loosely
+    /// against both to forgive typos
+    #[validate(length(
+        min = 1,
+        max = 64,
+        message = "Search term length must be between 1 and 64 characters."
+    ))]
+    pub q: String,
+
+    /// The `next_cursor` of the previous page
+    pub cursor: Option<UserSearchCursor>,
+
+    #[validate(range(min = 1, max = 50, message = "Size must be between 1 and 50."))]
+    pub size: Option<i32>,
+}
+
+impl UserSearchRequest {
+    pub fn size(&self) -> i32 {
+        self.size.unwrap_or(10)
+    }
+}
+
+/// A user found by the search, with the rank and score the results are ordered by
+#[derive(Clone, FromRow)]
+pub struct UserSearchResult {
+    #[sqlx(flatten)]
+    pub user: User,
+    pub rank: i32,
+    pub score: f32,
+}
+
+/// Position in the search results, the rank, score and id of the last user on a page. Sent as
+/// `<rank>_<score>_<id>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UserSearchCursor {
+    pub rank: i32,
+    pub score: f32,
+    pub user_id: i64,
+}
+
+impl Display for UserSearchCursor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}_{}", self.rank, self.score, self.user_id)
+    }
+}
+
+impl FromStr for UserSearchCursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::BadRequest("Invalid cursor".to_string());
+        let mut parts = s.splitn(3, '_');
+        let mut next = || parts.next().ok_or_else(invalid);
+        let (rank, score, user_id) = (next()?, next()?, next()?);
+
+        Ok(UserSearchCursor {
+            rank: rank.parse().map_err(|_| invalid())?,
+            score: score
+                .parse::<f32>()
+                .ok()
+                .filter(|score| score.is_finite())
+                .ok_or_else(invalid)?,
+            user_id: user_id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl Serialize for UserSearchCursor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for UserSearchCursor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|error: Error| de::Error::custom(error))
+    }
+}
+
+/// A user's own profile, only ever returned to the user themselves
 #[derive(Clone, Serialize)]
 pub struct UserResponse {
     pub id: i64,
@@ -40,6 +153,8 @@ pub struct UserResponse {
     pub email: String,
     pub name: String,
     pub photo_url: Option<String>,
+    pub photo_thumbnail_url: Option<String>,
+    pub email_verified: bool,
     pub deleted_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -53,9 +168,357 @@ impl UserResponse {
             email: user.email,
             name: user.name,
             photo_url: user.photo_url,
+            photo_thumbnail_url: user.photo_thumbnail_url,
+            email_verified: user.email_verified_at.is_some(),
+            deleted_at: user.deleted_at,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+/// Who can see a part of a profile. Contacts are the users one shares a conversation with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Visibility {
+    Everyone,
+    Contacts,
+    Nobody,
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Everyone => "EVERYONE",
+            Visibility::Contacts => "CONTACTS",
+            Visibility::Nobody => "NOBODY",
+        }
+    }
+
+    pub fn allows(&self, is_contact: bool) -> bool {
+        match self {
+            Visibility::Everyone => true,
+            Visibility::Contacts => is_contact,
+            Visibility::Nobody => false,
+        }
+    }
+}
+
+impl FromStr for Visibility {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "EVERYONE" => Ok(Visibility::Everyone),
+            "CONTACTS" => Ok(Visibility::Contacts),
+            "NOBODY" => Ok(Visibility::Nobody),
+            _ => Err(Error::InternalServerError(format!(
+                "Unknown visibility {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl TryFrom<String> for Visibility {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Privacy settings of a user, users without a row have the defaults
+#[derive(Debug, Clone, FromRow)]
+pub struct UserPrivacy {
+    pub user_id: i64,
+    #[sqlx(try_from = "String")]
+    pub email_visibility: Visibility,
+    #[sqlx(try_from = "String")]
+    pub last_seen_visibility: Visibility,
+    #[sqlx(try_from = "String")]
+    pub photo_visibility: Visibility,
+}
+
+impl UserPrivacy {
+    pub fn default(user_id: i64) -> UserPrivacy {
+        UserPrivacy {
+            user_id,
+            email_visibility: Visibility::Nobody,
+            last_seen_visibility: Visibility::Contacts,
+            photo_visibility: Visibility::Everyone,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct UpdateUserPrivacyRequest {
+    pub email_visibility: Option<Visibility>,
+    pub last_seen_visibility: Option<Visibility>,
+    pub photo_visibility: Option<Visibility>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct UserPrivacyResponse {
+    pub email_visibility: Visibility,
+    pub last_seen_visibility: Visibility,
+    pub photo_visibility: Visibility,
+}
+
+impl UserPrivacyResponse {
+    pub fn from(privacy: UserPrivacy) -> UserPrivacyResponse {
+        UserPrivacyResponse {
+            email_visibility: privacy.email_visibility,
+            last_seen_visibility: privacy.last_seen_visibility,
+            photo_visibility: privacy.photo_visibility,
+        }
+    }
+}
+
+/// Another user's profile as seen by the viewer. Fields the user hides from the viewer are
+/// left out.
+#[derive(Clone, Serialize)]
+pub struct PublicUserResponse {
+    pub id: i64,
+    pub username: String,
+    pub name: String,
+    pub photo_url: Option<String>,
+    pub photo_thumbnail_url: Option<String>,
+    pub email: Option<String>,
+    /// Latest authenticated request from any of the user's sessions, up to `SESSION_CACHE_TTL`
+    /// behind. Open WebSocket connections count when they connect.
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl PublicUserResponse {
+    /// `is_contact` tells whether the viewer is the user or shares a conversation with them
+    pub fn from(
+        user: User,
+        privacy: &UserPrivacy,
+        is_contact: bool,
+        last_seen_at: Option<DateTime<Utc>>,
+    ) -> PublicUserResponse {
+        let shows_photo = privacy.photo_visibility.allows(is_contact);
+
+        PublicUserResponse {
+            id: user.id,
+            username: user.username,
+            name: user.name,
+            photo_url: user.photo_url.filter(|_| shows_photo),
+            photo_thumbnail_url: user.photo_thumbnail_url.filter(|_| shows_photo),
+            email: Some(user.email).filter(|_| privacy.email_visibility.allows(is_contact)),
+            last_seen_at: last_seen_at.filter(|_| privacy.last_seen_visibility.allows(is_contact)),
+            created_at: user.created_at,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AdminUserSearchRequest {
+    /// Matches the start of the username or email, or anywhere in the name
+    pub q: Option<String>,
+    /// Only deleted users when true, only not deleted ones when false, both when unset
+    pub deleted: Option<bool>,
+    pub cursor: Option<i64>,
+    pub size: Option<i32>,
+}
+
+#[derive(Clone, Deserialize, Validate)]
+pub struct SuspendUserRequest {
+    #[validate(length(
+        min = 1,
+        max = 255,
+        message = "Reason length must be between 1 and 255 characters."
+    ))]
+    pub reason: String,
+
+    /// When the suspension ends, a ban when unset
+    pub until: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Default, Deserialize, Validate)]
+pub struct ModerationRequest {
+    #[validate(length(max = 255, message = "Reason length must be at most 255 characters."))]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ModerationAction {
+    Suspend,
+    Ban,
+    Unsuspend,
+    Restore,
+    ForcePasswordReset,
+    RevokeSessions,
+}
+
+impl ModerationAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationAction::Suspend => "suspend",
+            ModerationAction::Ban => "ban",
+            ModerationAction::Unsuspend => "unsuspend",
+            ModerationAction::Restore => "restore",
+            ModerationAction::ForcePasswordReset => "force_password_reset",
+            ModerationAction::RevokeSessions => "revoke_sessions",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CreateModerationLogRequest {
+    pub actor_id: i64,
+    pub user_id: i64,
+    pub action: ModerationAction,
+    pub reason: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A user as operators see it, including moderation state
+#[derive(Clone, Serialize)]
+pub struct AdminUserResponse {
+    pub id: i64,
+    pub username: String,
+    pub email: String,
+    pub name: String,
+    pub photo_url: Option<String>,
+    pub photo_thumbnail_url: Option<String>,
+    pub email_verified: bool,
+    pub suspended: bool,
+    pub suspended_at: Option<DateTime<Utc>>,
+    pub suspended_until: Option<DateTime<Utc>>,
+    pub suspension_reason: Option<String>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl AdminUserResponse {
+    pub fn from(user: User) -> AdminUserResponse {
+        AdminUserResponse {
+            suspended: user.is_suspended(),
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            name: user.name,
+            photo_url: user.photo_url,
+            photo_thumbnail_url: user.photo_thumbnail_url,
+            email_verified: user.email_verified_at.is_some(),
+            suspended_at: user.suspended_at,
+            suspended_until: user.suspended_until,
+            suspension_reason: user.suspension_reason,
             deleted_at: user.deleted_at,
             created_at: user.created_at,
             updated_at: user.updated_at,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> User {
+        User {
+            id: 1,
+            username: "jane".to_string(),
+            email: "jane@example.com".to_string(),
+            password: String::new(),
+            name: "Jane".to_string(),
+            photo_url: Some("photo.webp".to_string()),
+            photo_thumbnail_url: Some("thumbnail.webp".to_string()),
+            email_verified_at: None,
+            suspended_at: None,
+            suspended_until: None,
+            suspension_reason: None,
+            deleted_at: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn privacy(visibility: Visibility) -> UserPrivacy {
+        UserPrivacy {
+            user_id: 1,
+            email_visibility: visibility,
+            last_seen_visibility: visibility,
+            photo_visibility: visibility,
+        }
+    }
+
+    #[test]
+    fn everyone_sees_what_is_shared_with_everyone() {
+        let response = PublicUserResponse::from(
+            user(),
+            &privacy(Visibility::Everyone),
+            false,
+            Some(Utc::now()),
+        );
+
+        assert!(response.email.is_some());
+        assert!(response.last_seen_at.is_some());
+        assert!(response.photo_url.is_some());
+        assert!(response.photo_thumbnail_url.is_some());
+    }
+
+    #[test]
+    fn only_contacts_see_what_is_shared_with_contacts() {
+        let privacy = privacy(Visibility::Contacts);
+
+        let stranger = PublicUserResponse::from(user(), &privacy, false, Some(Utc::now()));
+        assert!(stranger.email.is_none());
+        assert!(stranger.last_seen_at.is_none());
+        assert!(stranger.photo_url.is_none());
+        assert!(stranger.photo_thumbnail_url.is_none());
+
+        let contact = PublicUserResponse::from(user(), &privacy, true, Some(Utc::now()));
+        assert!(contact.email.is_some());
+        assert!(contact.last_seen_at.is_some());
+        assert!(contact.photo_url.is_some());
+    }
+
+    #[test]
+    fn nobody_sees_what_is_shared_with_nobody() {
+        let response =
+            PublicUserResponse::from(user(), &privacy(Visibility::Nobody), true, Some(Utc::now()));
+
+        assert!(response.email.is_none());
+        assert!(response.last_seen_at.is_none());
+        assert!(response.photo_url.is_none());
+        assert!(response.photo_thumbnail_url.is_none());
+        assert_eq!(response.username, "jane");
+        assert_eq!(response.name, "Jane");
+    }
+
+    #[test]
+    fn defaults_hide_the_email_and_show_the_photo() {
+        let response =
+            PublicUserResponse::from(user(), &UserPrivacy::default(1), false, Some(Utc::now()));
+
+        assert!(response.email.is_none());
+        assert!(response.last_seen_at.is_none());
+        assert!(response.photo_url.is_some());
+    }
+
+    #[test]
+    fn search_cursor_round_trips() {
+        let cursor = UserSearchCursor {
+            rank: 1,
+            score: 2.0 / 3.0,
+            user_id: 42,
+        };
+
+        assert_eq!(
+            cursor.to_string().parse::<UserSearchCursor>().unwrap(),
+            cursor
+        );
+    }
+
+    #[test]
+    fn malformed_search_cursors_are_rejected() {
+        for cursor in ["", "1", "1_0.5", "x_0.5_1", "1_NaN_1", "1_0.5_x"] {
+            assert!(cursor.parse::<UserSearchCursor>().is_err(), "{}", cursor);
+        }
+    }
+}
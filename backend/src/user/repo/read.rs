@@ -1,5 +1,9 @@
 use crate::common::model::{Error, PageRequest, PageResponse};
-use crate::user::model::User;
+use crate::user::model::{
+    AdminUserSearchRequest, User, UserPrivacy, UserSearchCursor, UserSearchRequest,
+    UserSearchResult,
+};
+use chrono::{DateTime, Utc};
 use sqlx::{Pool, Postgres};
 use std::future::Future;
 use std::sync::Arc;
@@ -17,12 +21,54 @@ pub trait UserReadRepo: Send + Sync {
         username_or_email: &str,
     ) -> impl Future<Output = Result<Option<User>, Error>> + Send;
 
+    fn find_by_email(
+        &self,
+        email: &str,
+    ) -> impl Future<Output = Result<Option<User>, Error>> + Send;
+
     fn exists_by_username(
         &self,
         username: &str,
     ) -> impl Future<Output = Result<bool, Error>> + Send;
 
     fn exists_by_email(&self, email: &str) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Like `find_by_id`, but also finds deleted users
+    fn find_by_id_with_deleted(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = Result<Option<User>, Error>> + Send;
+
+    /// Search across all users for operators, deleted ones included
+    fn search_all(
+        &self,
+        req: &AdminUserSearchRequest,
+    ) -> impl Future<Output = Result<PageResponse<User>, Error>> + Send;
+
+    /// Ranked search of active users by username and name for `user_id`, who is left out along
+    /// with users either of them blocked. Exact usernames come first, then username prefixes,
+    /// then name prefixes, then fuzzy matches, each by similarity.
+    fn search(
+        &self,
+        user_id: i64,
+        req: &UserSearchRequest,
+    ) -> impl Future<Output = Result<PageResponse<User, UserSearchCursor>, Error>> + Send;
+
+    /// Users `user_id` blocked, latest first
+    fn find_blocked(&self, user_id: i64) -> impl Future<Output = Result<Vec<User>, Error>> + Send;
+
+    /// Those of `user_ids` who share a conversation with `user_id`
+    fn find_contact_ids(
+        &self,
+        user_id: i64,
+        user_ids: &[i64],
+    ) -> impl Future<Output = Result<Vec<i64>, Error>> + Send;
+
+    /// Last activity across all sessions of each of `user_ids` who has ever signed in
+    fn find_last_seen(
+        &self,
+        user_ids: &[i64],
+    ) -> impl Future<Output = Result<Vec<(i64, DateTime<Utc>)>, Error>> + Send;
 }
 
 pub struct UserReadRepoPg {
@@ -39,7 +85,9 @@ impl UserReadRepo for UserReadRepoPg {
     async fn find_by_id(&self, user_id: i64) -> Result<Option<User>, Error> {
         let query = r#"
             SELECT 
-                id, username, email, password, name, photo_url, deleted_at, created_at,  updated_at
+                id, username, email, password, name, photo_url, photo_thumbnail_url,
+                email_verified_at, suspended_at, suspended_until, suspension_reason, deleted_at,
+                created_at, updated_at
             FROM 
                 "user"
             WHERE 
@@ -56,7 +104,9 @@ impl UserReadRepo for UserReadRepoPg {
     async fn find_all(&self, req: PageRequest) -> Result<PageResponse<User>, Error> {
         let query = r#"
             SELECT
-                id, username, email, password, name, photo_url, deleted_at, created_at,  updated_at
+                id, username, email, password, name, photo_url, photo_thumbnail_url,
+                email_verified_at, suspended_at, suspended_until, suspension_reason, deleted_at,
+                created_at, updated_at
             FROM
                 "user"
             WHERE
@@ -90,7 +140,9 @@ impl UserReadRepo for UserReadRepoPg {
     ) -> Result<Option<User>, Error> {
         let query = r#"
             SELECT 
-                id, username, email, password, name, photo_url, deleted_at, created_at,  updated_at
+                id, username, email, password, name, photo_url, photo_thumbnail_url,
+                email_verified_at, suspended_at, suspended_until, suspension_reason, deleted_at,
+                created_at, updated_at
             FROM 
                 "user"
             WHERE 
@@ -104,6 +156,25 @@ impl UserReadRepo for UserReadRepoPg {
             .map_err(|e| Error::InternalServerError(e.to_string()))
     }
 
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, Error> {
+        let query = r#"
+            SELECT 
+                id, username, email, password, name, photo_url, photo_thumbnail_url,
+                email_verified_at, suspended_at, suspended_until, suspension_reason, deleted_at,
+                created_at, updated_at
+            FROM 
+                "user"
+            WHERE 
+                email = $1 AND deleted_at IS NULL
+        "#;
+
+        sqlx::query_as::<_, User>(query)
+            .bind(email)
+            .fetch_optional(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
     async fn exists_by_username(&self, username: &str) -> Result<bool, Error> {
         let query = r#"
             SELECT 
@@ -137,4 +208,257 @@ impl UserReadRepo for UserReadRepoPg {
             .await
             .map_err(|e| Error::InternalServerError(e.to_string()))
     }
+
+    async fn find_by_id_with_deleted(&self, user_id: i64) -> Result<Option<User>, Error> {
+        let query = r#"
+            SELECT 
+                id, username, email, password, name, photo_url, photo_thumbnail_url,
+                email_verified_at, suspended_at, suspended_until, suspension_reason, deleted_at,
+                created_at, updated_at
+            FROM 
+                "user"
+            WHERE 
+                id = $1
+        "#;
+
+        sqlx::query_as::<_, User>(query)
+            .bind(user_id)
+            .fetch_optional(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn search_all(&self, req: &AdminUserSearchRequest) -> Result<PageResponse<User>, Error> {
+        let query = r#"
+            SELECT
+                id, username, email, password, name, photo_url, photo_thumbnail_url,
+                email_verified_at, suspended_at, suspended_until, suspension_reason, deleted_at,
+                created_at, updated_at
+            FROM
+                "user"
+            WHERE
+                (
+                    $1::TEXT IS NULL
+                    OR username ILIKE $1 || '%'
+                    OR email ILIKE $1 || '%'
+                    OR name ILIKE '%' || $1 || '%'
+                )
+                AND ($2::BOOLEAN IS NULL OR (deleted_at IS NOT NULL) = $2)
+                AND id < $3
+            ORDER
+                BY id DESC
+            LIMIT
+                $4
+        "#;
+
+        let page = PageRequest {
+            cursor: req.cursor,
+            size: req.size,
+        };
+        let q = req.q.as_deref().map(escape_like);
+
+        let users: Vec<User> = sqlx::query_as::<_, User>(query)
+            .bind(q)
+            .bind(req.deleted)
+            .bind(page.cursor())
+            .bind(page.size())
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+        let next_cursor = users.last().map(|u| u.id);
+
+        Ok(PageResponse {
+            data: users,
+            size: page.size(),
+            next_cursor,
+        })
+    }
+
+    async fn search(
+        &self,
+        user_id: i64,
+        req: &UserSearchRequest,
+    ) -> Result<PageResponse<User, UserSearchCursor>, Error> {
+        // Rank ascends while score and id descend, so the keyset compares their negations
+        let query = r#"
+            SELECT
+                *
+            FROM (
+                SELECT
+                    u.id, u.username, u.email, u.password, u.name, u.photo_url,
+                    u.photo_thumbnail_url, u.email_verified_at, u.suspended_at, u.suspended_until,
+                    u.suspension_reason, u.deleted_at, u.created_at, u.updated_at,
+                    CASE
+                        WHEN u.username ILIKE $1 THEN 0
+                        WHEN u.username ILIKE $1 || '%' THEN 1
+                        WHEN u.name ILIKE $1 || '%' OR u.name ILIKE '% ' || $1 || '%' THEN 2
+                        ELSE 3
+                    END AS rank,
+                    GREATEST(similarity(u.username, $2), similarity(u.name, $2)) AS score
+                FROM
+                    "user" u
+                WHERE
+                    (
+                        u.username ILIKE $1 || '%'
+                        OR u.name ILIKE $1 || '%'
+                        OR u.name ILIKE '% ' || $1 || '%'
+                        OR u.username % $2
+                        OR u.name % $2
+                    )
+                    AND u.deleted_at IS NULL
+                    AND (u.suspended_at IS NULL OR u.suspended_until <= NOW())
+                    AND u.id <> $3
+                    AND NOT EXISTS (
+                        SELECT
+                            1
+                        FROM
+                            "user_block" b
+                        WHERE
+                            (b.user_id = $3 AND b.blocked_user_id = u.id)
+                            OR (b.user_id = u.id AND b.blocked_user_id = $3)
+                    )
+            ) ranked
+            WHERE
+                $4::INT IS NULL OR (rank, -score, -id) > ($4, -$5::REAL, -$6::BIGINT)
+            ORDER BY
+                rank, score DESC, id DESC
+            LIMIT
+                $7
+        "#;
+
+        let q = req.q.trim();
+        let size = req.size();
+
+        let results: Vec<UserSearchResult> = sqlx::query_as::<_, UserSearchResult>(query)
+            .bind(escape_like(q))
+            .bind(q)
+            .bind(user_id)
+            .bind(req.cursor.map(|cursor| cursor.rank))
+            .bind(req.cursor.map(|cursor| cursor.score))
+            .bind(req.cursor.map(|cursor| cursor.user_id))
+            .bind(size)
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+        let next_cursor = results.last().map(|result| UserSearchCursor {
+            rank: result.rank,
+            score: result.score,
+            user_id: result.user.id,
+        });
+
+        Ok(PageResponse {
+            data: results.into_iter().map(|result| result.user).collect(),
+            size,
+            next_cursor,
+        })
+    }
+
+    async fn find_blocked(&self, user_id: i64) -> Result<Vec<User>, Error> {
+        let query = r#"
+            SELECT
+                u.id, u.username, u.email, u.password, u.name, u.photo_url, u.photo_thumbnail_url,
+                u.email_verified_at, u.suspended_at, u.suspended_until, u.suspension_reason,
+                u.deleted_at, u.created_at, u.updated_at
+            FROM
+                "user_block" b
+            JOIN
+                "user" u ON u.id = b.blocked_user_id
+            WHERE
+                b.user_id = $1 AND u.deleted_at IS NULL
+            ORDER BY
+                b.created_at DESC
+        "#;
+
+        sqlx::query_as::<_, User>(query)
+            .bind(user_id)
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn find_contact_ids(&self, user_id: i64, user_ids: &[i64]) -> Result<Vec<i64>, Error> {
+        let query = r#"
+            SELECT DISTINCT
+                other.user_id
+            FROM
+                "conversation_participant" own
+            JOIN
+                "conversation_participant" other ON other.conversation_id = own.conversation_id
+            WHERE
+                own.user_id = $1
+                AND own.deleted_at IS NULL
+                AND other.deleted_at IS NULL
+                AND other.user_id = ANY($2)
+        "#;
+
+        sqlx::query_scalar::<_, i64>(query)
+            .bind(user_id)
+            .bind(user_ids)
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn find_last_seen(&self, user_ids: &[i64]) -> Result<Vec<(i64, DateTime<Utc>)>, Error> {
+        let query = r#"
+            SELECT
+                user_id, MAX(last_seen_at)
+            FROM
+                "session"
+            WHERE
+                user_id = ANY($1)
+            GROUP
+                BY user_id
+        "#;
+
+        sqlx::query_as::<_, (i64, DateTime<Utc>)>(query)
+            .bind(user_ids)
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+pub trait UserPrivacyReadRepo: Send + Sync {
+    /// Settings of those of `user_ids` who changed them, the others have the defaults
+    fn find_by_user_ids(
+        &self,
+        user_ids: &[i64],
+    ) -> impl Future<Output = Result<Vec<UserPrivacy>, Error>> + Send;
+}
+
+pub struct UserPrivacyReadRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl UserPrivacyReadRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl UserPrivacyReadRepo for UserPrivacyReadRepoPg {
+    async fn find_by_user_ids(&self, user_ids: &[i64]) -> Result<Vec<UserPrivacy>, Error> {
+        let query = r#"
+            SELECT
+                user_id, email_visibility, last_seen_visibility, photo_visibility
+            FROM
+                "user_privacy"
+            WHERE
+                user_id = ANY($1)
+        "#;
+
+        sqlx::query_as::<_, UserPrivacy>(query)
+            .bind(user_ids)
+            .fetch_all(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+/// The term is matched literally, not as a LIKE pattern
+fn escape_like(term: &str) -> String {
+    term.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
 }
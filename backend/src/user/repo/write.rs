@@ -1,5 +1,8 @@
+use crate::common::database::connection;
 use crate::common::model::Error;
-use crate::user::model::{CreateUserRequest, UpdateUserRequest, User};
+use crate::user::model::{
+    CreateModerationLogRequest, CreateUserRequest, UpdateUserRequest, User, UserPrivacy,
+};
 use chrono::{DateTime, Local, Utc};
 use sqlx::{Pool, Postgres};
 use std::future::Future;
@@ -18,6 +21,62 @@ pub trait UserWriteRepo: Send + Sync {
     ) -> impl Future<Output = Result<User, Error>> + Send;
 
     fn delete(&self, user_id: i64) -> impl Future<Output = Result<User, Error>> + Send;
+
+    /// Sets the URLs of a photo the server stored, or removes the photo when none
+    fn update_photo(
+        &self,
+        user_id: i64,
+        photo_url: Option<&str>,
+        photo_thumbnail_url: Option<&str>,
+    ) -> impl Future<Output = Result<User, Error>> + Send;
+
+    /// Sets a new email, which is unverified until its verification link is opened
+    fn update_email(
+        &self,
+        user_id: i64,
+        email: &str,
+    ) -> impl Future<Output = Result<User, Error>> + Send;
+
+    fn update_password(
+        &self,
+        user_id: i64,
+        password: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Suspends the user until the given time, or bans them when there is none
+    fn suspend(
+        &self,
+        user_id: i64,
+        until: Option<DateTime<Utc>>,
+        reason: &str,
+    ) -> impl Future<Output = Result<User, Error>> + Send;
+
+    fn unsuspend(&self, user_id: i64) -> impl Future<Output = Result<User, Error>> + Send;
+
+    /// Undoes a soft delete
+    fn restore(&self, user_id: i64) -> impl Future<Output = Result<User, Error>> + Send;
+
+    /// Returns whether `blocked_user_id` was not blocked by the user yet
+    fn block(
+        &self,
+        user_id: i64,
+        blocked_user_id: i64,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Returns whether `blocked_user_id` was blocked by the user
+    fn unblock(
+        &self,
+        user_id: i64,
+        blocked_user_id: i64,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Marks `email` as verified, but only while it is still the user's unverified email.
+    /// Returns whether it was marked.
+    fn verify_email(
+        &self,
+        user_id: i64,
+        email: &str,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
 }
 
 pub struct UserWriteRepoPg {
@@ -62,23 +121,19 @@ impl UserWriteRepo for UserWriteRepoPg {
                 "user"
             SET 
                 username = $1,
-                email = $2,
-                password = $3,
-                name = $4,
-                photo_url = $5,
-                updated_at = $6
+                name = $2,
+                updated_at = $3
             WHERE
-                id = $7
+                id = $4
             RETURNING 
-                id, username, email, password, name, photo_url, deleted_at, created_at,  updated_at
+                id, username, email, password, name, photo_url, photo_thumbnail_url,
+                email_verified_at, suspended_at, suspended_until, suspension_reason, deleted_at,
+                created_at, updated_at
         "#;
 
         sqlx::query_as::<_, User>(query)
             .bind(&request.username)
-            .bind(&request.email)
-            .bind(&request.password)
             .bind(&request.name)
-            .bind(&request.photo_url)
             .bind(Utc::now()) // updated_at
             .bind(user_id)
             .fetch_one(&*self.pool)
@@ -96,7 +151,9 @@ impl UserWriteRepo for UserWriteRepoPg {
             WHERE 
                 id = $3
             RETURNING 
-                id, username, email, password, name, photo_url, deleted_at, created_at,  updated_at
+                id, username, email, password, name, photo_url, photo_thumbnail_url,
+                email_verified_at, suspended_at, suspended_until, suspension_reason, deleted_at,
+                created_at, updated_at
         "#;
 
         sqlx::query_as::<_, User>(query)
@@ -107,4 +164,319 @@ impl UserWriteRepo for UserWriteRepoPg {
             .await
             .map_err(|e| Error::InternalServerError(e.to_string()))
     }
+
+    async fn update_photo(
+        &self,
+        user_id: i64,
+        photo_url: Option<&str>,
+        photo_thumbnail_url: Option<&str>,
+    ) -> Result<User, Error> {
+        let query = r#"
+            UPDATE
+                "user"
+            SET
+                photo_url = $1,
+                photo_thumbnail_url = $2,
+                updated_at = $3
+            WHERE
+                id = $4
+            RETURNING
+                id, username, email, password, name, photo_url, photo_thumbnail_url,
+                email_verified_at, suspended_at, suspended_until, suspension_reason, deleted_at,
+                created_at, updated_at
+        "#;
+
+        sqlx::query_as::<_, User>(query)
+            .bind(photo_url)
+            .bind(photo_thumbnail_url)
+            .bind(Utc::now()) // updated_at
+            .bind(user_id)
+            .fetch_one(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn update_email(&self, user_id: i64, email: &str) -> Result<User, Error> {
+        let query = r#"
+            UPDATE
+                "user"
+            SET 
+                email = $1,
+                email_verified_at = CASE WHEN email = $1 THEN email_verified_at END,
+                updated_at = $2
+            WHERE 
+                id = $3
+            RETURNING 
+                id, username, email, password, name, photo_url, photo_thumbnail_url,
+                email_verified_at, suspended_at, suspended_until, suspension_reason, deleted_at,
+                created_at, updated_at
+        "#;
+
+        sqlx::query_as::<_, User>(query)
+            .bind(email)
+            .bind(Utc::now())
+            .bind(user_id)
+            .fetch_one(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn suspend(
+        &self,
+        user_id: i64,
+        until: Option<DateTime<Utc>>,
+        reason: &str,
+    ) -> Result<User, Error> {
+        let query = r#"
+            UPDATE
+                "user"
+            SET 
+                suspended_at = $1,
+                suspended_until = $2,
+                suspension_reason = $3,
+                updated_at = $4
+            WHERE 
+                id = $5
+            RETURNING 
+                id, username, email, password, name, photo_url, photo_thumbnail_url,
+                email_verified_at, suspended_at, suspended_until, suspension_reason, deleted_at,
+                created_at, updated_at
+        "#;
+
+        let query = sqlx::query_as::<_, User>(query)
+            .bind(Utc::now())
+            .bind(until)
+            .bind(reason)
+            .bind(Utc::now())
+            .bind(user_id);
+
+        // Runs in the transaction of a moderation action's unit of work, if there is one
+        query
+            .fetch_one(&mut *connection(&self.pool).await?)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn unsuspend(&self, user_id: i64) -> Result<User, Error> {
+        let query = r#"
+            UPDATE
+                "user"
+            SET 
+                suspended_at = NULL,
+                suspended_until = NULL,
+                suspension_reason = NULL,
+                updated_at = $1
+            WHERE 
+                id = $2
+            RETURNING 
+                id, username, email, password, name, photo_url, photo_thumbnail_url,
+                email_verified_at, suspended_at, suspended_until, suspension_reason, deleted_at,
+                created_at, updated_at
+        "#;
+
+        let query = sqlx::query_as::<_, User>(query)
+            .bind(Utc::now())
+            .bind(user_id);
+
+        query
+            .fetch_one(&mut *connection(&self.pool).await?)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn restore(&self, user_id: i64) -> Result<User, Error> {
+        let query = r#"
+            UPDATE
+                "user"
+            SET 
+                deleted_at = NULL,
+                updated_at = $1
+            WHERE 
+                id = $2
+            RETURNING 
+                id, username, email, password, name, photo_url, photo_thumbnail_url,
+                email_verified_at, suspended_at, suspended_until, suspension_reason, deleted_at,
+                created_at, updated_at
+        "#;
+
+        let query = sqlx::query_as::<_, User>(query)
+            .bind(Utc::now())
+            .bind(user_id);
+
+        query
+            .fetch_one(&mut *connection(&self.pool).await?)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn update_password(&self, user_id: i64, password: &str) -> Result<(), Error> {
+        let query = r#"
+            UPDATE
+                "user"
+            SET 
+                password = $1,
+                updated_at = $2
+            WHERE 
+                id = $3
+        "#;
+
+        let query = sqlx::query(query)
+            .bind(password)
+            .bind(Utc::now())
+            .bind(user_id);
+
+        query
+            .execute(&mut *connection(&self.pool).await?)
+            .await
+            .map(|_| ())
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn block(&self, user_id: i64, blocked_user_id: i64) -> Result<bool, Error> {
+        let query = r#"
+            INSERT INTO "user_block" (
+                user_id, blocked_user_id, created_at
+            ) VALUES (
+                $1, $2, $3
+            )
+            ON CONFLICT (user_id, blocked_user_id) DO NOTHING
+        "#;
+
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(blocked_user_id)
+            .bind(Utc::now()) // created_at
+            .execute(&*self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn unblock(&self, user_id: i64, blocked_user_id: i64) -> Result<bool, Error> {
+        let query = r#"
+            DELETE FROM
+                "user_block"
+            WHERE
+                user_id = $1 AND blocked_user_id = $2
+        "#;
+
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(blocked_user_id)
+            .execute(&*self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+
+    async fn verify_email(&self, user_id: i64, email: &str) -> Result<bool, Error> {
+        let query = r#"
+            UPDATE
+                "user"
+            SET 
+                email_verified_at = $1,
+                updated_at = $2
+            WHERE 
+                id = $3 AND email = $4 AND email_verified_at IS NULL AND deleted_at IS NULL
+        "#;
+
+        sqlx::query(query)
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .bind(user_id)
+            .bind(email)
+            .execute(&*self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+pub trait ModerationLogWriteRepo: Send + Sync {
+    fn create(
+        &self,
+        req: CreateModerationLogRequest,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+pub struct ModerationLogWriteRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl ModerationLogWriteRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl ModerationLogWriteRepo for ModerationLogWriteRepoPg {
+    async fn create(&self, req: CreateModerationLogRequest) -> Result<(), Error> {
+        let query = r#"
+            INSERT INTO "moderation_log" (
+                id, actor_id, user_id, action, reason, expires_at, created_at
+            ) VALUES (
+                default, $1, $2, $3, $4, $5, $6
+            )
+        "#;
+
+        let query = sqlx::query(query)
+            .bind(req.actor_id)
+            .bind(req.user_id)
+            .bind(req.action.as_str())
+            .bind(req.reason)
+            .bind(req.expires_at)
+            .bind(Utc::now()); // created_at
+
+        query
+            .execute(&mut *connection(&self.pool).await?)
+            .await
+            .map(|_| ())
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+pub trait UserPrivacyWriteRepo: Send + Sync {
+    fn save(
+        &self,
+        privacy: &UserPrivacy,
+    ) -> impl Future<Output = Result<UserPrivacy, Error>> + Send;
+}
+
+pub struct UserPrivacyWriteRepoPg {
+    pool: Arc<Pool<Postgres>>,
+}
+
+impl UserPrivacyWriteRepoPg {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl UserPrivacyWriteRepo for UserPrivacyWriteRepoPg {
+    async fn save(&self, privacy: &UserPrivacy) -> Result<UserPrivacy, Error> {
+        let query = r#"
+            INSERT INTO "user_privacy" (
+                user_id, email_visibility, last_seen_visibility, photo_visibility, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5
+            )
+            ON CONFLICT (user_id) DO UPDATE SET
+                email_visibility = EXCLUDED.email_visibility,
+                last_seen_visibility = EXCLUDED.last_seen_visibility,
+                photo_visibility = EXCLUDED.photo_visibility,
+                updated_at = EXCLUDED.updated_at
+            RETURNING
+                user_id, email_visibility, last_seen_visibility, photo_visibility
+        "#;
+
+        sqlx::query_as::<_, UserPrivacy>(query)
+            .bind(privacy.user_id)
+            .bind(privacy.email_visibility.as_str())
+            .bind(privacy.last_seen_visibility.as_str())
+            .bind(privacy.photo_visibility.as_str())
+            .bind(Utc::now()) // updated_at
+            .fetch_one(&*self.pool)
+            .await
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
 }
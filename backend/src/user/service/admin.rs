@@ -0,0 +1,349 @@
+use crate::auth::model::Permission;
+use crate::auth::repo::RoleReadRepo;
+use crate::auth::service::AuthWriteService;
+use crate::common::database::UnitOfWork;
+use crate::common::model::{Error, PageResponse};
+use crate::user::model::{
+    AdminUserResponse, AdminUserSearchRequest, CreateModerationLogRequest, ModerationAction,
+    ModerationRequest, SuspendUserRequest, User,
+};
+use crate::user::repo::{ModerationLogWriteRepo, UserReadRepo, UserWriteRepo};
+use chrono::{DateTime, Utc};
+use std::future::Future;
+use std::sync::Arc;
+use validator::Validate;
+
+/// Operator actions on users. `actor_id` is the operator, every action is written to the
+/// moderation log in the same transaction.
+pub trait UserAdminService: Send + Sync {
+    fn search(
+        &self,
+        req: AdminUserSearchRequest,
+    ) -> impl Future<Output = Result<PageResponse<AdminUserResponse>, Error>> + Send;
+
+    fn find_by_id(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = Result<AdminUserResponse, Error>> + Send;
+
+    /// Suspends the user, or bans them without an end date, and signs them out everywhere
+    fn suspend(
+        &self,
+        actor_id: i64,
+        user_id: i64,
+        req: SuspendUserRequest,
+    ) -> impl Future<Output = Result<AdminUserResponse, Error>> + Send;
+
+    fn unsuspend(
+        &self,
+        actor_id: i64,
+        user_id: i64,
+        req: ModerationRequest,
+    ) -> impl Future<Output = Result<AdminUserResponse, Error>> + Send;
+
+    fn restore(
+        &self,
+        actor_id: i64,
+        user_id: i64,
+        req: ModerationRequest,
+    ) -> impl Future<Output = Result<AdminUserResponse, Error>> + Send;
+
+    fn force_password_reset(
+        &self,
+        actor_id: i64,
+        user_id: i64,
+        req: ModerationRequest,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn revoke_sessions(
+        &self,
+        actor_id: i64,
+        user_id: i64,
+        req: ModerationRequest,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+pub struct UserAdminServiceImpl<R, W, L, G, A, U>
+where
+    R: UserReadRepo + Send + Sync + 'static,
+    W: UserWriteRepo + Send + Sync + 'static,
+    L: ModerationLogWriteRepo + Send + Sync + 'static,
+    G: RoleReadRepo + Send + Sync + 'static,
+    A: AuthWriteService + Send + Sync + 'static,
+    U: UnitOfWork + Send + Sync + 'static,
+{
+    user_read_repo: Arc<R>,
+    user_write_repo: Arc<W>,
+    moderation_log_write_repo: Arc<L>,
+    role_read_repo: Arc<G>,
+    auth_write_service: Arc<A>,
+    unit_of_work: Arc<U>,
+}
+
+impl<R, W, L, G, A, U> UserAdminServiceImpl<R, W, L, G, A, U>
+where
+    R: UserReadRepo + Send + Sync + 'static,
+    W: UserWriteRepo + Send + Sync + 'static,
+    L: ModerationLogWriteRepo + Send + Sync + 'static,
+    G: RoleReadRepo + Send + Sync + 'static,
+    A: AuthWriteService + Send + Sync + 'static,
+    U: UnitOfWork + Send + Sync + 'static,
+{
+    pub fn new(
+        user_read_repo: Arc<R>,
+        user_write_repo: Arc<W>,
+        moderation_log_write_repo: Arc<L>,
+        role_read_repo: Arc<G>,
+        auth_write_service: Arc<A>,
+        unit_of_work: Arc<U>,
+    ) -> Self {
+        Self {
+            user_read_repo,
+            user_write_repo,
+            moderation_log_write_repo,
+            role_read_repo,
+            auth_write_service,
+            unit_of_work,
+        }
+    }
+
+    async fn find_user(&self, user_id: i64) -> Result<User, Error> {
+        self.user_read_repo
+            .find_by_id_with_deleted(user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("User with id {} not found", user_id)))
+    }
+
+    /// Operators cannot act on themselves, and only those who manage roles can act on other
+    /// operators
+    async fn find_moderatable_user(&self, actor_id: i64, user_id: i64) -> Result<User, Error> {
+        if actor_id == user_id {
+            return Err(Error::Forbidden(
+                "You cannot moderate your own account".to_string(),
+            ));
+        }
+        let user = self.find_user(user_id).await?;
+
+        let user_permissions = self
+            .role_read_repo
+            .find_permissions_by_user_id(user_id)
+            .await?;
+        if user_permissions.contains(&Permission::UserModerate) {
+            let actor_permissions = self
+                .role_read_repo
+                .find_permissions_by_user_id(actor_id)
+                .await?;
+            if !actor_permissions.contains(&Permission::RoleManage) {
+                return Err(Error::Forbidden(
+                    "Only super admins can moderate other operators".to_string(),
+                ));
+            }
+        }
+
+        Ok(user)
+    }
+
+    async fn log(
+        &self,
+        actor_id: i64,
+        user_id: i64,
+        action: ModerationAction,
+        reason: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), Error> {
+        self.moderation_log_write_repo
+            .create(CreateModerationLogRequest {
+                actor_id,
+                user_id,
+                action,
+                reason,
+                expires_at,
+            })
+            .await
+    }
+}
+
+impl<R, W, L, G, A, U> UserAdminService for UserAdminServiceImpl<R, W, L, G, A, U>
+where
+    R: UserReadRepo + Send + Sync + 'static,
+    W: UserWriteRepo + Send + Sync + 'static,
+    L: ModerationLogWriteRepo + Send + Sync + 'static,
+    G: RoleReadRepo + Send + Sync + 'static,
+    A: AuthWriteService + Send + Sync + 'static,
+    U: UnitOfWork + Send + Sync + 'static,
+{
+    async fn search(
+        &self,
+        req: AdminUserSearchRequest,
+    ) -> Result<PageResponse<AdminUserResponse>, Error> {
+        let page = self.user_read_repo.search_all(&req).await?;
+
+        Ok(PageResponse {
+            data: page.data.into_iter().map(AdminUserResponse::from).collect(),
+            next_cursor: page.next_cursor,
+            size: page.size,
+        })
+    }
+
+    async fn find_by_id(&self, user_id: i64) -> Result<AdminUserResponse, Error> {
+        self.find_user(user_id).await.map(AdminUserResponse::from)
+    }
+
+    async fn suspend(
+        &self,
+        actor_id: i64,
+        user_id: i64,
+        req: SuspendUserRequest,
+    ) -> Result<AdminUserResponse, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+        if req.until.is_some_and(|until| until <= Utc::now()) {
+            return Err(Error::BadRequest(
+                "Suspension must end in the future".to_string(),
+            ));
+        }
+        let user = self.find_moderatable_user(actor_id, user_id).await?;
+
+        let action = match req.until {
+            Some(_) => ModerationAction::Suspend,
+            None => ModerationAction::Ban,
+        };
+        let user = self
+            .unit_of_work
+            .run(async {
+                let user = self
+                    .user_write_repo
+                    .suspend(user.id, req.until, &req.reason)
+                    .await?;
+                self.auth_write_service.revoke_all_sessions(user.id).await?;
+                self.log(
+                    actor_id,
+                    user.id,
+                    action,
+                    Some(req.reason.clone()),
+                    req.until,
+                )
+                .await?;
+
+                Ok(user)
+            })
+            .await?;
+
+        Ok(AdminUserResponse::from(user))
+    }
+
+    async fn unsuspend(
+        &self,
+        actor_id: i64,
+        user_id: i64,
+        req: ModerationRequest,
+    ) -> Result<AdminUserResponse, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+        let user = self.find_moderatable_user(actor_id, user_id).await?;
+        if user.suspended_at.is_none() {
+            return Err(Error::Conflict("User is not suspended".to_string()));
+        }
+
+        let user = self
+            .unit_of_work
+            .run(async {
+                let user = self.user_write_repo.unsuspend(user.id).await?;
+                self.log(
+                    actor_id,
+                    user.id,
+                    ModerationAction::Unsuspend,
+                    req.reason,
+                    None,
+                )
+                .await?;
+
+                Ok(user)
+            })
+            .await?;
+
+        Ok(AdminUserResponse::from(user))
+    }
+
+    async fn restore(
+        &self,
+        actor_id: i64,
+        user_id: i64,
+        req: ModerationRequest,
+    ) -> Result<AdminUserResponse, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+        let user = self.find_moderatable_user(actor_id, user_id).await?;
+        if user.deleted_at.is_none() {
+            return Err(Error::Conflict("User is not deleted".to_string()));
+        }
+
+        let user = self
+            .unit_of_work
+            .run(async {
+                let user = self.user_write_repo.restore(user.id).await?;
+                self.log(
+                    actor_id,
+                    user.id,
+                    ModerationAction::Restore,
+                    req.reason,
+                    None,
+                )
+                .await?;
+
+                Ok(user)
+            })
+            .await?;
+
+        Ok(AdminUserResponse::from(user))
+    }
+
+    async fn force_password_reset(
+        &self,
+        actor_id: i64,
+        user_id: i64,
+        req: ModerationRequest,
+    ) -> Result<(), Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+        let user = self.find_moderatable_user(actor_id, user_id).await?;
+
+        // Logged in the transaction of the reset itself, which runs its own unit of work so the
+        // password is hashed before it and the mail sent after it
+        let audit = self.log(
+            actor_id,
+            user.id,
+            ModerationAction::ForcePasswordReset,
+            req.reason,
+            None,
+        );
+        self.auth_write_service
+            .force_password_reset(user.id, audit)
+            .await
+    }
+
+    async fn revoke_sessions(
+        &self,
+        actor_id: i64,
+        user_id: i64,
+        req: ModerationRequest,
+    ) -> Result<(), Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+        let user = self.find_moderatable_user(actor_id, user_id).await?;
+
+        self.unit_of_work
+            .run(async {
+                self.auth_write_service.revoke_all_sessions(user.id).await?;
+                self.log(
+                    actor_id,
+                    user.id,
+                    ModerationAction::RevokeSessions,
+                    req.reason,
+                    None,
+                )
+                .await
+            })
+            .await
+    }
+}
@@ -1,5 +1,7 @@
+mod admin;
 mod read;
 mod write;
 
+pub use admin::*;
 pub use read::*;
 pub use write::*;
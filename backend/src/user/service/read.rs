@@ -1,37 +1,123 @@
 use crate::common::model::{Error, PageRequest, PageResponse};
-use crate::user::model::UserResponse;
-use crate::user::repo::UserReadRepo;
+use crate::user::model::{
+    PublicUserResponse, User, UserPrivacy, UserPrivacyResponse, UserResponse, UserSearchCursor,
+    UserSearchRequest,
+};
+use crate::user::repo::{UserPrivacyReadRepo, UserReadRepo};
+use std::collections::HashMap;
 use std::future::Future;
 use std::sync::Arc;
+use validator::Validate;
 
 pub trait UserReadService: Send + Sync {
+    /// The user's own profile
     fn find_by_id(&self, user_id: i64) -> impl Future<Output = Result<UserResponse, Error>> + Send;
 
+    /// Profile of `user_id` as `viewer_id` is allowed to see it
+    fn find_profile(
+        &self,
+        viewer_id: i64,
+        user_id: i64,
+    ) -> impl Future<Output = Result<PublicUserResponse, Error>> + Send;
+
     fn find_all(
         &self,
+        viewer_id: i64,
         req: PageRequest,
-    ) -> impl Future<Output = Result<PageResponse<UserResponse>, Error>> + Send;
+    ) -> impl Future<Output = Result<PageResponse<PublicUserResponse>, Error>> + Send;
+
+    /// Finds people `viewer_id` can start a conversation with
+    fn search(
+        &self,
+        viewer_id: i64,
+        req: UserSearchRequest,
+    ) -> impl Future<Output = Result<PageResponse<PublicUserResponse, UserSearchCursor>, Error>> + Send;
+
+    /// Users `user_id` blocked, latest first
+    fn find_blocked(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = Result<Vec<PublicUserResponse>, Error>> + Send;
+
+    fn find_privacy(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = Result<UserPrivacyResponse, Error>> + Send;
 }
 
-pub struct UserReadServiceImpl<R>
+pub struct UserReadServiceImpl<R, P>
 where
     R: UserReadRepo + Send + Sync + 'static,
+    P: UserPrivacyReadRepo + Send + Sync + 'static,
 {
     user_read_repo: Arc<R>,
+    user_privacy_read_repo: Arc<P>,
 }
 
-impl<R> UserReadServiceImpl<R>
+impl<R, P> UserReadServiceImpl<R, P>
 where
     R: UserReadRepo + Send + Sync + 'static,
+    P: UserPrivacyReadRepo + Send + Sync + 'static,
 {
-    pub fn new(user_read_repo: Arc<R>) -> UserReadServiceImpl<R> {
-        UserReadServiceImpl { user_read_repo }
+    pub fn new(
+        user_read_repo: Arc<R>,
+        user_privacy_read_repo: Arc<P>,
+    ) -> UserReadServiceImpl<R, P> {
+        UserReadServiceImpl {
+            user_read_repo,
+            user_privacy_read_repo,
+        }
+    }
+
+    /// Hides what each of the users does not share with the viewer
+    async fn to_public(
+        &self,
+        viewer_id: i64,
+        users: Vec<User>,
+    ) -> Result<Vec<PublicUserResponse>, Error> {
+        let user_ids = users.iter().map(|user| user.id).collect::<Vec<_>>();
+        if user_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let privacies = self
+            .user_privacy_read_repo
+            .find_by_user_ids(&user_ids)
+            .await?
+            .into_iter()
+            .map(|privacy| (privacy.user_id, privacy))
+            .collect::<HashMap<_, _>>();
+        let contact_ids = self
+            .user_read_repo
+            .find_contact_ids(viewer_id, &user_ids)
+            .await?;
+        let last_seen = self
+            .user_read_repo
+            .find_last_seen(&user_ids)
+            .await?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        Ok(users
+            .into_iter()
+            .map(|user| {
+                let privacy = privacies
+                    .get(&user.id)
+                    .cloned()
+                    .unwrap_or_else(|| UserPrivacy::default(user.id));
+                let is_contact = user.id == viewer_id || contact_ids.contains(&user.id);
+                let last_seen_at = last_seen.get(&user.id).copied();
+
+                PublicUserResponse::from(user, &privacy, is_contact, last_seen_at)
+            })
+            .collect())
     }
 }
 
-impl<R> UserReadService for UserReadServiceImpl<R>
+impl<R, P> UserReadService for UserReadServiceImpl<R, P>
 where
     R: UserReadRepo + Send + Sync + 'static,
+    P: UserPrivacyReadRepo + Send + Sync + 'static,
 {
     async fn find_by_id(&self, user_id: i64) -> Result<UserResponse, Error> {
         self.user_read_repo
@@ -41,13 +127,76 @@ where
             .ok_or_else(|| Error::NotFound(format!("User with id {} not found", user_id)))
     }
 
-    async fn find_all(&self, req: PageRequest) -> Result<PageResponse<UserResponse>, Error> {
+    async fn find_profile(
+        &self,
+        viewer_id: i64,
+        user_id: i64,
+    ) -> Result<PublicUserResponse, Error> {
+        let user = self
+            .user_read_repo
+            .find_by_id(user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("User with id {} not found", user_id)))?;
+
+        self.to_public(viewer_id, vec![user])
+            .await?
+            .pop()
+            .ok_or_else(|| Error::NotFound(format!("User with id {} not found", user_id)))
+    }
+
+    async fn find_all(
+        &self,
+        viewer_id: i64,
+        req: PageRequest,
+    ) -> Result<PageResponse<PublicUserResponse>, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+
         let users = self.user_read_repo.find_all(req).await?;
 
         Ok(PageResponse {
-            data: users.data.into_iter().map(UserResponse::from).collect(),
+            data: self.to_public(viewer_id, users.data).await?,
             next_cursor: users.next_cursor,
             size: users.size,
         })
     }
+
+    async fn search(
+        &self,
+        viewer_id: i64,
+        req: UserSearchRequest,
+    ) -> Result<PageResponse<PublicUserResponse, UserSearchCursor>, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+        if req.q.trim().is_empty() {
+            return Err(Error::BadRequest(
+                "Search term must not be blank".to_string(),
+            ));
+        }
+
+        let users = self.user_read_repo.search(viewer_id, &req).await?;
+
+        Ok(PageResponse {
+            data: self.to_public(viewer_id, users.data).await?,
+            next_cursor: users.next_cursor,
+            size: users.size,
+        })
+    }
+
+    async fn find_blocked(&self, user_id: i64) -> Result<Vec<PublicUserResponse>, Error> {
+        let users = self.user_read_repo.find_blocked(user_id).await?;
+
+        self.to_public(user_id, users).await
+    }
+
+    async fn find_privacy(&self, user_id: i64) -> Result<UserPrivacyResponse, Error> {
+        let privacy = self
+            .user_privacy_read_repo
+            .find_by_user_ids(&[user_id])
+            .await?
+            .pop()
+            .unwrap_or_else(|| UserPrivacy::default(user_id));
+
+        Ok(UserPrivacyResponse::from(privacy))
+    }
 }
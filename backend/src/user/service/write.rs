@@ -1,9 +1,15 @@
 use crate::common::model::Error;
-use crate::user::model::{UpdateUserRequest, UserResponse};
+use crate::common::photo::Photo;
+use crate::common::storage::BlobStore;
+use crate::user::model::{
+    UpdateUserPrivacyRequest, UpdateUserRequest, UserPrivacy, UserPrivacyResponse, UserResponse,
+};
 use crate::user::repo::UserReadRepo;
 use crate::user::repo::UserWriteRepo;
+use crate::user::repo::{UserPrivacyReadRepo, UserPrivacyWriteRepo};
 use std::future::Future;
 use std::sync::Arc;
+use validator::Validate;
 
 pub trait UserWriteService: Send + Sync {
     fn update(
@@ -13,46 +19,92 @@ pub trait UserWriteService: Send + Sync {
     ) -> impl Future<Output = Result<UserResponse, Error>> + Send;
 
     fn delete(&self, user_id: i64) -> impl Future<Output = Result<UserResponse, Error>> + Send;
+
+    fn update_privacy(
+        &self,
+        user_id: i64,
+        request: UpdateUserPrivacyRequest,
+    ) -> impl Future<Output = Result<UserPrivacyResponse, Error>> + Send;
+
+    /// Replaces the user's photo with an uploaded image
+    fn update_photo(
+        &self,
+        user_id: i64,
+        photo: Vec<u8>,
+    ) -> impl Future<Output = Result<UserResponse, Error>> + Send;
+
+    /// Hides the two users from each other in the user search
+    fn block(
+        &self,
+        user_id: i64,
+        blocked_user_id: i64,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn unblock(
+        &self,
+        user_id: i64,
+        blocked_user_id: i64,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
 }
 
-pub struct UserWriteServiceImpl<W, R>
+pub struct UserWriteServiceImpl<W, R, PR, PW, B>
 where
     W: UserWriteRepo + Send + Sync + 'static,
     R: UserReadRepo + Send + Sync + 'static,
+    PR: UserPrivacyReadRepo + Send + Sync + 'static,
+    PW: UserPrivacyWriteRepo + Send + Sync + 'static,
+    B: BlobStore + Send + Sync + 'static,
 {
     user_write_repo: Arc<W>,
     user_read_repo: Arc<R>,
+    user_privacy_read_repo: Arc<PR>,
+    user_privacy_write_repo: Arc<PW>,
+    blob_store: Arc<B>,
 }
 
-impl<W: UserWriteRepo + Send + Sync, R: UserReadRepo + Send + Sync> UserWriteServiceImpl<W, R> {
-    pub fn new(user_write_repo: Arc<W>, user_read_repo: Arc<R>) -> Self {
+impl<W, R, PR, PW, B> UserWriteServiceImpl<W, R, PR, PW, B>
+where
+    W: UserWriteRepo + Send + Sync,
+    R: UserReadRepo + Send + Sync,
+    PR: UserPrivacyReadRepo + Send + Sync,
+    PW: UserPrivacyWriteRepo + Send + Sync,
+    B: BlobStore + Send + Sync,
+{
+    pub fn new(
+        user_write_repo: Arc<W>,
+        user_read_repo: Arc<R>,
+        user_privacy_read_repo: Arc<PR>,
+        user_privacy_write_repo: Arc<PW>,
+        blob_store: Arc<B>,
+    ) -> Self {
         UserWriteServiceImpl {
             user_write_repo,
             user_read_repo,
+            user_privacy_read_repo,
+            user_privacy_write_repo,
+            blob_store,
         }
     }
 }
 
-impl<W, R> UserWriteService for UserWriteServiceImpl<W, R>
+impl<W, R, PR, PW, B> UserWriteService for UserWriteServiceImpl<W, R, PR, PW, B>
 where
     W: UserWriteRepo + Send + Sync,
     R: UserReadRepo + Send + Sync,
+    PR: UserPrivacyReadRepo + Send + Sync,
+    PW: UserPrivacyWriteRepo + Send + Sync,
+    B: BlobStore + Send + Sync,
 {
     async fn update(&self, user_id: i64, req: UpdateUserRequest) -> Result<UserResponse, Error> {
+        req.validate()
+            .map_err(|errors| Error::BadRequest(errors.to_string()))?;
+
         let user = self
             .user_read_repo
             .find_by_id(user_id)
             .await?
             .ok_or_else(|| Error::NotFound(format!("User with id {} not found", user_id)))?;
 
-        let password = req
-            .password
-            .map(|password| {
-                bcrypt::hash(password, bcrypt::DEFAULT_COST)
-                    .map_err(|e| Error::InternalServerError(e.to_string()))
-            })
-            .unwrap_or(Ok(user.password))?;
-
         if let Some(username) = &req.username {
             let is_present = self.user_read_repo.exists_by_username(username).await?;
 
@@ -61,19 +113,9 @@ where
             }
         }
 
-        if let Some(email) = &req.email {
-            let is_present = self.user_read_repo.exists_by_email(email).await?;
-            if email != &user.email && is_present {
-                return Err(Error::Conflict("Email already exists".to_string()));
-            }
-        }
-
         let updated_request = UpdateUserRequest {
             name: req.name.or(Some(user.name)),
-            password: Some(password),
-            photo_url: req.photo_url.or(user.photo_url),
             username: req.username.or(Some(user.username)),
-            email: req.email.or(Some(user.email)),
         };
 
         let updated_user = self
@@ -95,4 +137,98 @@ where
 
         Ok(UserResponse::from(user))
     }
+
+    async fn update_privacy(
+        &self,
+        user_id: i64,
+        req: UpdateUserPrivacyRequest,
+    ) -> Result<UserPrivacyResponse, Error> {
+        let privacy = self
+            .user_privacy_read_repo
+            .find_by_user_ids(&[user_id])
+            .await?
+            .pop()
+            .unwrap_or_else(|| UserPrivacy::default(user_id));
+
+        let privacy = self
+            .user_privacy_write_repo
+            .save(&UserPrivacy {
+                user_id,
+                email_visibility: req.email_visibility.unwrap_or(privacy.email_visibility),
+                last_seen_visibility: req
+                    .last_seen_visibility
+                    .unwrap_or(privacy.last_seen_visibility),
+                photo_visibility: req.photo_visibility.unwrap_or(privacy.photo_visibility),
+            })
+            .await?;
+
+        Ok(UserPrivacyResponse::from(privacy))
+    }
+
+    async fn update_photo(&self, user_id: i64, photo: Vec<u8>) -> Result<UserResponse, Error> {
+        let user = self
+            .user_read_repo
+            .find_by_id(user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("User with id {} not found", user_id)))?;
+
+        let (photo_url, photo_thumbnail_url) = Photo::process(photo)
+            .await?
+            .store(&*self.blob_store, &format!("users/{}", user.id))
+            .await?;
+        let updated_user = match self
+            .user_write_repo
+            .update_photo(user.id, Some(&photo_url), Some(&photo_thumbnail_url))
+            .await
+        {
+            Ok(updated_user) => updated_user,
+            Err(err) => {
+                Photo::remove(
+                    &*self.blob_store,
+                    &[Some(photo_url), Some(photo_thumbnail_url)],
+                )
+                .await;
+                return Err(err);
+            }
+        };
+        Photo::remove(
+            &*self.blob_store,
+            &[user.photo_url, user.photo_thumbnail_url],
+        )
+        .await;
+
+        Ok(UserResponse::from(updated_user))
+    }
+
+    async fn block(&self, user_id: i64, blocked_user_id: i64) -> Result<(), Error> {
+        if user_id == blocked_user_id {
+            return Err(Error::BadRequest("You cannot block yourself".to_string()));
+        }
+        self.user_read_repo
+            .find_by_id(blocked_user_id)
+            .await?
+            .ok_or_else(|| {
+                Error::NotFound(format!("User with id {} not found", blocked_user_id))
+            })?;
+
+        self.user_write_repo
+            .block(user_id, blocked_user_id)
+            .await
+            .map(|_| ())
+    }
+
+    async fn unblock(&self, user_id: i64, blocked_user_id: i64) -> Result<(), Error> {
+        if !self
+            .user_write_repo
+            .unblock(user_id, blocked_user_id)
+            .await?
+        {
+            return Err(Error::NotFound(format!(
+                "User with id {} is not blocked",
+                blocked_user_id
+            )));
+        }
+
+        Ok(())
+    }
 }